@@ -1,12 +1,12 @@
 // In this game, the player moves around in 2D with the arrow keys, but if they get too close to the
 // enemy, the enemy moves towards them, until the player moves back out of range
 
-use bevy::prelude::*;
+use bevy::{log::Level, prelude::*};
 use seldom_state::prelude::*;
 
 fn main() {
     App::new()
-        .add_plugins((DefaultPlugins, StateMachinePlugin))
+        .add_plugins((DefaultPlugins, StateMachinePlugin::default()))
         // This plugin is required for `seldom_state`
         .add_systems(Startup, init)
         .add_systems(Update, (follow, move_player))
@@ -75,7 +75,7 @@ fn init(mut commands: Commands, asset_server: Res<AssetServer>) {
             // negates the trigger. `.and(other)` and `.or(other)` also exist.
             .trans::<Follow, _>(near_player.not(), Idle)
             // Enable transition logging
-            .set_trans_logging(true),
+            .log_transitions_at(Level::INFO),
         // The initial state is `Idle`
         Idle,
     ));
@@ -1,12 +1,12 @@
 // In this game, you control the player by clicking where they should go. This is an example of how
 // to use `DoneTrigger` and the `Done` component. `chase.rs` is a better example to start with.
 
-use bevy::prelude::*;
+use bevy::{log::Level, prelude::*};
 use seldom_state::prelude::*;
 
 fn main() {
     App::new()
-        .add_plugins((DefaultPlugins, StateMachinePlugin))
+        .add_plugins((DefaultPlugins, StateMachinePlugin::default()))
         .init_resource::<CursorPosition>()
         .add_systems(Startup, init)
         .add_systems(Update, (update_cursor_position, go_to_target))
@@ -33,7 +33,7 @@ fn init(mut commands: Commands, asset_server: Res<AssetServer>) {
             // `done` triggers when the `Done` component is added to the entity. When they're done
             // going to the selection, idle.
             .trans::<GoToSelection, _>(done(Some(Done::Success)), Idle)
-            .set_trans_logging(true),
+            .log_transitions_at(Level::INFO),
         Idle,
     ));
 }
@@ -0,0 +1,162 @@
+//! Derive macros for `seldom_state`. Use them through `seldom_state`'s prelude.
+
+#![warn(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, Visibility};
+
+/// Derives `MachineStates` for an enum, so each of its variants is a state. Generates a module,
+/// named after the enum in snake case, with a component for each variant, which has the variant's
+/// name and fields, and `From` impls from each component to the enum.
+#[proc_macro_derive(MachineStates)]
+pub fn derive_machine_states(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "`MachineStates` can only be derived for enums",
+        ));
+    };
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "`MachineStates` can't be derived for generic enums",
+        ));
+    }
+
+    let krate = quote!(::seldom_state);
+    let bevy = quote!(#krate::__private::bevy);
+    let name = &input.ident;
+    let module = Ident::new(&snake_case(&name.to_string()), name.span());
+    let vis = &input.vis;
+    // The components are declared in a child module, so private enums' components must be visible
+    // to the enum's module
+    let item_vis = match vis {
+        Visibility::Inherited => quote!(pub(super)),
+        vis => quote!(#vis),
+    };
+
+    let mut components = Vec::new();
+    let mut impls = Vec::new();
+    let mut inserts = Vec::new();
+    let mut variants = Vec::new();
+
+    for variant in &data.variants {
+        let ident = &variant.ident;
+        variants.push(ident);
+
+        let (component, destructure, construct) = match &variant.fields {
+            Fields::Unit => (
+                quote!(#item_vis struct #ident;),
+                quote!(#ident),
+                quote!(#ident),
+            ),
+            Fields::Unnamed(fields) => {
+                let tys = fields.unnamed.iter().map(|field| &field.ty);
+                let bindings = (0..fields.unnamed.len())
+                    .map(|i| format_ident!("field_{}", i))
+                    .collect::<Vec<_>>();
+
+                (
+                    quote!(#item_vis struct #ident(#(#item_vis #tys),*);),
+                    quote!(#ident(#(#bindings),*)),
+                    quote!(#ident(#(::std::clone::Clone::clone(#bindings)),*)),
+                )
+            }
+            Fields::Named(fields) => {
+                let tys = fields.named.iter().map(|field| &field.ty);
+                let idents = fields
+                    .named
+                    .iter()
+                    .map(|field| &field.ident)
+                    .collect::<Vec<_>>();
+
+                (
+                    quote!(#item_vis struct #ident { #(#item_vis #idents: #tys),* }),
+                    quote!(#ident { #(#idents),* }),
+                    quote!(#ident { #(#idents: ::std::clone::Clone::clone(#idents)),* }),
+                )
+            }
+        };
+
+        components.push(quote! {
+            #[derive(::std::clone::Clone)]
+            #component
+        });
+
+        impls.push(quote! {
+            impl #bevy::ecs::component::Component for #module::#ident {
+                type Storage = #bevy::ecs::component::TableStorage;
+            }
+
+            impl ::std::convert::From<#module::#ident> for #name {
+                fn from(state: #module::#ident) -> Self {
+                    let #module::#destructure = state;
+                    #name::#destructure
+                }
+            }
+        });
+
+        inserts.push(quote! {
+            #name::#destructure => {
+                entity.insert(#module::#construct);
+            }
+        });
+    }
+
+    Ok(quote! {
+        #[allow(non_snake_case)]
+        #vis mod #module {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #(#components)*
+        }
+
+        #(#impls)*
+
+        impl #krate::prelude::MachineStates for #name {
+            fn state_id(&self) -> ::std::any::TypeId {
+                match self {
+                    #(#name::#variants { .. } => ::std::any::TypeId::of::<#module::#variants>(),)*
+                }
+            }
+
+            fn register(
+                machine: #krate::prelude::StateMachine,
+            ) -> #krate::prelude::StateMachine {
+                machine #(.with_state::<#module::#variants>())*
+            }
+
+            #[allow(unused_variables)]
+            fn insert(&self, entity: &mut #bevy::ecs::system::EntityCommands) {
+                match self {
+                    #(#inserts)*
+                }
+            }
+        }
+    })
+}
+
+/// Converts an `UpperCamelCase` identifier to `snake_case`
+fn snake_case(ident: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in ident.char_indices() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+
+        snake.extend(c.to_lowercase());
+    }
+
+    snake
+}
@@ -0,0 +1,109 @@
+//! Plays animations when state machines transition. Enabled by the `animation` feature. See
+//! [`StateAnimations`].
+
+use std::{any::TypeId, time::Duration};
+
+use bevy::utils::HashMap;
+
+use crate::{prelude::*, set::StateSet};
+
+pub(crate) fn animation_plugin(app: &mut App) {
+    app.configure_sets(PostUpdate, StateSet::Animation.after(StateSet::Transition))
+        .add_systems(
+            PostUpdate,
+            play_state_animations.in_set(StateSet::Animation),
+        );
+}
+
+/// Add to an entity with a [`StateMachine`] to play an animation clip for each of its states. When
+/// the machine transitions to a state with a clip, the clip crossfades in on the
+/// [`AnimationPlayer`]. The player may be on another entity, such as a child in a glTF scene.
+#[derive(Clone, Component, Debug, Default)]
+pub struct StateAnimations {
+    /// Each state's clip, and whether it repeats
+    clips: HashMap<TypeId, (Handle<AnimationClip>, bool)>,
+    /// How long crossfades take, unless overridden
+    blend: Duration,
+    /// Crossfade durations for transitions from one state to another
+    blends: HashMap<(TypeId, TypeId), Duration>,
+    /// The entity with the [`AnimationPlayer`], if it isn't this entity
+    player: Option<Entity>,
+    /// The state whose clip was last played
+    last: Option<TypeId>,
+}
+
+impl StateAnimations {
+    /// Creates state animations that crossfade for the given duration
+    pub fn new(blend: Duration) -> Self {
+        Self { blend, ..default() }
+    }
+
+    /// Plays the given clip, repeating, while the entity is in the given state
+    pub fn with_clip<S: Clone + Component>(mut self, clip: Handle<AnimationClip>) -> Self {
+        self.clips.insert(TypeId::of::<S>(), (clip, true));
+        self
+    }
+
+    /// Plays the given clip once when the entity enters the given state
+    pub fn with_clip_once<S: Clone + Component>(mut self, clip: Handle<AnimationClip>) -> Self {
+        self.clips.insert(TypeId::of::<S>(), (clip, false));
+        self
+    }
+
+    /// Sets how long the crossfade takes when transitioning from `From` to `To`
+    pub fn with_blend<From: Clone + Component, To: Clone + Component>(
+        mut self,
+        blend: Duration,
+    ) -> Self {
+        self.blends
+            .insert((TypeId::of::<From>(), TypeId::of::<To>()), blend);
+        self
+    }
+
+    /// Plays the animations on the given entity's [`AnimationPlayer`] instead of this entity's
+    pub fn with_player(mut self, player: Entity) -> Self {
+        self.player = Some(player);
+        self
+    }
+}
+
+fn play_state_animations(
+    mut machines: Query<(Entity, &StateMachine, &mut StateAnimations)>,
+    mut players: Query<&mut AnimationPlayer>,
+) {
+    for (entity, machine, mut animations) in &mut machines {
+        let Some(current) = machine.current_state_id() else {
+            continue;
+        };
+
+        if animations.last == Some(current) {
+            continue;
+        }
+
+        let last = animations.last.replace(current);
+        let Some((clip, repeat)) = animations.clips.get(&current) else {
+            continue;
+        };
+
+        let Ok(mut player) = players.get_mut(animations.player.unwrap_or(entity)) else {
+            continue;
+        };
+
+        let player = match last {
+            Some(last) => {
+                let blend = animations
+                    .blends
+                    .get(&(last, current))
+                    .copied()
+                    .unwrap_or(animations.blend);
+                player.play_with_transition(clip.clone(), blend)
+            }
+            // The initial state's clip starts immediately
+            None => player.play(clip.clone()),
+        };
+
+        if *repeat {
+            player.repeat();
+        }
+    }
+}
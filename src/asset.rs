@@ -0,0 +1,519 @@
+//! State machines defined in RON assets, which hot reload onto live entities. Enabled by the
+//! `assets` feature. See [`StateMachineAsset`].
+
+use std::{
+    any::TypeId,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    ecs::schedule::BoxedScheduleLabel,
+    reflect::{serde::TypedReflectDeserializer, TypePath, TypeRegistry},
+    utils::{BoxedFuture, HashMap},
+};
+use serde::{
+    de::{DeserializeOwned, DeserializeSeed},
+    Deserialize,
+};
+
+use crate::{prelude::*, set::StateSet, state::Insert};
+
+pub(crate) fn asset_plugin(app: &mut App, schedule: BoxedScheduleLabel) {
+    app.init_asset::<StateMachineAsset>()
+        .init_asset_loader::<StateMachineAssetLoader>()
+        .init_resource::<MachineAssetRegistry>()
+        .add_systems(
+            schedule,
+            (build_asset_machines, reload_asset_machines).before(StateSet::Transition),
+        );
+}
+
+/// A state machine defined in a `.machine.ron` file, so it can be edited without recompiling.
+/// Add a [`MachineAsset`] with its handle to an entity, along with the entity's initial state, and
+/// the entity gets a [`StateMachine`] built from the asset once it loads. States and triggers are
+/// referred to by the names they're registered with in the [`MachineAssetRegistry`]. For example:
+///
+/// ```ron
+/// (
+///     states: ["Idle"],
+///     transitions: [
+///         (from: "Idle", trigger: "near_player", params: (range: 5.0), to: "Chase"),
+///         (from: "Chase", trigger: "hit", to: "Stunned", state: (secs: 2.0), priority: 1),
+///         (from: "AnyState", trigger: "dead", to: "Dead"),
+///     ],
+/// )
+/// ```
+///
+/// When the asset changes, machines built from it are rebuilt, and their runtime data is carried
+/// over with a [`MachineSnapshot`]. Hot reloading requires Bevy's `file_watcher` feature.
+#[derive(Asset, Clone, Debug, Default, Deserialize, TypePath)]
+pub struct StateMachineAsset {
+    /// States to register that aren't used in any transitions. See [`StateMachine::with_state`].
+    #[serde(default)]
+    pub states: Vec<String>,
+    /// The machine's transitions, in the order they're added
+    #[serde(default)]
+    pub transitions: Vec<TransitionAsset>,
+}
+
+/// A transition in a [`StateMachineAsset`]. See [`StateMachine::trans`].
+#[derive(Clone, Debug, Deserialize)]
+pub struct TransitionAsset {
+    /// The registered name of the state to transition from, or `"AnyState"`
+    pub from: String,
+    /// The registered name of the trigger
+    pub trigger: String,
+    /// Parameters passed to the trigger's constructor. Defaults to `()`.
+    #[serde(default)]
+    pub params: Option<ron::Value>,
+    /// The registered name of the state to transition to. Ignored if there's a `builder`.
+    #[serde(default)]
+    pub to: String,
+    /// The fields of the state to transition to. Defaults to `()`, for unit structs.
+    #[serde(default)]
+    pub state: Option<ron::Value>,
+    /// The name of a state builder in the [`StateBuilderRegistry`] that builds the state to
+    /// transition to from the trigger's output, instead of `to` and `state`. The trigger must be
+    /// in the [`TriggerRegistry`]. See [`StateMachine::trans_builder_registered`].
+    #[serde(default)]
+    pub builder: Option<String>,
+    /// Parameters passed to the state builder's constructor. Defaults to `()`.
+    #[serde(default)]
+    pub builder_params: Option<ron::Value>,
+    /// See [`StateMachine::priority`]
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl StateMachineAsset {
+    /// Builds a state machine from this asset. States and triggers that aren't in the registry, or
+    /// whose fields or parameters can't be deserialized, are skipped with a warning.
+    pub fn build(&self, registry: &MachineAssetRegistry) -> StateMachine {
+        self.build_with(
+            registry,
+            &TriggerRegistry::default(),
+            &StateBuilderRegistry::default(),
+            &TypeRegistry::empty(),
+        )
+    }
+
+    /// Like [`StateMachineAsset::build`], but triggers that aren't in the
+    /// [`MachineAssetRegistry`] are constructed by the [`TriggerRegistry`], and transitions'
+    /// `builder`s by the [`StateBuilderRegistry`], with their parameters deserialized with the
+    /// given type registry. This is how machines are built from [`MachineAsset`]s.
+    pub fn build_with(
+        &self,
+        registry: &MachineAssetRegistry,
+        triggers: &TriggerRegistry,
+        builders: &StateBuilderRegistry,
+        types: &TypeRegistry,
+    ) -> StateMachine {
+        let mut machine = StateMachine::default();
+
+        for name in &self.states {
+            if let Some(state) = registry.state(name) {
+                machine = (state.register)(machine);
+            }
+        }
+
+        for transition in &self.transitions {
+            let from = match &*transition.from {
+                "AnyState" => None,
+                name => match registry.state(name) {
+                    Some(state) => Some(state),
+                    None => continue,
+                },
+            };
+            let from_id = from.map_or(TypeId::of::<AnyState>(), |from| from.id);
+
+            if let Some(builder) = &transition.builder {
+                let built = build_registered(
+                    triggers.params_type(&transition.trigger),
+                    types,
+                    transition.params.clone(),
+                    |params| triggers.build(&transition.trigger, params),
+                )
+                .and_then(|trigger| {
+                    let builder = build_registered(
+                        builders.params_type(builder),
+                        types,
+                        transition.builder_params.clone(),
+                        |params| builders.build(builder, params),
+                    )?;
+                    Ok((trigger, builder))
+                });
+
+                match built {
+                    Ok((trigger, builder)) => {
+                        if let Some(from) = from {
+                            machine = (from.register)(machine);
+                        }
+                        machine = machine.trans_builder_dyn(from_id, trigger, builder);
+                        if transition.priority != 0 {
+                            machine = machine.priority(transition.priority);
+                        }
+                    }
+                    Err(err) => warn!(
+                        "couldn't build transition from {} triggered by {}: {err}",
+                        transition.from, transition.trigger,
+                    ),
+                }
+
+                continue;
+            }
+
+            let Some(to) = registry.state(&transition.to) else {
+                continue;
+            };
+            let params = transition.params.clone().unwrap_or(ron::Value::Unit);
+            let trigger = match registry.triggers.get(&transition.trigger) {
+                Some(trigger) => trigger(params).map_err(|err| err.to_string()),
+                None if triggers.contains(&transition.trigger) => build_registered(
+                    triggers.params_type(&transition.trigger),
+                    types,
+                    Some(params),
+                    |params| triggers.build(&transition.trigger, params),
+                ),
+                None => {
+                    warn!("no trigger named {} is registered", transition.trigger);
+                    continue;
+                }
+            };
+
+            let trigger = match trigger {
+                Ok(trigger) => trigger,
+                Err(err) => {
+                    warn!(
+                        "invalid parameters for trigger {}: {err}",
+                        transition.trigger
+                    );
+                    continue;
+                }
+            };
+            let state = match (to.build)(transition.state.clone().unwrap_or(ron::Value::Unit)) {
+                Ok(state) => state,
+                Err(err) => {
+                    warn!("invalid fields for state {}: {err}", transition.to);
+                    continue;
+                }
+            };
+
+            if let Some(from) = from {
+                machine = (from.register)(machine);
+            }
+            machine = (to.register)(machine);
+
+            let trigger_name = trigger.name();
+            machine = machine.trans_dyn(from_id, trigger, trigger_name, to.id, state);
+
+            if transition.priority != 0 {
+                machine = machine.priority(transition.priority);
+            }
+        }
+
+        machine
+    }
+}
+
+/// Add to an entity, along with its initial state, to give it a [`StateMachine`] built from the
+/// given [`StateMachineAsset`] once it loads
+#[derive(Clone, Component, Debug, Default)]
+pub struct MachineAsset(pub Handle<StateMachineAsset>);
+
+type StateBuilder = fn(ron::Value) -> ron::Result<Box<dyn Fn() -> Box<dyn Insert> + Send + Sync>>;
+type TriggerBuilder = Arc<dyn Fn(ron::Value) -> ron::Result<DynTrigger> + Send + Sync>;
+
+struct RegisteredState {
+    id: TypeId,
+    register: fn(StateMachine) -> StateMachine,
+    build: StateBuilder,
+}
+
+/// The states and triggers that [`StateMachineAsset`]s may refer to, by name. Register them when
+/// building your app, before any machine assets load.
+#[derive(Default, Resource)]
+pub struct MachineAssetRegistry {
+    states: HashMap<String, RegisteredState>,
+    triggers: HashMap<String, TriggerBuilder>,
+}
+
+impl MachineAssetRegistry {
+    /// Registers a state under the given name. Its fields are deserialized from the transitions'
+    /// `state` fields.
+    pub fn register_state<S: Clone + Component + DeserializeOwned>(
+        &mut self,
+        name: impl Into<String>,
+    ) -> &mut Self {
+        self.states.insert(
+            name.into(),
+            RegisteredState {
+                id: TypeId::of::<S>(),
+                register: StateMachine::with_state::<S>,
+                build: build_state::<S>,
+            },
+        );
+        self
+    }
+
+    /// Registers a trigger under the given name. `trigger` constructs the trigger from the
+    /// transitions' `params` fields. For triggers without parameters, take `()`.
+    pub fn register_trigger<
+        P: DeserializeOwned + 'static,
+        T: IntoTrigger<Marker> + 'static,
+        Marker: 'static,
+    >(
+        &mut self,
+        name: impl Into<String>,
+        trigger: impl Fn(P) -> T + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.triggers.insert(
+            name.into(),
+            Arc::new(move |params: ron::Value| {
+                Ok(DynTrigger::new(trigger(params.into_rust()?).into_trigger()))
+            }),
+        );
+        self
+    }
+
+    fn state(&self, name: &str) -> Option<&RegisteredState> {
+        let state = self.states.get(name);
+        if state.is_none() {
+            warn!("no state named {name} is registered");
+        }
+        state
+    }
+}
+
+/// Constructs a trigger or state builder with the [`TriggerRegistry`] or [`StateBuilderRegistry`],
+/// deserializing its parameters as its registered parameter type, given by `params_type`
+fn build_registered<T>(
+    params_type: Option<TypeId>,
+    types: &TypeRegistry,
+    params: Option<ron::Value>,
+    build: impl FnOnce(&dyn Reflect) -> Result<T, RegistryError>,
+) -> Result<T, String> {
+    let Some(id) = params_type.filter(|&id| id != TypeId::of::<()>()) else {
+        // Unregistered names fail in `build`
+        return build(&()).map_err(|err| err.to_string());
+    };
+
+    let registration = types
+        .get(id)
+        .ok_or("its parameter type isn't registered in the `AppTypeRegistry`")?;
+    let params = TypedReflectDeserializer::new(registration, types)
+        .deserialize(params.unwrap_or(ron::Value::Unit))
+        .map_err(|err| err.to_string())?;
+    build(&*params).map_err(|err| err.to_string())
+}
+
+fn build_state<S: Clone + Component + DeserializeOwned>(
+    fields: ron::Value,
+) -> ron::Result<Box<dyn Fn() -> Box<dyn Insert> + Send + Sync>> {
+    let state = fields.into_rust::<S>()?;
+    Ok(Box::new(move || Box::new(state.clone())))
+}
+
+#[derive(Default)]
+struct StateMachineAssetLoader;
+
+/// An error while loading a [`StateMachineAsset`]
+#[derive(Debug)]
+enum StateMachineAssetError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl Display for StateMachineAssetError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "couldn't read state machine asset: {err}"),
+            Self::Ron(err) => write!(f, "couldn't parse state machine asset: {err}"),
+        }
+    }
+}
+
+impl Error for StateMachineAssetError {}
+
+impl From<std::io::Error> for StateMachineAssetError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for StateMachineAssetError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        Self::Ron(err)
+    }
+}
+
+impl AssetLoader for StateMachineAssetLoader {
+    type Asset = StateMachineAsset;
+    type Settings = ();
+    type Error = StateMachineAssetError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _: &'a (),
+        _: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<StateMachineAsset, StateMachineAssetError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["machine.ron"]
+    }
+}
+
+fn build_asset_machines(
+    mut commands: Commands,
+    entities: Query<(Entity, &MachineAsset), Without<StateMachine>>,
+    assets: Res<Assets<StateMachineAsset>>,
+    registry: Res<MachineAssetRegistry>,
+    triggers: Res<TriggerRegistry>,
+    builders: Res<StateBuilderRegistry>,
+    type_registry: Res<AppTypeRegistry>,
+) {
+    let type_registry = type_registry.read();
+    for (entity, handle) in &entities {
+        if let Some(asset) = assets.get(&handle.0) {
+            commands.entity(entity).insert(asset.build_with(
+                &registry,
+                &triggers,
+                &builders,
+                &type_registry,
+            ));
+        }
+    }
+}
+
+fn reload_asset_machines(
+    mut events: EventReader<AssetEvent<StateMachineAsset>>,
+    mut machines: Query<(&MachineAsset, &mut StateMachine)>,
+    assets: Res<Assets<StateMachineAsset>>,
+    registry: Res<MachineAssetRegistry>,
+    triggers: Res<TriggerRegistry>,
+    builders: Res<StateBuilderRegistry>,
+    type_registry: Res<AppTypeRegistry>,
+) {
+    let type_registry = type_registry.read();
+    for event in events.read() {
+        let AssetEvent::Modified { id } = *event else {
+            continue;
+        };
+        let Some(asset) = assets.get(id) else {
+            continue;
+        };
+
+        for (handle, mut machine) in &mut machines {
+            if handle.0.id() == id {
+                let snapshot = machine.snapshot();
+                *machine = asset.build_with(&registry, &triggers, &builders, &type_registry);
+                machine.restore(&snapshot, &type_registry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component, Deserialize)]
+    struct Idle;
+
+    #[derive(Clone, Component, Deserialize)]
+    struct Chase {
+        speed: f32,
+    }
+
+    #[derive(Resource)]
+    struct Distance(f32);
+
+    const MACHINE: &str = r#"(
+        transitions: [
+            (from: "Idle", trigger: "near", params: 5.0, to: "Chase", state: (speed: 2.0)),
+            (from: "Chase", trigger: "always", to: "Idle"),
+            (from: "Chase", trigger: "near", params: 1.0, to: "Chase", priority: 1),
+        ],
+    )"#;
+
+    #[test]
+    fn test_state_machine_asset() {
+        let mut registry = MachineAssetRegistry::default();
+        registry
+            .register_state::<Idle>("Idle")
+            .register_state::<Chase>("Chase")
+            .register_trigger("always", |(): ()| always)
+            .register_trigger("near", |range: f32| {
+                move |In(_): In<Entity>, distance: Res<Distance>| distance.0 <= range
+            });
+
+        let asset = ron::from_str::<StateMachineAsset>(MACHINE).unwrap();
+        // The last transition has no fields for `Chase`, so it's skipped
+        let machine = asset.build(&registry);
+
+        let mut app = App::new();
+        app.insert_resource(Distance(10.))
+            .add_systems(Update, transition);
+        let entity = app.world.spawn((machine, Idle)).id();
+
+        app.update();
+        assert!(app.world.get::<Idle>(entity).is_some());
+
+        app.world.resource_mut::<Distance>().0 = 3.;
+        app.update();
+        assert_eq!(app.world.get::<Chase>(entity).unwrap().speed, 2.);
+
+        app.update();
+        assert!(app.world.get::<Idle>(entity).is_some());
+    }
+
+    #[test]
+    fn test_registered_triggers() {
+        #[derive(Reflect)]
+        struct Near {
+            range: f32,
+        }
+
+        let mut registry = MachineAssetRegistry::default();
+        registry
+            .register_state::<Idle>("Idle")
+            .register_state::<Chase>("Chase");
+        let mut triggers = TriggerRegistry::default();
+        triggers.register("near", |near: Near| {
+            move |In(_): In<Entity>, distance: Res<Distance>| distance.0 <= near.range
+        });
+        let mut types = TypeRegistry::default();
+        types.register::<Near>();
+
+        let asset = ron::from_str::<StateMachineAsset>(
+            r#"(transitions: [(
+                from: "Idle",
+                trigger: "near",
+                params: (range: 5.0),
+                to: "Chase",
+                state: (speed: 2.0),
+            )])"#,
+        )
+        .unwrap();
+        let machine = asset.build_with(&registry, &triggers, &default(), &types);
+
+        let mut app = App::new();
+        app.insert_resource(Distance(3.))
+            .add_systems(Update, transition);
+        let entity = app.world.spawn((machine, Idle)).id();
+
+        app.update();
+        assert_eq!(app.world.get::<Chase>(entity).unwrap().speed, 2.);
+    }
+}
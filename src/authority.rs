@@ -0,0 +1,177 @@
+//! Letting a server's decisions override an entity's local state machine. See
+//! [`AuthoritativeState`].
+
+use bevy::{ecs::schedule::BoxedScheduleLabel, reflect::ReflectFromReflect};
+
+use crate::{prelude::*, set::StateSet};
+
+pub(crate) fn authority_plugin(app: &mut App, schedule: BoxedScheduleLabel) {
+    app.register_type::<AuthoritativeState>().add_systems(
+        schedule,
+        // Released machines check their own triggers in the same run
+        (
+            follow_authoritative_states,
+            release_authoritative_states,
+            apply_deferred,
+        )
+            .chain()
+            .before(StateSet::Transition),
+    );
+}
+
+/// While an entity has this component, its [`StateMachine`] doesn't check its own triggers, and
+/// instead follows whichever of its states the entity is in, as set by another source, usually a
+/// networking library that replicates the server's state components and this component. When the
+/// entity's current state is replaced by another of its states, the machine is forced into that
+/// state (see [`StateMachine::force_state_reflect`]), running `on_exit` and `on_enter` events and
+/// other hooks, so client-side machines are cheap followers that share the server's definition.
+/// The states must be registered in the [`AppTypeRegistry`] with `#[reflect(Component)]`.
+///
+/// Optionally, control returns to the local machine after this component hasn't changed for a
+/// given number of runs of the [`StateMachinePlugin`]'s schedule (see
+/// [`AuthoritativeState::release_after()`]), and the machine continues from the state it's in.
+#[derive(Clone, Component, Debug, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct AuthoritativeState {
+    /// How many runs without a change to wait before removing this component, or `None` to keep
+    /// it until it's removed otherwise
+    pub release_after: Option<u32>,
+    /// Runs since this component last changed
+    #[reflect(ignore)]
+    ticks: u32,
+}
+
+impl AuthoritativeState {
+    /// Returns control to the local machine after this component hasn't changed for the given
+    /// number of runs
+    pub fn release_after(mut self, ticks: u32) -> Self {
+        self.release_after = Some(ticks);
+        self
+    }
+}
+
+/// Gets the state that the entity's machine should be forced into, if the entity's current state
+/// has been replaced by another of its states
+fn authoritative_state(world: &World, entity: Entity) -> Option<Box<dyn Reflect>> {
+    let machine = world.get::<StateMachine>(entity)?;
+    let registry = world.get_resource::<AppTypeRegistry>()?.read();
+    let entity_ref = world.get_entity(entity)?;
+    let component = |id| registry.get_type_data::<ReflectComponent>(id);
+
+    let current = machine.current_state_id()?;
+    if component(current)?.contains(entity_ref) {
+        return None;
+    }
+
+    let (state, _) = machine
+        .states()
+        .find(|&(id, _)| component(id).is_some_and(|component| component.contains(entity_ref)))?;
+    let value = component(state)?.reflect(entity_ref)?;
+    registry
+        .get_type_data::<ReflectFromReflect>(state)?
+        .from_reflect(value)
+}
+
+fn follow_authoritative_states(world: &mut World) {
+    let entities = world
+        .query_filtered::<Entity, (With<AuthoritativeState>, With<StateMachine>)>()
+        .iter(world)
+        .collect::<Vec<_>>();
+
+    for entity in entities {
+        if let Some(state) = authoritative_state(world, entity) {
+            world
+                .get_mut::<StateMachine>(entity)
+                .unwrap()
+                .force_state_reflect(state);
+        }
+    }
+}
+
+fn release_authoritative_states(
+    mut commands: Commands,
+    mut states: Query<(Entity, &mut AuthoritativeState)>,
+) {
+    for (entity, mut state) in &mut states {
+        let changed = state.is_changed();
+        // Counting runs isn't a change from the server
+        let state = state.bypass_change_detection();
+        if changed {
+            state.ticks = 0;
+            continue;
+        }
+
+        let Some(release_after) = state.release_after else {
+            continue;
+        };
+
+        state.ticks += 1;
+        if state.ticks >= release_after {
+            commands.entity(entity).remove::<AuthoritativeState>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component, Reflect)]
+    #[reflect(Component)]
+    struct StateOne;
+    #[derive(Clone, Component, Reflect)]
+    #[reflect(Component)]
+    struct StateTwo;
+
+    #[test]
+    fn test_authoritative_state() {
+        let mut app = App::new();
+        app.init_resource::<AppTypeRegistry>()
+            .register_type::<StateOne>()
+            .register_type::<StateTwo>()
+            .add_systems(
+                Update,
+                (
+                    follow_authoritative_states,
+                    release_authoritative_states,
+                    apply_deferred,
+                    transition,
+                )
+                    .chain(),
+            );
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateTwo, _>(always, StateOne);
+        let authority = AuthoritativeState::default().release_after(2);
+        let entity = app.world.spawn((machine, StateOne, authority.clone())).id();
+
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+
+        // The server's machine transitioned
+        app.world
+            .entity_mut(entity)
+            .remove::<StateOne>()
+            .insert((StateTwo, authority));
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert_eq!(
+            app.world
+                .get::<StateMachine>(entity)
+                .unwrap()
+                .current_state_id(),
+            Some(std::any::TypeId::of::<StateTwo>()),
+        );
+
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        // No word from the server for 2 runs, so the local machine takes over
+        app.update();
+        assert!(app.world.get::<AuthoritativeState>(entity).is_none());
+        assert!(app.world.get::<StateOne>(entity).is_some());
+    }
+}
@@ -0,0 +1,167 @@
+//! Blended state weights, which ease in and out as the state machine transitions. Useful for
+//! feeding animation blend trees. See [`StateWeights`].
+
+use std::{any::TypeId, time::Duration};
+
+use bevy::{ecs::schedule::BoxedScheduleLabel, utils::HashMap};
+
+use crate::{prelude::*, set::StateSet};
+
+pub(crate) fn blend_plugin(app: &mut App, schedule: BoxedScheduleLabel) {
+    app.configure_sets(
+        schedule.dyn_clone(),
+        StateSet::Blend.after(StateSet::Transition),
+    )
+    .add_systems(schedule, update_state_weights.in_set(StateSet::Blend));
+}
+
+/// Add to an entity with a [`StateMachine`] to track a weight for each state that eases towards `1`
+/// while the entity is in that state, and towards `0` otherwise. The state machine still switches
+/// states discretely; these weights are derived from it, so they only affect what reads them, such
+/// as an animation blend tree. States that have fully faded out are forgotten.
+#[derive(Clone, Component, Debug, Default)]
+pub struct StateWeights {
+    weights: HashMap<TypeId, f32>,
+    /// How long states take to fade in and out, unless overridden
+    fade: Duration,
+    /// Per-state fade in and fade out durations
+    fades: HashMap<TypeId, (Duration, Duration)>,
+}
+
+impl StateWeights {
+    /// Creates state weights that take the given duration to fade in and out
+    pub fn new(fade: Duration) -> Self {
+        Self { fade, ..default() }
+    }
+
+    /// Sets how long the given state takes to fade in and out
+    pub fn with_fade<S: Clone + Component>(
+        mut self,
+        fade_in: Duration,
+        fade_out: Duration,
+    ) -> Self {
+        self.fades.insert(TypeId::of::<S>(), (fade_in, fade_out));
+        self
+    }
+
+    /// Gets the given state's weight, from `0` to `1`
+    pub fn weight<S: Clone + Component>(&self) -> f32 {
+        self.weight_by_id(TypeId::of::<S>())
+    }
+
+    /// Gets the weight of the state with the given [`TypeId`], from `0` to `1`
+    pub fn weight_by_id(&self, state: TypeId) -> f32 {
+        self.weights.get(&state).copied().unwrap_or(0.)
+    }
+
+    /// Iterates over the states with nonzero weights, and their weights. The weights may not sum to
+    /// `1` while fading.
+    pub fn iter(&self) -> impl Iterator<Item = (TypeId, f32)> + '_ {
+        self.weights.iter().map(|(&state, &weight)| (state, weight))
+    }
+}
+
+/// Moves `weight` towards `target`, so that it would take `fade` to go from `0` to `1`
+fn approach(weight: f32, target: f32, delta: Duration, fade: Duration) -> f32 {
+    if fade.is_zero() {
+        return target;
+    }
+
+    let step = delta.as_secs_f32() / fade.as_secs_f32();
+    match weight < target {
+        true => (weight + step).min(target),
+        false => (weight - step).max(target),
+    }
+}
+
+fn update_state_weights(
+    time: Res<Time>,
+    mut machines: Query<(&StateMachine, &mut StateWeights), Without<Paused>>,
+) {
+    let delta = time.delta();
+
+    for (machine, mut weights) in &mut machines {
+        let weights = &mut *weights;
+        let current = machine.current_state_id();
+
+        if let Some(current) = current {
+            // The initial state starts fully weighted, instead of fading in from nothing
+            let initial = weights.weights.is_empty();
+            weights
+                .weights
+                .entry(current)
+                .or_insert(if initial { 1. } else { 0. });
+        }
+
+        weights.weights.retain(|&state, weight| {
+            let (fade_in, fade_out) = weights
+                .fades
+                .get(&state)
+                .copied()
+                .unwrap_or((weights.fade, weights.fade));
+
+            *weight = match Some(state) == current {
+                true => approach(*weight, 1., delta, fade_in),
+                false => approach(*weight, 0., delta, fade_out),
+            };
+
+            *weight > 0.
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component)]
+    struct StateOne;
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component)]
+    struct StateTwo;
+
+    #[derive(Resource)]
+    struct SomeResource;
+
+    #[test]
+    fn test_state_weights() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .add_systems(Update, (transition, update_state_weights).chain());
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(resource_exists::<SomeResource>(), StateTwo);
+        let weights = StateWeights::new(Duration::from_secs(1));
+        let entity = app.world.spawn((machine, weights, StateOne)).id();
+
+        app.update();
+        let weights = app.world.get::<StateWeights>(entity).unwrap();
+        assert_eq!(weights.weight::<StateOne>(), 1.);
+        assert_eq!(weights.weight::<StateTwo>(), 0.);
+
+        app.insert_resource(SomeResource);
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_millis(250));
+        app.update();
+        let weights = app.world.get::<StateWeights>(entity).unwrap();
+        assert_eq!(weights.weight::<StateOne>(), 0.75);
+        assert_eq!(weights.weight::<StateTwo>(), 0.25);
+
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs(1));
+        app.update();
+        let weights = app.world.get::<StateWeights>(entity).unwrap();
+        assert_eq!(weights.weight::<StateOne>(), 0.);
+        assert_eq!(weights.weight::<StateTwo>(), 1.);
+        assert_eq!(
+            weights.iter().count(),
+            1,
+            "faded out states should be forgotten"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Interoperability with `big-brain`, so state machines can carry out utility AI's actions, and
+//! scorers can be used as triggers. Enabled by the `big_brain` feature. See [`MachineAction`] and
+//! [`scorer`].
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+use bevy::ecs::{schedule::BoxedScheduleLabel, system::EntityCommands};
+use big_brain::prelude::{ActionBuilder, ActionState, Actor, Score};
+
+use crate::{machine::MachineSchedule, prelude::*, set::StateSet};
+
+/// Runs [`MachineAction`]s. Add it after [`StateMachinePlugin`] and `BigBrainPlugin`. It runs in
+/// the same schedule as [`StateMachinePlugin`].
+pub fn big_brain_machine_plugin(app: &mut App) {
+    let schedule = app.world.get_resource::<MachineSchedule>().map_or_else(
+        || Box::new(PostUpdate) as BoxedScheduleLabel,
+        |schedule| schedule.0.dyn_clone(),
+    );
+    app.add_systems(
+        schedule,
+        run_machine_actions
+            .after(StateSet::Transition)
+            .before(StateSet::RemoveDoneMarkers),
+    );
+}
+
+type BuildMachine = Arc<dyn Fn(&mut EntityCommands, Entity) + Send + Sync>;
+
+/// A `big-brain` action that's carried out by a [`StateMachine`]. When the action is requested, the
+/// machine is inserted on the action's entity in the given initial state, with the actor as its
+/// trigger context (see [`StateMachine::set_context`]), so its triggers check the actor's
+/// components. The action succeeds or fails when its entity gets [`Done::Success`] or
+/// [`Done::Failure`], and the machine is then removed. If the action is cancelled, the machine is
+/// removed, and the action fails. Use it with `Thinker::build().when(scorer, action)`, and add
+/// [`big_brain_machine_plugin`].
+#[derive(Clone)]
+pub struct MachineAction {
+    build: BuildMachine,
+}
+
+impl Debug for MachineAction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("MachineAction").finish_non_exhaustive()
+    }
+}
+
+impl MachineAction {
+    /// Creates an action that runs the machine built by `machine`, starting in `initial`
+    pub fn new<S: Clone + Component>(
+        initial: S,
+        machine: impl Fn() -> StateMachine + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            build: Arc::new(move |action, actor| {
+                action.insert((
+                    machine().set_context(TriggerContext::Entity(actor)),
+                    initial.clone(),
+                ));
+            }),
+        }
+    }
+}
+
+impl ActionBuilder for MachineAction {
+    fn build(&self, cmd: &mut Commands, action: Entity, _: Entity) {
+        cmd.entity(action)
+            .insert(MachineActionBuilder(self.build.clone()));
+    }
+}
+
+/// Builds the [`MachineAction`]'s machine when the action is requested
+#[derive(Component)]
+struct MachineActionBuilder(BuildMachine);
+
+fn run_machine_actions(
+    mut commands: Commands,
+    mut actions: Query<(
+        Entity,
+        &Actor,
+        &mut ActionState,
+        &MachineActionBuilder,
+        Option<&Done>,
+    )>,
+) {
+    for (entity, &Actor(actor), mut state, builder, done) in &mut actions {
+        match *state {
+            ActionState::Requested => {
+                (builder.0)(&mut commands.entity(entity), actor);
+                *state = ActionState::Executing;
+            }
+            ActionState::Executing => {
+                let Some(&done) = done else {
+                    continue;
+                };
+
+                commands.entity(entity).remove::<StateMachine>();
+                *state = match done {
+                    Done::Success => ActionState::Success,
+                    Done::Failure => ActionState::Failure,
+                };
+            }
+            ActionState::Cancelled => {
+                commands.entity(entity).remove::<StateMachine>();
+                *state = ActionState::Failure;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Trigger that transitions when the `big-brain` scorer with the component `S` whose actor is the
+/// context entity has a score of at least `threshold`. Outputs the score. Spawn the scorer with
+/// `Actor`, `Score`, and `S`, or with `ScorerBuilder::spawn_scorer`, and add its scoring system.
+pub fn scorer<S: Component>(threshold: f32) -> impl Trigger<Out = Result<f32, f32>> {
+    (move |In(entity): In<Entity>, scorers: Query<(&Actor, &Score), With<S>>| {
+        let score = scorers
+            .iter()
+            .find(|&(&Actor(actor), _)| actor == entity)
+            .map_or(0., |(_, score)| score.get());
+
+        if score >= threshold {
+            Ok(score)
+        } else {
+            Err(score)
+        }
+    })
+    .into_trigger()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct StateOne;
+    #[derive(Clone, Component)]
+    struct StateTwo;
+
+    #[derive(Component)]
+    struct SomeScorer;
+
+    #[test]
+    fn test_machine_action() {
+        let mut app = App::new();
+        app.add_systems(Update, (transition, run_machine_actions).chain());
+
+        let actor = app.world.spawn_empty().id();
+        let mut score = Score::default();
+        score.set(0.8);
+        app.world.spawn((Actor(actor), score, SomeScorer));
+
+        let action = MachineAction::new(StateOne, || {
+            StateMachine::default().trans::<StateOne, _>(scorer::<SomeScorer>(0.5), StateTwo)
+        });
+        let action = app
+            .world
+            .spawn((
+                Actor(actor),
+                ActionState::Requested,
+                MachineActionBuilder(action.build),
+            ))
+            .id();
+
+        app.update();
+        assert_eq!(
+            *app.world.get::<ActionState>(action).unwrap(),
+            ActionState::Executing,
+        );
+
+        // The scorer is checked on the actor
+        app.update();
+        assert!(app.world.get::<StateTwo>(action).is_some());
+
+        app.world.entity_mut(action).insert(Done::Failure);
+        app.update();
+        assert_eq!(
+            *app.world.get::<ActionState>(action).unwrap(),
+            ActionState::Failure,
+        );
+        assert!(app.world.get::<StateMachine>(action).is_none());
+    }
+}
@@ -0,0 +1,214 @@
+//! An egui window that lists every state machine, for debugging. Enabled by the `debug_panel`
+//! feature. See [`StateMachineDebugPlugin`].
+
+use std::{any::TypeId, collections::VecDeque, time::Duration};
+
+use bevy::utils::{get_short_name, HashMap};
+use bevy_egui::{egui, EguiContexts, EguiPlugin};
+
+use crate::{prelude::*, set::StateSet};
+
+/// How many recent transitions are shown for each entity
+const RECENT_TRANSITIONS: usize = 8;
+
+/// Adds an egui window that lists every entity with a [`StateMachine`], with its name, current
+/// state, time in that state, and recent transitions. Entities may be filtered by their
+/// [`Name`], their machine's name, or their current state. Add it along with
+/// [`StateMachinePlugin`]. Adds `EguiPlugin` if it hasn't been added. Toggle the window with
+/// [`StateMachineDebug::open`].
+#[derive(Debug, Default)]
+pub struct StateMachineDebugPlugin;
+
+impl Plugin for StateMachineDebugPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+
+        app.init_resource::<StateMachineDebug>()
+            .add_systems(Update, show_debug_panel)
+            .add_systems(PostUpdate, track_states.after(StateSet::Transition));
+    }
+}
+
+/// The state of the [`StateMachineDebugPlugin`]'s window
+#[derive(Debug, Resource)]
+pub struct StateMachineDebug {
+    /// Whether the window is shown. Defaults to `true`.
+    pub open: bool,
+    /// Only entities whose names or current states contain this are listed
+    pub filter: String,
+    entries: HashMap<Entity, DebugEntry>,
+}
+
+impl Default for StateMachineDebug {
+    fn default() -> Self {
+        Self {
+            open: true,
+            filter: default(),
+            entries: default(),
+        }
+    }
+}
+
+/// What the window shows for an entity, besides what's in its machine
+#[derive(Debug, Default)]
+struct DebugEntry {
+    state: Option<TypeId>,
+    /// When the entity entered its current state, according to [`Time`]
+    since: Duration,
+    /// The short names of the states that the entity recently transitioned from and to
+    recent: VecDeque<(String, String)>,
+}
+
+/// Notices when machines transition. Transitions that are chained within a frame (see
+/// [`StateMachine::set_chain_depth`]) appear as one.
+fn track_states(
+    machines: Query<(Entity, &StateMachine)>,
+    mut debug: ResMut<StateMachineDebug>,
+    time: Res<Time>,
+) {
+    let debug = &mut *debug;
+    debug.entries.retain(|&entity, _| machines.contains(entity));
+
+    for (entity, machine) in &machines {
+        let entry = debug.entries.entry(entity).or_insert_with(|| DebugEntry {
+            state: machine.current_state_id(),
+            since: time.elapsed(),
+            recent: default(),
+        });
+
+        let state = machine.current_state_id();
+        if entry.state == state {
+            continue;
+        }
+
+        let name = |name: Option<&str>| name.map_or("unknown".to_string(), get_short_name);
+        if entry.recent.len() == RECENT_TRANSITIONS {
+            entry.recent.pop_front();
+        }
+        entry.recent.push_back((
+            name(entry.state.and_then(|state| {
+                machine
+                    .states()
+                    .find(|&(id, _)| id == state)
+                    .map(|(_, name)| name)
+            })),
+            name(machine.current_state_name()),
+        ));
+        entry.state = state;
+        entry.since = time.elapsed();
+    }
+}
+
+fn show_debug_panel(
+    mut contexts: EguiContexts,
+    machines: Query<(Entity, &StateMachine, Option<&Name>)>,
+    mut debug: ResMut<StateMachineDebug>,
+    time: Res<Time>,
+) {
+    let debug = &mut *debug;
+    if !debug.open {
+        return;
+    }
+
+    let mut machines = machines
+        .iter()
+        .map(|(entity, machine, name)| {
+            let state = machine
+                .current_state_name()
+                .map_or("unknown".to_string(), get_short_name);
+            let mut label = format!("{entity:?}");
+            for name in name.map(Name::as_str).into_iter().chain(machine.name()) {
+                label.push(' ');
+                label.push_str(name);
+            }
+            (entity, label, state)
+        })
+        .filter(|(_, label, state)| {
+            let filter = debug.filter.to_lowercase();
+            label.to_lowercase().contains(&filter) || state.to_lowercase().contains(&filter)
+        })
+        .collect::<Vec<_>>();
+    machines.sort_by_key(|&(entity, ..)| entity);
+
+    egui::Window::new("State machines")
+        .open(&mut debug.open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Filter");
+                ui.text_edit_singleline(&mut debug.filter);
+            });
+            ui.label(format!("{} machines", machines.len()));
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (entity, label, state) in machines {
+                    let entry = debug.entries.get(&entity);
+                    let time_in_state = entry.map_or(Duration::ZERO, |entry| {
+                        time.elapsed().saturating_sub(entry.since)
+                    });
+
+                    egui::CollapsingHeader::new(format!(
+                        "{label}: {state} ({:.1}s)",
+                        time_in_state.as_secs_f32(),
+                    ))
+                    .id_source(entity)
+                    .show(ui, |ui| {
+                        let recent = entry
+                            .map(|entry| &entry.recent)
+                            .filter(|recent| !recent.is_empty());
+                        let Some(recent) = recent else {
+                            ui.label("No transitions yet");
+                            return;
+                        };
+
+                        for (from, to) in recent.iter().rev() {
+                            ui.label(format!("{from} -> {to}"));
+                        }
+                    });
+                }
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct StateOne;
+    #[derive(Clone, Component)]
+    struct StateTwo;
+
+    #[test]
+    fn test_track_states() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .init_resource::<StateMachineDebug>()
+            .add_systems(Update, (transition, track_states).chain());
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateTwo, _>(always, StateOne);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        app.update();
+        app.update();
+
+        let debug = app.world.resource::<StateMachineDebug>();
+        let recent = debug.entries[&entity]
+            .recent
+            .iter()
+            .map(|(from, to)| (&**from, &**to))
+            .collect::<Vec<_>>();
+        // The first transition is taken before the entity is tracked
+        assert_eq!(recent, [("StateTwo", "StateOne"), ("StateOne", "StateTwo")]);
+
+        app.world.despawn(entity);
+        app.update();
+        assert!(app.world.resource::<StateMachineDebug>().entries.is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+//! Bevy diagnostics for state machines. See [`StateMachineDiagnosticsPlugin`].
+
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics, RegisterDiagnostic};
+
+use crate::{machine::MachineStats, prelude::*};
+
+/// Records how much work state machines do each frame as Bevy diagnostics, so they show up in
+/// diagnostics overlays and `LogDiagnosticsPlugin`. Add it along with [`StateMachinePlugin`].
+/// Measurements include the transition systems of every schedule that machines run in.
+#[derive(Debug, Default)]
+pub struct StateMachineDiagnosticsPlugin;
+
+impl StateMachineDiagnosticsPlugin {
+    /// Transitions taken per frame
+    pub const TRANSITIONS: DiagnosticId =
+        DiagnosticId::from_u128(0xe3c4b1d473a40318cc63d75852d00c8);
+    /// Machines evaluated per frame
+    pub const MACHINES: DiagnosticId = DiagnosticId::from_u128(0x4e48bc9d2d8a42ccb6349dbdd206ee1e);
+    /// Triggers checked per frame, including those of regions and substates
+    pub const TRIGGERS: DiagnosticId = DiagnosticId::from_u128(0xbc14340a1a2841a8a8e82c448b86b37a);
+    /// Milliseconds spent in the transition systems per frame
+    pub const TIME: DiagnosticId = DiagnosticId::from_u128(0x8e8a3a5d921f4abcabe9d4b65a06dbd3);
+}
+
+impl Plugin for StateMachineDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MachineStats>()
+            .register_diagnostic(Diagnostic::new(
+                Self::TRANSITIONS,
+                "state_machine/transitions",
+                20,
+            ))
+            .register_diagnostic(Diagnostic::new(
+                Self::MACHINES,
+                "state_machine/machines",
+                20,
+            ))
+            .register_diagnostic(Diagnostic::new(
+                Self::TRIGGERS,
+                "state_machine/triggers",
+                20,
+            ))
+            .register_diagnostic(
+                Diagnostic::new(Self::TIME, "state_machine/time", 20).with_suffix("ms"),
+            )
+            .add_systems(Last, record_diagnostics);
+    }
+}
+
+fn record_diagnostics(mut stats: ResMut<MachineStats>, mut diagnostics: Diagnostics) {
+    let stats = std::mem::take(&mut *stats);
+    diagnostics.add_measurement(StateMachineDiagnosticsPlugin::TRANSITIONS, || {
+        stats.transitions as f64
+    });
+    diagnostics.add_measurement(StateMachineDiagnosticsPlugin::MACHINES, || {
+        stats.machines as f64
+    });
+    diagnostics.add_measurement(StateMachineDiagnosticsPlugin::TRIGGERS, || {
+        stats.triggers as f64
+    });
+    diagnostics.add_measurement(StateMachineDiagnosticsPlugin::TIME, || {
+        stats.time.as_secs_f64() * 1000.
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct StateOne;
+    #[derive(Clone, Component)]
+    struct StateTwo;
+
+    #[test]
+    fn test_machine_stats() {
+        let mut app = App::new();
+        app.init_resource::<MachineStats>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(|| false, StateTwo)
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateTwo, _>(|| false, StateOne);
+        app.world.spawn((machine, StateOne));
+        app.world.spawn((StateMachine::default(), StateOne));
+
+        app.update();
+        let stats = app.world.resource::<MachineStats>();
+        assert_eq!(
+            (stats.transitions, stats.machines, stats.triggers),
+            (1, 2, 2)
+        );
+    }
+}
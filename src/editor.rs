@@ -0,0 +1,237 @@
+//! A `bevy_editor_pls` window that draws the selected entity's state machine as a node graph.
+//! Enabled by the `editor` feature. See [`StateMachineEditorWindow`].
+
+use std::{any::TypeId, f32::consts::TAU};
+
+use bevy::utils::get_short_name;
+use bevy_editor_pls::{
+    default_windows::hierarchy::HierarchyWindow,
+    editor_window::{EditorWindow, EditorWindowContext},
+    egui::{self, Align2, Color32, FontId, Pos2, Sense, Stroke, Vec2},
+};
+
+use crate::prelude::*;
+
+/// How long a transition's edge is highlighted after it's taken, in seconds
+const FLASH_DURATION: f32 = 0.6;
+/// The radius of each state's node
+const NODE_RADIUS: f32 = 28.;
+
+/// An editor window for `bevy_editor_pls` that draws the [`StateMachine`] of the entity selected in
+/// the hierarchy as a node graph. States are laid out in a circle, with [`AnyState`] in the
+/// middle, and the current state is highlighted. When the machine transitions, the edge it took
+/// flashes. Transitions that don't go to a known state, like those of [`StateMachine::trans_pop`],
+/// aren't drawn, and neither are substates and regions. Add it with
+/// `app.add_editor_window::<StateMachineEditorWindow>()`, after adding `EditorPlugin`.
+#[derive(Debug, Default)]
+pub struct StateMachineEditorWindow;
+
+/// The state of the [`StateMachineEditorWindow`]
+#[derive(Debug, Default)]
+pub struct StateMachineEditorState {
+    /// The entity whose machine was last drawn, and its state at the time
+    last: Option<(Entity, Option<TypeId>)>,
+    /// The last transition that was noticed, and when, according to [`Time`]
+    flash: Option<(TypeId, TypeId, f32)>,
+}
+
+impl StateMachineEditorState {
+    /// Notices whether the entity's machine transitioned since it was last drawn. Transitions that
+    /// are chained within a frame (see [`StateMachine::set_chain_depth`]) appear as one.
+    fn observe(&mut self, entity: Entity, state: Option<TypeId>, now: f32) {
+        if let Some((last_entity, Some(last_state))) = self.last {
+            if last_entity == entity && state != Some(last_state) {
+                if let Some(state) = state {
+                    self.flash = Some((last_state, state, now));
+                }
+            }
+        }
+
+        if self.last.map(|(last_entity, _)| last_entity) != Some(entity) {
+            self.flash = None;
+        }
+        self.last = Some((entity, state));
+    }
+
+    /// How brightly the edge from `from` to `to` is highlighted, from 0 to 1
+    fn flash(&self, from: TypeId, to: TypeId, now: f32) -> f32 {
+        self.flash
+            .filter(|&(flash_from, flash_to, _)| {
+                (flash_from == from || from == TypeId::of::<AnyState>()) && flash_to == to
+            })
+            .map_or(0., |(.., at)| {
+                (1. - (now - at) / FLASH_DURATION).clamp(0., 1.)
+            })
+    }
+}
+
+impl EditorWindow for StateMachineEditorWindow {
+    type State = StateMachineEditorState;
+
+    const NAME: &'static str = "State Machine";
+
+    fn ui(world: &mut World, mut cx: EditorWindowContext, ui: &mut egui::Ui) {
+        let selected = cx
+            .state::<HierarchyWindow>()
+            .and_then(|hierarchy| hierarchy.selected.iter().next());
+        let Some(state) = cx.state_mut::<StateMachineEditorWindow>() else {
+            return;
+        };
+
+        let Some((entity, machine)) =
+            selected.and_then(|entity| Some((entity, world.get::<StateMachine>(entity)?)))
+        else {
+            ui.label("Select an entity with a state machine");
+            return;
+        };
+
+        let now = world.resource::<Time>().elapsed_seconds();
+        let current = machine.current_state_id();
+        state.observe(entity, current, now);
+
+        if let Some(name) = machine.name() {
+            ui.label(format!("Name: {name}"));
+        }
+
+        let any = TypeId::of::<AnyState>();
+        let mut states = machine.states().collect::<Vec<_>>();
+        states.sort_by_key(|&(_, name)| name);
+
+        let size = ui.available_size().max(Vec2::splat(NODE_RADIUS * 6.));
+        let (response, painter) = ui.allocate_painter(size, Sense::hover());
+        let rect = response.rect;
+        let positions = layout(
+            states.len(),
+            rect.center(),
+            (rect.width().min(rect.height()) / 2. - NODE_RADIUS * 1.5).max(NODE_RADIUS),
+        );
+        let position = |state: TypeId| {
+            states
+                .iter()
+                .position(|&(id, _)| id == state)
+                .map_or(rect.center(), |index| positions[index])
+        };
+
+        let visuals = ui.visuals();
+        let text_color = visuals.text_color();
+        let font = FontId::proportional(12.);
+        let mut animating = false;
+
+        for transition in machine.transitions() {
+            let Some(to) = transition.to else {
+                continue;
+            };
+            if transition.from == to {
+                continue;
+            }
+
+            let flash = state.flash(transition.from, to, now);
+            animating |= flash > 0.;
+            let color = lerp_color(visuals.weak_text_color(), Color32::YELLOW, flash);
+
+            let (start, end) = (position(transition.from), position(to));
+            let direction = (end - start).normalized();
+            // Offset edges sideways so transitions in both directions don't overlap
+            let side = direction.rot90() * 4.;
+            let start = start + direction * NODE_RADIUS + side;
+            let end = end - direction * NODE_RADIUS + side;
+            painter.arrow(start, end - start, Stroke::new(1.5 + flash * 2., color));
+            painter.text(
+                start.lerp(end, 0.5) + side * 2.,
+                Align2::CENTER_CENTER,
+                get_short_name(transition.trigger_name),
+                font.clone(),
+                color,
+            );
+        }
+
+        let nodes = states
+            .iter()
+            .map(|&(id, name)| (id, get_short_name(name)))
+            .chain(
+                machine
+                    .transitions()
+                    .any(|transition| transition.from == any)
+                    .then(|| (any, "AnyState".to_string())),
+            );
+
+        for (id, name) in nodes {
+            let center = position(id);
+            let fill = if Some(id) == current {
+                visuals.selection.bg_fill
+            } else {
+                visuals.extreme_bg_color
+            };
+
+            painter.circle(center, NODE_RADIUS, fill, Stroke::new(1., text_color));
+            painter.text(
+                center,
+                Align2::CENTER_CENTER,
+                name,
+                font.clone(),
+                text_color,
+            );
+        }
+
+        if animating {
+            ui.ctx().request_repaint();
+        }
+    }
+}
+
+/// Positions `count` nodes evenly around a circle, starting at the top
+fn layout(count: usize, center: Pos2, radius: f32) -> Vec<Pos2> {
+    (0..count)
+        .map(|index| {
+            let angle = index as f32 / count as f32 * TAU - TAU / 4.;
+            center + Vec2::angled(angle) * radius
+        })
+        .collect()
+}
+
+fn lerp_color(from: Color32, to: Color32, t: f32) -> Color32 {
+    let lerp = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+    Color32::from_rgba_unmultiplied(
+        lerp(from.r(), to.r()),
+        lerp(from.g(), to.g()),
+        lerp(from.b(), to.b()),
+        lerp(from.a(), to.a()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StateOne;
+    struct StateTwo;
+
+    #[test]
+    fn test_flash() {
+        let one = TypeId::of::<StateOne>();
+        let two = TypeId::of::<StateTwo>();
+        let entity = Entity::from_raw(0);
+        let mut state = StateMachineEditorState::default();
+
+        state.observe(entity, Some(one), 0.);
+        assert_eq!(state.flash(one, two, 0.), 0.);
+
+        state.observe(entity, Some(two), 1.);
+        assert_eq!(state.flash(one, two, 1.), 1.);
+        assert_eq!(state.flash(TypeId::of::<AnyState>(), two, 1.), 1.);
+        assert_eq!(state.flash(two, one, 1.), 0.);
+        assert_eq!(state.flash(one, two, 1. + FLASH_DURATION), 0.);
+
+        // Selecting another entity doesn't flash
+        state.observe(Entity::from_raw(1), Some(one), 2.);
+        assert_eq!(state.flash(two, one, 2.), 0.);
+    }
+
+    #[test]
+    fn test_layout() {
+        let positions = layout(4, Pos2::ZERO, 10.);
+        assert_eq!(positions.len(), 4);
+        assert!((positions[0] - Pos2::new(0., -10.)).length() < 1e-4);
+        assert!((positions[1] - Pos2::new(10., 0.)).length() < 1e-4);
+    }
+}
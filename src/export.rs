@@ -0,0 +1,383 @@
+//! Exporting state machines as graphs, to review their structure visually, and as JSON, for
+//! external tools. See [`StateMachine::to_dot`], [`StateMachine::to_mermaid`], and
+//! [`StateMachine::to_json`].
+
+use std::{any::TypeId, fmt::Write};
+
+use bevy::utils::{get_short_name, HashSet};
+
+use crate::prelude::*;
+
+impl StateMachine {
+    /// Exports the state machine's states and transitions as a Graphviz DOT graph, named after the
+    /// machine (see [`StateMachine::named`]). Edges are labeled with their triggers' type names and
+    /// priorities. [`AnyState`] transitions are dashed edges from a dashed `AnyState` node.
+    /// Transitions that don't go to a known state, like those of [`StateMachine::trans_pop`],
+    /// aren't exported, and neither are substates and regions. Render it with `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        let any = TypeId::of::<AnyState>();
+        let mut states = self.states().collect::<Vec<_>>();
+        states.sort_by_key(|&(_, name)| name);
+
+        let mut dot = String::new();
+        writeln!(
+            dot,
+            "digraph \"{}\" {{",
+            escape(self.name().unwrap_or("StateMachine"))
+        )
+        .unwrap();
+
+        for (_, name) in states {
+            writeln!(
+                dot,
+                "    \"{}\" [label=\"{}\"];",
+                escape(name),
+                escape(&get_short_name(name)),
+            )
+            .unwrap();
+        }
+
+        if let Some(transition) = self.transitions().find(|transition| transition.from == any) {
+            writeln!(
+                dot,
+                "    \"{}\" [label=\"AnyState\", style=dashed];",
+                escape(transition.from_name),
+            )
+            .unwrap();
+        }
+
+        for transition in self.transitions() {
+            let Some(to_name) = transition.to_name else {
+                continue;
+            };
+
+            let mut label = get_short_name(transition.trigger_name);
+            if transition.priority != 0 {
+                write!(label, " (priority {})", transition.priority).unwrap();
+            }
+
+            let style = if transition.from == any {
+                ", style=dashed"
+            } else {
+                ""
+            };
+
+            writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"{}\"{style}];",
+                escape(transition.from_name),
+                escape(to_name),
+                escape(&label),
+            )
+            .unwrap();
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// Exports the state machine's states and transitions as a Mermaid state diagram, which may be
+    /// pasted into Markdown in a ```` ```mermaid ```` block. Like [`StateMachine::to_dot`], edges
+    /// are labeled with their triggers' type names and priorities, and [`AnyState`] transitions are
+    /// from an `AnyState` state. The diagram is titled with the machine's name, if it has one.
+    pub fn to_mermaid(&self) -> String {
+        let any = TypeId::of::<AnyState>();
+        let mut states = self.states().collect::<Vec<_>>();
+        states.sort_by_key(|&(_, name)| name);
+
+        let mut mermaid = String::new();
+        if let Some(name) = self.name() {
+            writeln!(mermaid, "---\ntitle: {}\n---", escape_mermaid(name)).unwrap();
+        }
+        writeln!(mermaid, "stateDiagram-v2").unwrap();
+
+        // Mermaid ids can't contain paths, so states are numbered in the order they're listed
+        let id = |state: TypeId| match states.iter().position(|&(id, _)| id == state) {
+            Some(index) => format!("s{index}"),
+            None => "any".to_string(),
+        };
+
+        for &(state, name) in &states {
+            writeln!(
+                mermaid,
+                "    state \"{}\" as {}",
+                escape_mermaid(&get_short_name(name)),
+                id(state),
+            )
+            .unwrap();
+        }
+
+        if self.transitions().any(|transition| transition.from == any) {
+            writeln!(mermaid, "    state \"AnyState\" as any").unwrap();
+        }
+
+        for transition in self.transitions() {
+            let Some(to) = transition.to else {
+                continue;
+            };
+
+            let mut label = get_short_name(transition.trigger_name);
+            if transition.priority != 0 {
+                write!(label, " (priority {})", transition.priority).unwrap();
+            }
+
+            writeln!(
+                mermaid,
+                "    {} --> {} : {}",
+                id(transition.from),
+                id(to),
+                escape_mermaid(&label),
+            )
+            .unwrap();
+        }
+
+        mermaid
+    }
+
+    /// Exports the state machine's structure and status as JSON, for external tools and bug
+    /// reports. Includes the machine's name, its current state, its states, and its transitions
+    /// in the order they're checked, with their triggers and whether each trigger occurred when it
+    /// was last checked (see [`TransitionInfo::last_result`]). States and triggers are given by
+    /// their full type names. Substates and regions aren't included.
+    pub fn to_json(&self) -> String {
+        let mut states = self.states().map(|(_, name)| name).collect::<Vec<_>>();
+        states.sort();
+
+        let mut json = String::new();
+        write!(
+            json,
+            r#"{{"name":{},"current":{},"states":["#,
+            json_option(self.name()),
+            json_option(self.current_state_name()),
+        )
+        .unwrap();
+
+        for (index, state) in states.into_iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            json.push_str(&json_string(state));
+        }
+
+        json.push_str(r#"],"transitions":["#);
+        for (index, transition) in self.transitions().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+
+            write!(
+                json,
+                r#"{{"from":{},"trigger":{},"to":{},"priority":{},"last_result":{}}}"#,
+                json_string(transition.from_name),
+                json_string(transition.trigger_name),
+                json_option(transition.to_name),
+                transition.priority,
+                transition
+                    .last_result
+                    .map_or("null".to_string(), |result| result.to_string()),
+            )
+            .unwrap();
+        }
+
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Logs the DOT graph (see [`StateMachine::to_dot`]) of each [`MachineDefinition`] in
+/// [`MachineDefinitions`], and of each distinct [`StateMachine`] in the world. Add it to
+/// `PostStartup`, after your machines are spawned, to dump them for review.
+pub fn log_machine_graphs(machines: Query<&StateMachine>, definitions: Res<MachineDefinitions>) {
+    let mut logged = HashSet::new();
+
+    for (name, definition) in definitions.iter() {
+        let dot = definition.instantiate().named(name.to_string()).to_dot();
+        if logged.insert(dot.clone()) {
+            info!("state machine definition {name}:\n{dot}");
+        }
+    }
+
+    for machine in &machines {
+        let dot = machine.to_dot();
+        if logged.insert(dot.clone()) {
+            info!("state machine:\n{dot}");
+        }
+    }
+}
+
+/// Exports every [`MachineDefinition`] in [`MachineDefinitions`], and every given entity's
+/// [`StateMachine`], as JSON (see [`StateMachine::to_json`]). The definitions are an object keyed
+/// by their names, and the entities are an array of objects with the entity's index, generation,
+/// and machine.
+pub fn machines_to_json<'a>(
+    definitions: &MachineDefinitions,
+    machines: impl IntoIterator<Item = (Entity, &'a StateMachine)>,
+) -> String {
+    let mut definitions = definitions.iter().collect::<Vec<_>>();
+    definitions.sort_by_key(|&(name, _)| name);
+
+    let mut json = r#"{"definitions":{"#.to_string();
+    for (index, (name, definition)) in definitions.into_iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        let machine = definition.instantiate().to_json();
+        write!(json, "{}:{machine}", json_string(name)).unwrap();
+    }
+
+    json.push_str(r#"},"entities":["#);
+    for (index, (entity, machine)) in machines.into_iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        write!(
+            json,
+            r#"{{"index":{},"generation":{},"machine":{}}}"#,
+            entity.index(),
+            entity.generation(),
+            machine.to_json(),
+        )
+        .unwrap();
+    }
+
+    json.push_str("]}");
+    json
+}
+
+/// Logs [`machines_to_json`] for every entity with a [`StateMachine`]. Bind it to a key for bug
+/// reports, such as with `log_machines_json.run_if(input_just_pressed(KeyCode::F9))`.
+pub fn log_machines_json(
+    machines: Query<(Entity, &StateMachine)>,
+    definitions: Res<MachineDefinitions>,
+) {
+    info!(
+        "state machines: {}",
+        machines_to_json(&definitions, &machines)
+    );
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for char in text.chars() {
+        match char {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            char if char.is_control() => write!(json, "\\u{:04x}", char as u32).unwrap(),
+            char => json.push(char),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_option(text: Option<&str>) -> String {
+    text.map_or("null".to_string(), json_string)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escapes characters that Mermaid would otherwise parse, using its entity codes
+fn escape_mermaid(text: &str) -> String {
+    text.replace('#', "#35;")
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace(':', "#58;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::type_name;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct StateOne;
+    #[derive(Clone, Component)]
+    struct StateTwo;
+
+    struct Far;
+
+    impl Trigger for Far {
+        type Out = bool;
+
+        fn init(&mut self, _: &mut World) {}
+
+        fn check(&mut self, _: Entity, _: &World) -> bool {
+            false
+        }
+    }
+
+    fn machine() -> StateMachine {
+        StateMachine::default()
+            .named("test")
+            .trans::<StateOne, _>(Far, StateTwo)
+            .trans::<AnyState, _>(Far, StateOne)
+            .priority(1)
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let one = type_name::<StateOne>();
+        let two = type_name::<StateTwo>();
+        assert_eq!(
+            machine().to_dot(),
+            format!(
+                r#"digraph "test" {{
+    "{one}" [label="StateOne"];
+    "{two}" [label="StateTwo"];
+    "seldom_state::state::AnyState" [label="AnyState", style=dashed];
+    "seldom_state::state::AnyState" -> "{one}" [label="Far (priority 1)", style=dashed];
+    "{one}" -> "{two}" [label="Far"];
+}}
+"#
+            ),
+        );
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        assert_eq!(
+            machine().to_mermaid(),
+            r#"---
+title: test
+---
+stateDiagram-v2
+    state "StateOne" as s0
+    state "StateTwo" as s1
+    state "AnyState" as any
+    any --> s0 : Far (priority 1)
+    s0 --> s1 : Far
+"#,
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let one = type_name::<StateOne>();
+        let two = type_name::<StateTwo>();
+        let any = type_name::<AnyState>();
+        let far = type_name::<Far>();
+        assert_eq!(
+            machine().to_json(),
+            format!(
+                concat!(
+                    r#"{{"name":"test","current":null,"states":["{one}","{two}"],"transitions":["#,
+                    r#"{{"from":"{any}","trigger":"{far}","to":"{one}","priority":1,"#,
+                    r#""last_result":null}},{{"from":"{one}","trigger":"{far}","to":"{two}","#,
+                    r#""priority":0,"last_result":null}}]}}"#,
+                ),
+                one = one,
+                two = two,
+                any = any,
+                far = far,
+            ),
+        );
+
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+    }
+}
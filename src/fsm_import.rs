@@ -0,0 +1,561 @@
+//! Importing state machines written for the `rust-fsm` and `statig` crates, to ease migrating them
+//! to ECS. Enabled by the `fsm_import` feature. See [`StateMachineAsset::from_rust_fsm`] and
+//! [`StateMachineAsset::from_statig`].
+
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::prelude::*;
+
+/// An error while importing a `rust-fsm` or `statig` definition. See
+/// [`StateMachineAsset::from_rust_fsm`] and [`StateMachineAsset::from_statig`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FsmImportError {
+    /// Something other than what was expected was found
+    Unexpected {
+        /// What was expected
+        expected: &'static str,
+        /// What was found, or `None` if the definition ended
+        found: Option<String>,
+    },
+    /// A bracket, brace, or parenthesis isn't closed
+    Unclosed,
+}
+
+impl Display for FsmImportError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Unexpected {
+                expected,
+                found: Some(found),
+            } => write!(f, "expected {expected}, found `{found}`"),
+            Self::Unexpected {
+                expected,
+                found: None,
+            } => write!(f, "expected {expected}, found the end of the definition"),
+            Self::Unclosed => write!(f, "unclosed delimiter"),
+        }
+    }
+}
+
+impl Error for FsmImportError {}
+
+impl StateMachineAsset {
+    /// Imports a state machine from the body of `rust-fsm`'s `state_machine!` macro, with or
+    /// without the macro invocation around it. Each transition, like `Closed(Unsuccessful) =>
+    /// Open` or `HalfOpen => { Successful => Closed }`, becomes a transition from its state to its
+    /// target state, triggered by its input. Inputs are looked up as triggers in the
+    /// [`MachineAssetRegistry`], and states as states, when the asset is built, so register your
+    /// states as unit components under the same names. Outputs, like `[SetupTimer]`, and
+    /// attributes, like `derive(Debug)`, are ignored.
+    pub fn from_rust_fsm(definition: &str) -> Result<Self, FsmImportError> {
+        let mut tokens = Tokens::new(definition)?;
+        if let Some(index) = tokens.position("state_machine") {
+            tokens.index = index + 1;
+            tokens.expect("!")?;
+            let open = tokens.next_token();
+            if !matches!(open, Some("{" | "(" | "[")) {
+                return Err(unexpected("`{`", open));
+            }
+        }
+
+        let mut asset = Self::default();
+        while let Some(token) = tokens.peek() {
+            match token {
+                "#" => {
+                    tokens.index += 1;
+                    tokens.skip_group()?;
+                }
+                "," => tokens.index += 1,
+                "pub" => {
+                    tokens.index += 1;
+                    if tokens.peek() == Some("(") {
+                        tokens.skip_group()?;
+                    }
+                }
+                "}" | ")" | "]" => break,
+                _ => {
+                    let from = tokens.ident()?.to_string();
+                    match tokens.next_token() {
+                        Some("(") => {
+                            let input = tokens.ident()?.to_string();
+                            tokens.expect(")")?;
+                            if tokens.peek() == Some("=>") {
+                                tokens.index += 1;
+                                let to = tokens.ident()?.to_string();
+                                tokens.skip_output()?;
+                                asset.push_transition(&from, &input, &to);
+                            } else if from != "derive" && from != "repr_c" {
+                                // The machine's name and initial state
+                                asset.push_state(&input);
+                            }
+                        }
+                        Some("=>") => {
+                            tokens.expect("{")?;
+                            while tokens.peek() != Some("}") {
+                                let input = tokens.ident()?.to_string();
+                                tokens.expect("=>")?;
+                                let to = tokens.ident()?.to_string();
+                                tokens.skip_output()?;
+                                asset.push_transition(&from, &input, &to);
+                                if tokens.peek() == Some(",") {
+                                    tokens.index += 1;
+                                }
+                            }
+                            tokens.index += 1;
+                        }
+                        token => return Err(unexpected("`(` or `=>`", token)),
+                    }
+                }
+            }
+        }
+
+        Ok(asset)
+    }
+
+    /// Imports a state machine from a `statig` `#[state_machine]` impl block. Each `#[state]`
+    /// function becomes a state named in `UpperCamelCase`, like the variants of `statig`'s `State`
+    /// enum, so `fn led_on` becomes `LedOn`. Each `match` arm in a state that returns
+    /// `Transition(State::led_off())` becomes a transition triggered by the arm's event variant,
+    /// like `TimerElapsed` for `Event::TimerElapsed`, or several for `Event::A | Event::B`.
+    /// Transitions of `#[superstate]`s are added to their states after the states' own, since
+    /// `Super` defers to them. Events are looked up as triggers in the [`MachineAssetRegistry`],
+    /// and states as states, when the asset is built. Wildcard arms, actions, and the data passed
+    /// to states are ignored, and arms that transition conditionally become unconditional, so
+    /// review the result.
+    pub fn from_statig(definition: &str) -> Result<Self, FsmImportError> {
+        let mut tokens = Tokens::new(definition)?;
+        // States and superstates, with their superstates and transitions
+        let mut states = Vec::<StatigState>::new();
+
+        while let Some(token) = tokens.next_token() {
+            if token != "#" {
+                continue;
+            }
+
+            tokens.expect("[")?;
+            let kind = tokens.ident()?.to_string();
+            if kind != "state" && kind != "superstate" {
+                tokens.index -= 2;
+                tokens.skip_group()?;
+                continue;
+            }
+
+            let mut superstate = None;
+            if tokens.peek() == Some("(") {
+                let start = tokens.index;
+                tokens.skip_group()?;
+                let arguments = &tokens.tokens[start..tokens.index];
+                superstate = arguments
+                    .windows(3)
+                    .find(|window| window[0] == "superstate" && window[1] == "=")
+                    .map(|window| window[2].trim_matches('"').to_string());
+            }
+            tokens.expect("]")?;
+
+            // Skip any other attributes and the function's qualifiers
+            while tokens.peek() != Some("fn") {
+                match tokens.next_token() {
+                    Some("#") => tokens.skip_group()?,
+                    Some(_) => (),
+                    None => return Err(unexpected("`fn`", None)),
+                }
+            }
+            tokens.index += 1;
+            let name = tokens.ident()?.to_string();
+
+            let start = tokens
+                .position_from("{", tokens.index)
+                .ok_or(unexpected("`{`", None))?;
+            tokens.index = start;
+            tokens.skip_group()?;
+            let body = Tokens {
+                tokens: tokens.tokens[start + 1..tokens.index - 1].to_vec(),
+                index: 0,
+            };
+
+            states.push(StatigState {
+                name,
+                superstate,
+                is_state: kind == "state",
+                transitions: statig_transitions(body)?,
+            });
+        }
+
+        let mut asset = Self::default();
+        for state in states.iter().filter(|state| state.is_state) {
+            let from = upper_camel_case(&state.name);
+            asset.push_state(&from);
+
+            let mut current = Some(state);
+            // Superstates can't nest more deeply than there are states
+            for _ in 0..=states.len() {
+                let Some(state) = current else {
+                    break;
+                };
+
+                for (event, to) in &state.transitions {
+                    asset.push_transition(&from, event, &upper_camel_case(to));
+                }
+
+                current = state.superstate.as_ref().and_then(|superstate| {
+                    states
+                        .iter()
+                        .find(|state| !state.is_state && &state.name == superstate)
+                });
+            }
+        }
+
+        Ok(asset)
+    }
+
+    fn push_state(&mut self, state: &str) {
+        if !self.states.iter().any(|existing| existing == state) {
+            self.states.push(state.to_string());
+        }
+    }
+
+    fn push_transition(&mut self, from: &str, trigger: &str, to: &str) {
+        self.push_state(from);
+        self.push_state(to);
+        self.transitions.push(TransitionAsset {
+            from: from.to_string(),
+            trigger: trigger.to_string(),
+            params: None,
+            to: to.to_string(),
+            state: None,
+            builder: None,
+            builder_params: None,
+            priority: 0,
+        });
+    }
+}
+
+/// A `statig` state or superstate
+struct StatigState {
+    name: String,
+    superstate: Option<String>,
+    /// Whether this is a state, rather than a superstate
+    is_state: bool,
+    /// The events and target states of the transitions in this state's `match` arms
+    transitions: Vec<(String, String)>,
+}
+
+/// Finds the transitions in the `match` arms of a `statig` state's body
+fn statig_transitions(mut body: Tokens) -> Result<Vec<(String, String)>, FsmImportError> {
+    let mut transitions = Vec::new();
+
+    while let Some(start) = body.position_from("match", body.index) {
+        let Some(open) = body.position_from("{", start) else {
+            break;
+        };
+        body.index = open;
+        body.skip_group()?;
+        let mut arms = Tokens {
+            tokens: body.tokens[open + 1..body.index - 1].to_vec(),
+            index: 0,
+        };
+
+        while arms.peek().is_some() {
+            let mut events = Vec::new();
+            while arms.peek() != Some("=>") {
+                match arms.next_token() {
+                    Some("::") => events.push(arms.ident()?.to_string()),
+                    Some("(" | "{" | "[") => {
+                        arms.index -= 1;
+                        arms.skip_group()?;
+                    }
+                    Some(_) => (),
+                    None => return Err(unexpected("`=>`", None)),
+                }
+            }
+            arms.index += 1;
+
+            let start = arms.index;
+            if arms.peek() == Some("{") {
+                arms.skip_group()?;
+            } else {
+                while !matches!(arms.peek(), Some(",") | None) {
+                    if matches!(arms.peek(), Some("(" | "{" | "[")) {
+                        arms.skip_group()?;
+                    } else {
+                        arms.index += 1;
+                    }
+                }
+            }
+            let arm = &arms.tokens[start..arms.index];
+            if arms.peek() == Some(",") {
+                arms.index += 1;
+            }
+
+            // Events are the last segments of the pattern's paths, like `TimerElapsed`
+            let events = events
+                .iter()
+                .filter(|event| event.chars().next().is_some_and(|char| char.is_uppercase()));
+            let targets = arm
+                .windows(5)
+                .filter(|window| window[..4] == ["Transition", "(", "State", "::"])
+                .map(|window| window[4].clone())
+                .collect::<Vec<_>>();
+
+            for event in events {
+                for target in &targets {
+                    transitions.push((event.clone(), target.clone()));
+                }
+            }
+        }
+    }
+
+    Ok(transitions)
+}
+
+/// Rust source split into identifiers, string literals, and punctuation, without comments
+struct Tokens {
+    tokens: Vec<String>,
+    index: usize,
+}
+
+impl Tokens {
+    fn new(source: &str) -> Result<Self, FsmImportError> {
+        let mut tokens = Vec::new();
+        let mut rest = source;
+
+        while let Some(char) = rest.chars().next() {
+            if char.is_whitespace() {
+                rest = &rest[char.len_utf8()..];
+            } else if let Some(comment) = rest.strip_prefix("//") {
+                rest = comment.find('\n').map_or("", |end| &comment[end..]);
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                let end = comment.find("*/").ok_or(FsmImportError::Unclosed)?;
+                rest = &comment[end + 2..];
+            } else if let Some(string) = rest.strip_prefix('"') {
+                let end = string.find('"').ok_or(FsmImportError::Unclosed)?;
+                tokens.push(rest[..end + 2].to_string());
+                rest = &string[end + 1..];
+            } else if char == '\'' && rest.chars().nth(2) == Some('\'') {
+                // A character literal, which may be a delimiter
+                let end = rest
+                    .char_indices()
+                    .nth(3)
+                    .map_or(rest.len(), |(end, _)| end);
+                tokens.push(rest[..end].to_string());
+                rest = &rest[end..];
+            } else if char.is_alphanumeric() || char == '_' {
+                let end = rest
+                    .find(|char: char| !char.is_alphanumeric() && char != '_')
+                    .unwrap_or(rest.len());
+                tokens.push(rest[..end].to_string());
+                rest = &rest[end..];
+            } else if rest.starts_with("=>") || rest.starts_with("::") || rest.starts_with("->") {
+                tokens.push(rest[..2].to_string());
+                rest = &rest[2..];
+            } else {
+                tokens.push(char.to_string());
+                rest = &rest[char.len_utf8()..];
+            }
+        }
+
+        Ok(Self { tokens, index: 0 })
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.index).map(String::as_str)
+    }
+
+    fn next_token(&mut self) -> Option<&str> {
+        self.index += 1;
+        self.tokens.get(self.index - 1).map(String::as_str)
+    }
+
+    fn position(&self, token: &str) -> Option<usize> {
+        self.position_from(token, 0)
+    }
+
+    fn position_from(&self, token: &str, start: usize) -> Option<usize> {
+        self.tokens
+            .get(start..)?
+            .iter()
+            .position(|other| other == token)
+            .map(|index| index + start)
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<(), FsmImportError> {
+        match self.next_token() {
+            Some(found) if found == token => Ok(()),
+            found => Err(unexpected(token, found)),
+        }
+    }
+
+    fn ident(&mut self) -> Result<&str, FsmImportError> {
+        match self.next_token() {
+            Some(found)
+                if found
+                    .chars()
+                    .all(|char| char.is_alphanumeric() || char == '_') =>
+            {
+                Ok(&self.tokens[self.index - 1])
+            }
+            found => Err(unexpected("an identifier", found)),
+        }
+    }
+
+    /// Skips a bracketed, braced, or parenthesized group, starting at its opening delimiter
+    fn skip_group(&mut self) -> Result<(), FsmImportError> {
+        let mut depth = 0;
+        while let Some(token) = self.next_token() {
+            match token {
+                "(" | "{" | "[" => depth += 1,
+                ")" | "}" | "]" => depth -= 1,
+                _ if depth == 0 => return Err(unexpected("`(`, `{`, or `[`", Some(token))),
+                _ => (),
+            }
+
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+
+        Err(FsmImportError::Unclosed)
+    }
+
+    /// Skips a `rust-fsm` transition's output, like `[SetupTimer]`, if there is one
+    fn skip_output(&mut self) -> Result<(), FsmImportError> {
+        if self.peek() == Some("[") {
+            self.skip_group()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn unexpected(expected: &'static str, found: Option<&str>) -> FsmImportError {
+    FsmImportError::Unexpected {
+        expected,
+        found: found.map(str::to_string),
+    }
+}
+
+/// Converts a `snake_case` name to `UpperCamelCase`
+fn upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(asset: &StateMachineAsset) -> Vec<(&str, &str, &str)> {
+        asset
+            .transitions
+            .iter()
+            .map(|transition| (&*transition.from, &*transition.trigger, &*transition.to))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_rust_fsm() {
+        let asset = StateMachineAsset::from_rust_fsm(
+            r#"state_machine! {
+                derive(Debug)
+                repr_c(true)
+                CircuitBreaker(Closed)
+
+                // Comments are skipped
+                Closed(Unsuccessful) => Open [SetupTimer],
+                Open(TimerTriggered) => HalfOpen,
+                HalfOpen => {
+                    Successful => Closed,
+                    Unsuccessful => Open [SetupTimer]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(asset.states, ["Closed", "Open", "HalfOpen"]);
+        assert_eq!(
+            names(&asset),
+            [
+                ("Closed", "Unsuccessful", "Open"),
+                ("Open", "TimerTriggered", "HalfOpen"),
+                ("HalfOpen", "Successful", "Closed"),
+                ("HalfOpen", "Unsuccessful", "Open"),
+            ],
+        );
+
+        assert_eq!(
+            StateMachineAsset::from_rust_fsm("Closed(Unsuccessful) =>").unwrap_err(),
+            FsmImportError::Unexpected {
+                expected: "an identifier",
+                found: None,
+            },
+        );
+    }
+
+    #[test]
+    fn test_from_statig() {
+        let asset = StateMachineAsset::from_statig(
+            r#"#[state_machine(initial = "State::led_on()")]
+            impl Blinky {
+                #[state(superstate = "blinking")]
+                fn led_on(event: &Event) -> Response<State> {
+                    match event {
+                        Event::TimerElapsed => Transition(State::led_off()),
+                        _ => Super,
+                    }
+                }
+
+                #[state(superstate = "blinking")]
+                fn led_off(event: &Event) -> Response<State> {
+                    match event {
+                        Event::TimerElapsed => Transition(State::led_on()),
+                        _ => Super,
+                    }
+                }
+
+                #[superstate]
+                fn blinking(event: &Event) -> Response<State> {
+                    match event {
+                        Event::ButtonPressed => Transition(State::not_blinking()),
+                        _ => Super,
+                    }
+                }
+
+                #[state]
+                async fn not_blinking(&mut self, event: &Event) -> Response<State> {
+                    match event {
+                        Event::ButtonPressed | Event::Reset { .. } => {
+                            self.count += 1;
+                            Transition(State::led_on())
+                        }
+                        _ => Super,
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(asset.states, ["LedOn", "LedOff", "NotBlinking"]);
+        assert_eq!(
+            names(&asset),
+            [
+                ("LedOn", "TimerElapsed", "LedOff"),
+                ("LedOn", "ButtonPressed", "NotBlinking"),
+                ("LedOff", "TimerElapsed", "LedOn"),
+                ("LedOff", "ButtonPressed", "NotBlinking"),
+                ("NotBlinking", "ButtonPressed", "LedOn"),
+                ("NotBlinking", "Reset", "LedOn"),
+            ],
+        );
+    }
+}
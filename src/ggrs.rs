@@ -0,0 +1,82 @@
+//! Rollback for state machines with `bevy_ggrs`. Enabled by the `ggrs` feature. See
+//! [`ggrs_machine_plugin`].
+
+use bevy_ggrs::{GgrsApp, GgrsSchedule};
+
+use crate::{machine::restore_snapshots, prelude::*, set::StateSet};
+
+/// Lets [`StateMachine`]s participate in `bevy_ggrs` rollback. Add it along with
+/// `StateMachinePlugin::in_schedule(GgrsSchedule)`, so machines transition as part of the rollback
+/// simulation. Each machine's runtime data is saved and rolled back as its [`MachineSnapshot`], and
+/// included in `bevy_ggrs`'s checksums, so machines that diverge between peers are detected as
+/// desyncs. After a rollback, each machine is restored from its snapshot before it transitions.
+///
+/// Add [`ggrs_state_plugin`] for each state type too, so the entity's states are rolled back along
+/// with its machine, and register the states in the [`AppTypeRegistry`] with
+/// `#[reflect(Component, Default)]`. Triggers' own data, like the timers of
+/// [`StateMachine::trans_after`], isn't rolled back, so keep data that triggers depend on in rolled
+/// back components, and use [`StateMachine::set_seed`] for random transitions.
+pub fn ggrs_machine_plugin(app: &mut App) {
+    app.rollback_component_with_clone::<MachineSnapshot>()
+        .checksum_component_with_hash::<MachineSnapshot>()
+        .add_systems(
+            GgrsSchedule,
+            // Snapshots only differ from their machines if they were rolled back
+            restore_snapshots::<()>.before(StateSet::Transition),
+        );
+}
+
+/// Rolls back the state `S` with `bevy_ggrs`. Add it along with [`ggrs_machine_plugin`], once for
+/// each state type.
+pub fn ggrs_state_plugin<S: Clone + Component>(app: &mut App) {
+    app.rollback_component_with_clone::<S>();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component, Default, Reflect)]
+    #[reflect(Component, Default)]
+    struct StateOne;
+    #[derive(Clone, Component, Default, Reflect)]
+    #[reflect(Component, Default)]
+    struct StateTwo;
+
+    #[derive(Resource)]
+    struct SomeResource;
+
+    #[test]
+    fn test_restore_rolled_back_machines() {
+        let mut app = App::new();
+        app.init_resource::<AppTypeRegistry>()
+            .register_type::<StateOne>()
+            .register_type::<StateTwo>()
+            .add_systems(Update, (restore_snapshots::<()>, transition).chain());
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(resource_exists::<SomeResource>(), StateTwo);
+        let entity = app.world.spawn((machine, StateOne)).id();
+        app.update();
+        let saved = app.world.get::<StateMachine>(entity).unwrap().snapshot();
+        app.world.entity_mut(entity).insert(saved.clone());
+
+        app.insert_resource(SomeResource);
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        // Roll back the entity to before it transitioned
+        app.world.remove_resource::<SomeResource>();
+        app.world
+            .entity_mut(entity)
+            .remove::<StateTwo>()
+            .insert(StateOne);
+        app.update();
+        assert_eq!(
+            app.world.get::<StateMachine>(entity).unwrap().snapshot(),
+            saved
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! `bevy-inspector-egui` UI for state machines. Enabled by the `inspector` feature. See
+//! [`state_machine_ui`].
+
+use std::any::TypeId;
+
+use bevy::{reflect::std_traits::ReflectDefault, utils::get_short_name};
+use bevy_inspector_egui::{egui, reflect_inspector::ui_for_value};
+
+use crate::prelude::*;
+
+/// Shows the [`StateMachine`] of the given entity in an egui UI, such as an inspector window built
+/// with `bevy_inspector_egui::bevy_inspector`. Shows the current state, with its fields editable if
+/// it's registered in the [`AppTypeRegistry`] with `#[reflect(Component)]`. Lists the transitions
+/// that apply in the current state, with whether each trigger occurred when it was last checked
+/// (see [`TransitionInfo::last_result`]), and buttons that force each transition's state (see
+/// [`StateMachine::force_state_reflect`]) if it's also registered with `#[reflect(Default)]`.
+pub fn state_machine_ui(world: &mut World, entity: Entity, ui: &mut egui::Ui) {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+
+    let Some(machine) = world.get::<StateMachine>(entity) else {
+        ui.label("No state machine");
+        return;
+    };
+
+    let current = machine.current_state_id();
+    let any = TypeId::of::<AnyState>();
+    let transitions = machine
+        .transitions()
+        .filter(|transition| Some(transition.from) == current || transition.from == any)
+        .map(|transition| {
+            (
+                get_short_name(transition.from_name),
+                get_short_name(transition.trigger_name),
+                transition.to,
+                transition.to_name.map(get_short_name),
+                transition.last_result,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(name) = machine.name() {
+        ui.label(format!("Name: {name}"));
+    }
+    ui.label(format!(
+        "Current state: {}",
+        machine
+            .current_state_name()
+            .map_or("unknown".to_string(), get_short_name),
+    ));
+
+    if let Some(component) =
+        current.and_then(|current| registry.get_type_data::<ReflectComponent>(current))
+    {
+        if let Some(mut state) = component.reflect_mut(&mut world.entity_mut(entity)) {
+            if ui_for_value(state.bypass_change_detection(), ui, &registry) {
+                state.set_changed();
+            }
+        }
+    }
+
+    let mut force = None;
+    egui::Grid::new(("state_machine_transitions", entity))
+        .striped(true)
+        .show(ui, |ui| {
+            for header in ["From", "Trigger", "To", "Last result", ""] {
+                ui.strong(header);
+            }
+            ui.end_row();
+
+            for (from, trigger, to, to_name, last_result) in &transitions {
+                ui.label(from.as_str());
+                ui.label(trigger.as_str());
+                ui.label(to_name.as_deref().unwrap_or("?"));
+                ui.label(match last_result {
+                    Some(true) => "occurred",
+                    Some(false) => "didn't occur",
+                    None => "not checked",
+                });
+
+                let default = to.and_then(|to| {
+                    registry.get_type_data::<ReflectComponent>(to)?;
+                    registry.get_type_data::<ReflectDefault>(to)
+                });
+                if ui
+                    .add_enabled(default.is_some(), egui::Button::new("Force"))
+                    .clicked()
+                {
+                    force = default.map(ReflectDefault::default);
+                }
+                ui.end_row();
+            }
+        });
+
+    if let Some(state) = force {
+        if let Some(mut machine) = world.get_mut::<StateMachine>(entity) {
+            machine.force_state_reflect(state);
+        }
+    }
+}
@@ -3,29 +3,123 @@
 
 #![warn(missing_docs)]
 
+// Lets the derive macros refer to `::seldom_state` within this crate
+extern crate self as seldom_state;
+
+#[cfg(feature = "animation")]
+mod animation;
+#[cfg(feature = "assets")]
+mod asset;
+mod authority;
+mod blend;
+#[cfg(feature = "big_brain")]
+mod brain;
+#[cfg(feature = "debug_panel")]
+mod debug_panel;
+mod diagnostics;
+#[cfg(feature = "editor")]
+mod editor;
+mod export;
+#[cfg(feature = "fsm_import")]
+mod fsm_import;
+#[cfg(feature = "ggrs")]
+mod ggrs;
+#[cfg(feature = "inspector")]
+mod inspector;
 mod machine;
+#[cfg(feature = "debug_overlay")]
+mod overlay;
+#[cfg(feature = "pixel")]
+mod pixel;
+#[cfg(feature = "lightyear")]
+mod prediction;
+mod registry;
+#[cfg(feature = "replicon")]
+mod replicon;
+mod schedule;
+#[cfg(any(feature = "scripting_lua", feature = "scripting_rhai"))]
+mod scripting;
+#[cfg(feature = "scxml")]
+mod scxml;
 pub mod set;
 mod state;
+#[cfg(feature = "behavior_tree")]
+mod tree;
 pub mod trigger;
+#[cfg(feature = "yarnspinner")]
+mod yarn;
 
+#[cfg(feature = "animation")]
+use animation::animation_plugin;
+#[cfg(feature = "assets")]
+use asset::asset_plugin;
+use authority::authority_plugin;
+use bevy::ecs::schedule::{BoxedScheduleLabel, ScheduleLabel};
+use blend::blend_plugin;
 use machine::machine_plugin;
+#[cfg(feature = "snapshot")]
+use machine::snapshot_plugin;
+#[cfg(feature = "pixel")]
+use pixel::pixel_plugin;
 use prelude::*;
+use registry::registry_plugin;
 use trigger::trigger_plugin;
 
 /// Add to your app to use this crate
-#[derive(Debug, Default)]
-pub struct StateMachinePlugin;
+#[derive(Debug)]
+pub struct StateMachinePlugin {
+    schedule: BoxedScheduleLabel,
+}
+
+impl Default for StateMachinePlugin {
+    fn default() -> Self {
+        Self::in_schedule(PostUpdate)
+    }
+}
+
+impl StateMachinePlugin {
+    /// Runs state machines, and removes [`Done`] markers, in the given schedule instead of
+    /// `PostUpdate`. Use `FixedUpdate` for machines that should stay in step with fixed-timestep
+    /// gameplay, such as physics. Add [`labeled_machine_plugin`]s and [`state_schedules_plugin`]s
+    /// after this plugin, so they use the same schedule.
+    pub fn in_schedule(schedule: impl ScheduleLabel) -> Self {
+        Self {
+            schedule: Box::new(schedule),
+        }
+    }
+}
 
 impl Plugin for StateMachinePlugin {
     fn build(&self, app: &mut App) {
-        app.fn_plugin(state_machine_plugin);
+        machine_plugin(app, self.schedule.dyn_clone());
+        trigger_plugin(app, self.schedule.dyn_clone());
+        authority_plugin(app, self.schedule.dyn_clone());
+        blend_plugin(app, self.schedule.dyn_clone());
+        app.fn_plugin(registry_plugin);
+
+        #[cfg(feature = "snapshot")]
+        snapshot_plugin(app, self.schedule.dyn_clone());
+
+        #[cfg(feature = "assets")]
+        asset_plugin(app, self.schedule.dyn_clone());
+
+        #[cfg(feature = "animation")]
+        app.fn_plugin(animation_plugin);
+
+        #[cfg(feature = "pixel")]
+        app.fn_plugin(pixel_plugin);
     }
 }
 
-/// Function called by [`StateMachinePlugin`]. You may instead call it directly or use
+/// Function that builds the default [`StateMachinePlugin`]. You may instead call it directly or use
 /// `seldom_fn_plugin`, which is another crate I maintain.
 pub fn state_machine_plugin(app: &mut App) {
-    app.fn_plugin(machine_plugin).fn_plugin(trigger_plugin);
+    StateMachinePlugin::default().build(app);
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use bevy;
 }
 
 /// Module for convenient imports. Use with `use seldom_state::prelude::*;`.
@@ -35,21 +129,80 @@ pub mod prelude {
     pub(crate) use leafwing_input_manager::prelude::*;
     pub(crate) use seldom_fn_plugin::FnPluginExt;
 
+    #[cfg(feature = "animation")]
+    pub use crate::animation::StateAnimations;
+    #[cfg(feature = "assets")]
+    pub use crate::asset::{
+        MachineAsset, MachineAssetRegistry, StateMachineAsset, TransitionAsset,
+    };
+    #[cfg(feature = "big_brain")]
+    pub use crate::brain::{big_brain_machine_plugin, scorer, MachineAction};
+    #[cfg(feature = "debug_panel")]
+    pub use crate::debug_panel::{StateMachineDebug, StateMachineDebugPlugin};
+    #[cfg(feature = "editor")]
+    pub use crate::editor::{StateMachineEditorState, StateMachineEditorWindow};
+    #[cfg(feature = "fsm_import")]
+    pub use crate::fsm_import::FsmImportError;
+    #[cfg(feature = "ggrs")]
+    pub use crate::ggrs::{ggrs_machine_plugin, ggrs_state_plugin};
+    #[cfg(feature = "inspector")]
+    pub use crate::inspector::state_machine_ui;
+    #[cfg(feature = "debug_overlay")]
+    pub use crate::overlay::{StateLabel, StateLabelPlugin};
+    #[cfg(feature = "pixel")]
+    pub use crate::pixel::StatePxAnimations;
+    #[cfg(feature = "lightyear")]
+    pub use crate::prediction::{MachineHistory, PredictedMachinePlugin};
+    #[cfg(feature = "replicon")]
+    pub use crate::replicon::{replicated_state_id, ReplicatedState, StateReplicationPlugin};
+    #[cfg(feature = "scripting_lua")]
+    pub use crate::scripting::StateMachineLuaApi;
+    #[cfg(feature = "scripting_rhai")]
+    pub use crate::scripting::StateMachineRhaiApi;
+    #[cfg(any(feature = "scripting_lua", feature = "scripting_rhai"))]
+    pub use crate::scripting::{script_current_state, script_request_transition, script_set_done};
+    #[cfg(feature = "scxml")]
+    pub use crate::scxml::ScxmlError;
+    #[cfg(feature = "behavior_tree")]
+    pub use crate::tree::BehaviorNode;
     #[cfg(feature = "leafwing_input")]
     pub use crate::trigger::{
         action_data, axis_pair, axis_pair_length_bounds, axis_pair_max_length,
         axis_pair_min_length, axis_pair_rotation_bounds, axis_pair_unbounded, clamped_axis_pair,
         clamped_axis_pair_length_bounds, clamped_axis_pair_max_length,
-        clamped_axis_pair_min_length, clamped_axis_pair_rotation_bounds,
+        clamped_axis_pair_min_length, clamped_axis_pair_rotation_bounds, clamped_axis_pair_snapped,
         clamped_axis_pair_unbounded, clamped_value, clamped_value_max, clamped_value_min,
-        clamped_value_unbounded, just_pressed, just_released, pressed, value, value_max, value_min,
-        value_unbounded,
+        clamped_value_unbounded, just_pressed, just_released, pressed, released_for, value,
+        value_delta, value_max, value_min, value_unbounded, ActionStateSource,
     };
+    #[cfg(feature = "yarnspinner")]
+    pub use crate::yarn::{yarn_node_completed, yarn_node_started};
     pub use crate::{
-        machine::StateMachine,
-        state::{AnyState, EntityState},
-        state_machine_plugin,
-        trigger::{always, done, on_event, Done, IntoTrigger, Never, Trigger},
+        authority::AuthoritativeState,
+        blend::StateWeights,
+        diagnostics::StateMachineDiagnosticsPlugin,
+        export::{log_machine_graphs, log_machines_json, machines_to_json},
+        machine::{
+            any_in_state, in_entity_state, labeled_machine_plugin, CurrentState, CurrentStateItem,
+            Desync, DeterministicMachines, DynamicState, History, LabeledMachine, MachineBudget,
+            MachineBuilder, MachineDefinition, MachineDefinitions, MachineInterval, MachineLabel,
+            MachineSnapshot, Paused, SceneMachine, Selection, SelfTransition, StateMachine,
+            StateMachineCommandsExt, StateMachines, StateMachinesEnabled, TransitionHistory,
+            TransitionInfo, TransitionRecord, TriggerContext, UtilityCandidates, ValidationIssue,
+        },
+        registry::{
+            DynStateBuilder, DynTrigger, RegistryError, StateBuilderRegistry, TriggerRegistry,
+        },
+        schedule::{
+            state_schedules_plugin, EnteredEntities, ExitedEntities, OnEnterState, OnExitState,
+        },
+        state::{AnyState, EntityState, EntityStates, MachineStates, StateEntered, StateExited},
+        state_machine, state_machine_plugin,
+        trigger::{
+            always, done, next_global_state, on_event, requested, Done, IntoTrigger, Never,
+            TransitionRequest, Trigger,
+        },
         StateMachinePlugin,
     };
+    pub use seldom_state_macros::MachineStates;
 }
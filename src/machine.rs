@@ -1,33 +1,162 @@
+mod dynamic;
+mod snapshot;
+#[cfg(feature = "trait_query")]
+mod trait_query;
+
+pub use dynamic::DynamicState;
+pub use snapshot::MachineSnapshot;
+#[cfg(feature = "snapshot")]
+pub(crate) use snapshot::{restore_snapshots, snapshot_plugin};
+
 use std::{
     any::{type_name, Any, TypeId},
-    fmt::Debug,
+    collections::VecDeque,
+    fmt::{Debug, Display},
     marker::PhantomData,
+    sync::Arc,
+    time::Duration,
 };
 
 use bevy::{
-    ecs::system::{Command, EntityCommands, SystemState},
+    ecs::{
+        component::Tick,
+        query::WorldQuery,
+        schedule::BoxedScheduleLabel,
+        system::{Command, EntityCommands, ReadOnlySystem, SystemParam, SystemState},
+    },
+    log::Level,
+    reflect::TypeRegistry,
     tasks::{ComputeTaskPool, ParallelSliceMut},
-    utils::HashMap,
+    utils::{HashMap, HashSet, Instant},
 };
 
 use crate::{
     prelude::*,
     set::StateSet,
-    state::{Insert, OnEvent},
-    trigger::{IntoTrigger, TriggerOut},
+    state::{
+        EntityStates, Insert, OnEvent, ReflectInsert, StateEntered, StateExited, WithCommands,
+    },
+    trigger::{collect_done_machines, remove_done_markers, IntoTrigger, TriggerIn, TriggerOut},
 };
 
-pub(crate) fn machine_plugin(app: &mut App) {
-    app.add_systems(PostUpdate, transition.in_set(StateSet::Transition));
+pub(crate) fn machine_plugin(app: &mut App, schedule: BoxedScheduleLabel) {
+    let enabled = |enabled: Res<StateMachinesEnabled>| enabled.0;
+
+    app.insert_resource(MachineSchedule(schedule.dyn_clone()))
+        .init_resource::<StateMachinesEnabled>()
+        .init_resource::<MachineDefinitions>()
+        .register_type::<SceneMachine>()
+        .configure_sets(
+            schedule.dyn_clone(),
+            (StateSet::Transition, StateSet::RemoveDoneMarkers).run_if(enabled),
+        )
+        .configure_sets(
+            FixedUpdate,
+            (StateSet::Transition, StateSet::RemoveDoneMarkers).run_if(enabled),
+        )
+        .add_systems(
+            schedule.dyn_clone(),
+            instantiate_scene_machines.before(StateSet::Transition),
+        )
+        .add_systems(schedule, transition.in_set(StateSet::Transition))
+        .add_systems(
+            FixedUpdate,
+            transition_machines::<StateMachine, true>.in_set(StateSet::Transition),
+        );
+}
+
+/// What the transition systems did since the diagnostics were last recorded. Only present with
+/// [`StateMachineDiagnosticsPlugin`](crate::diagnostics::StateMachineDiagnosticsPlugin).
+#[derive(Debug, Default, Resource)]
+pub(crate) struct MachineStats {
+    /// Transitions taken
+    pub transitions: u32,
+    /// Machines evaluated
+    pub machines: u32,
+    /// Triggers checked
+    pub triggers: u32,
+    /// Time spent in the transition systems
+    pub time: Duration,
 }
 
+/// The schedule that state machines run in. See [`StateMachinePlugin::in_schedule`].
+#[derive(Resource)]
+pub(crate) struct MachineSchedule(pub(crate) BoxedScheduleLabel);
+
 /// Performs a transition. We have a trait for this so we can erase [`TransitionImpl`]'s generics.
 trait Transition: Debug + Send + Sync + 'static {
+    /// Describes the transition's trigger, for diagnostics
+    fn trigger_name(&self) -> &'static str;
     /// Called before any call to `check`
     fn init(&mut self, world: &mut World);
     /// Checks whether the transition should be taken. `entity` is the entity that contains the
-    /// state machine.
-    fn check(&mut self, world: &World, entity: Entity) -> Option<(Box<dyn Insert>, TypeId)>;
+    /// state machine, and `context` is the entity that the trigger is checked on. See
+    /// [`StateMachine::set_context`].
+    fn check(&mut self, world: &World, entity: Entity, context: Entity) -> Option<NextState>;
+
+    /// States that the transition may go to, if they aren't known when it's added. See
+    /// [`StateMachine::validate`].
+    fn candidates(&self) -> Vec<TypeId> {
+        default()
+    }
+
+    /// Identifies the transition's type, to find duplicates. See [`StateMachine::validate`].
+    fn transition_type(&self) -> TypeId {
+        TypeId::of::<Self>()
+    }
+
+    /// Inserts the state that the transition stores, if `check` returns [`NextState::Stored`]
+    fn insert_stored(&self, _entity: &mut EntityCommands) {
+        unreachable!("only transitions that store their state return `NextState::Stored`")
+    }
+
+    /// Gets the state that the transition stores as a `Reflect`, if `check` returns
+    /// [`NextState::Stored`] and the state's type is registered
+    fn reflect_stored<'a>(&'a self, _registry: &TypeRegistry) -> Option<&'a dyn Reflect> {
+        None
+    }
+}
+
+/// Where a transition that is taken goes. States that are built when the transition is taken are
+/// boxed, which doesn't allocate if they're zero-sized, like most marker states. Transitions that
+/// always go to the same state return [`NextState::Stored`] instead, so they don't allocate.
+enum NextState {
+    /// Go to the given state
+    Go(Box<dyn Insert>, TypeId),
+    /// Go to the state of the given type that the transition stores, cloning it straight into the
+    /// entity's commands. See [`Transition::insert_stored`].
+    Stored(TypeId),
+    /// Go to the given state, pushing the previous state onto the stack
+    Push(Box<dyn Insert>, TypeId),
+    /// Go to the state on top of the stack
+    Pop,
+}
+
+/// The state that a transition that's being taken inserts
+enum NextInsert<'a> {
+    /// A state that was built when the transition was taken
+    Boxed(Box<dyn Insert>),
+    /// The state that's stored in the transition that was taken. See [`NextState::Stored`].
+    Stored(&'a dyn Transition),
+}
+
+impl NextInsert<'_> {
+    fn insert(self, entity: &mut EntityCommands) {
+        match self {
+            Self::Boxed(insert) => {
+                insert.insert(entity);
+            }
+            Self::Stored(transition) => transition.insert_stored(entity),
+        }
+    }
+
+    /// Gets the state as a `Reflect`, if its type is registered
+    fn reflect<'a>(&'a self, registry: &TypeRegistry) -> Option<&'a dyn Reflect> {
+        match self {
+            Self::Boxed(insert) => insert.reflect(registry),
+            Self::Stored(transition) => transition.reflect_stored(registry),
+        }
+    }
 }
 
 /// An edge in the state machine. The type parameters are the [`Trigger`] that causes this
@@ -45,6 +174,8 @@ where
 {
     pub trigger: Trig,
     pub builder: Build,
+    /// Whether to push the previous state onto the stack
+    pub push: bool,
     phantom: PhantomData<Prev>,
 }
 
@@ -59,6 +190,7 @@ where
         f.debug_struct("TransitionImpl")
             .field("trigger", &self.trigger.type_id())
             .field("builder", &self.builder.type_id())
+            .field("push", &self.push)
             .field("phantom", &self.phantom)
             .finish()
     }
@@ -71,17 +203,26 @@ where
     Build: Fn(&Prev, <<Trig as Trigger>::Out as TriggerOut>::Ok) -> Option<Next> + Send + Sync,
     Next: Component + EntityState,
 {
+    fn trigger_name(&self) -> &'static str {
+        type_name::<Trig>()
+    }
+
     fn init(&mut self, world: &mut World) {
         self.trigger.init(world);
     }
 
-    fn check(&mut self, world: &World, entity: Entity) -> Option<(Box<dyn Insert>, TypeId)> {
-        let Ok(res) = self.trigger.check(entity, world).into_result() else {
+    fn check(&mut self, world: &World, entity: Entity, context: Entity) -> Option<NextState> {
+        let Ok(res) = self.trigger.check(context, world).into_result() else {
             return None;
         };
 
-        (self.builder)(Prev::from_entity(entity, world), res)
-            .map(|state| (Box::new(state) as Box<dyn Insert>, TypeId::of::<Next>()))
+        (self.builder)(Prev::from_entity(entity, world), res).map(|state| {
+            let insert = Box::new(state) as Box<dyn Insert>;
+            match self.push {
+                true => NextState::Push(insert, TypeId::of::<Next>()),
+                false => NextState::Go(insert, TypeId::of::<Next>()),
+            }
+        })
     }
 }
 
@@ -96,369 +237,5219 @@ where
         Self {
             trigger,
             builder,
+            push: false,
             phantom: PhantomData,
         }
     }
 }
 
-/// Information about a state
-#[derive(Debug)]
-struct StateMetadata {
-    /// For debug information
-    name: String,
-    on_enter: Vec<OnEvent>,
-    on_exit: Vec<OnEvent>,
+/// A transition that always goes to the same state, like those added by [`StateMachine::trans`].
+/// Unlike [`TransitionImpl`], it doesn't get the previous state or call a builder, and it checks
+/// its trigger with [`TriggerOut::occurred`], so `bool` triggers aren't converted to a `Result`.
+struct StateTransition<Trig: Trigger, Next: Clone + Component> {
+    trigger: Trig,
+    state: Next,
 }
 
-impl StateMetadata {
-    fn new<S: EntityState>() -> Self {
-        Self {
-            name: type_name::<S>().to_owned(),
-            on_enter: default(),
-            on_exit: vec![OnEvent::Entity(Box::new(|entity: &mut EntityCommands| {
-                S::remove(entity);
-            }))],
-        }
+impl<Trig: Trigger, Next: Clone + Component> Debug for StateTransition<Trig, Next> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateTransition")
+            .field("trigger", &self.trigger.type_id())
+            .field("state", &type_name::<Next>())
+            .finish()
     }
 }
 
-/// State machine component. Entities with this component will have components (the states) added
-/// and removed based on the transitions that you add. Build one with `StateMachine::default`,
-/// `StateMachine::trans`, and other methods.
-#[derive(Component)]
-pub struct StateMachine {
-    states: HashMap<TypeId, StateMetadata>,
-    /// Each transition and the state it should apply in (or [`AnyState`]). We store the transitions
-    /// in a flat list so that we ensure we always check them in the right order; storing them in
-    /// each StateMetadata would mean that e.g. we'd have to check every AnyState trigger before any
-    /// state-specific trigger or vice versa.
-    transitions: Vec<(TypeId, Box<dyn Transition>)>,
-    /// Transitions must be initialized whenever a transition is added or a transition occurs
-    init_transitions: bool,
-    /// If true, all transitions are logged at info level
-    log_transitions: bool,
-}
-
-impl Default for StateMachine {
-    fn default() -> Self {
-        Self {
-            states: HashMap::from([(
-                TypeId::of::<AnyState>(),
-                StateMetadata {
-                    name: "AnyState".to_owned(),
-                    on_enter: vec![],
-                    on_exit: vec![],
-                },
-            )]),
-            transitions: vec![],
-            init_transitions: true,
-            log_transitions: false,
-        }
+impl<Trig: Trigger, Next: Clone + Component> Transition for StateTransition<Trig, Next> {
+    fn trigger_name(&self) -> &'static str {
+        type_name::<Trig>()
     }
-}
 
-impl StateMachine {
-    /// Registers a state. This is only necessary for states that are not used in any transitions.
-    pub fn with_state<S: Clone + Component>(mut self) -> Self {
-        self.metadata_mut::<S>();
-        self
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
     }
 
-    /// Adds a transition to the state machine. When the entity is in the state given as a
-    /// type parameter, and the given trigger occurs, it will transition to the state given as a
-    /// function parameter. Elide the `Marker` type parameter with `_`. Transitions have priority
-    /// in the order they are added.
-    pub fn trans<S: EntityState, Marker>(
-        self,
-        trigger: impl IntoTrigger<Marker>,
-        state: impl Clone + Component,
-    ) -> Self {
-        self.trans_builder(trigger, move |_: &S, _| Some(state.clone()))
+    fn check(&mut self, world: &World, _: Entity, context: Entity) -> Option<NextState> {
+        self.trigger
+            .check(context, world)
+            .occurred()
+            .then_some(NextState::Stored(TypeId::of::<Next>()))
     }
 
-    /// Get the metadata for the given state, creating it if necessary.
-    fn metadata_mut<S: EntityState>(&mut self) -> &mut StateMetadata {
-        self.states
-            .entry(TypeId::of::<S>())
-            .or_insert(StateMetadata::new::<S>())
+    fn insert_stored(&self, entity: &mut EntityCommands) {
+        entity.insert(self.state.clone());
     }
 
-    /// Adds a transition builder to the state machine. When the entity is in `Prev` state, and
-    /// `Trig` occurs, the given builder will be run on `Trig::Ok`. If the builder returns
-    /// `Some(Next)`, the machine will transition to that `Next` state.
-    pub fn trans_builder<
-        Prev: EntityState,
-        Trig: IntoTrigger<Marker>,
-        Next: Clone + Component,
-        Marker,
-    >(
-        mut self,
-        trigger: Trig,
-        builder: impl 'static
-            + Clone
-            + Fn(&Prev, <<Trig::Trigger as Trigger>::Out as TriggerOut>::Ok) -> Option<Next>
-            + Send
-            + Sync,
-    ) -> Self {
-        self.metadata_mut::<Prev>();
-        self.metadata_mut::<Next>();
-        let transition = TransitionImpl::<_, Prev, _, _>::new(trigger.into_trigger(), builder);
-        self.transitions.push((
-            TypeId::of::<Prev>(),
-            Box::new(transition) as Box<dyn Transition>,
-        ));
-        self.init_transitions = true;
-        self
+    fn reflect_stored<'a>(&'a self, registry: &TypeRegistry) -> Option<&'a dyn Reflect> {
+        Insert::reflect(&self.state, registry)
     }
+}
 
-    /// Adds an on-enter event to the state machine. Whenever the state machine transitions into the
-    /// given state, it will run the event.
-    pub fn on_enter<S: EntityState>(
-        mut self,
-        on_enter: impl 'static + Fn(&mut EntityCommands) + Send + Sync,
-    ) -> Self {
-        self.metadata_mut::<S>()
-            .on_enter
-            .push(OnEvent::Entity(Box::new(on_enter)));
+/// A transition that goes to the state of a [`TransitionRequest`] for the entity. Unlike other
+/// transitions, its trigger is checked on the entity that contains the state machine, regardless
+/// of the machine's context, since requests are addressed to that entity. See
+/// [`StateMachine::trans_on_request`].
+struct RequestTransition<Trig: Trigger<Out = Option<S>>, S: Clone + Component> {
+    trigger: Trig,
+    phantom: PhantomData<S>,
+}
 
-        self
+impl<Trig: Trigger<Out = Option<S>>, S: Clone + Component> Debug for RequestTransition<Trig, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestTransition")
+            .field("state", &type_name::<S>())
+            .finish()
     }
+}
 
-    /// Adds an on-exit event to the state machine. Whenever the state machine transitions from the
-    /// given state, it will run the event.
-    pub fn on_exit<S: EntityState>(
-        mut self,
-        on_exit: impl 'static + Fn(&mut EntityCommands) + Send + Sync,
-    ) -> Self {
-        self.metadata_mut::<S>()
-            .on_exit
-            .push(OnEvent::Entity(Box::new(on_exit)));
-
-        self
+impl<Trig: Trigger<Out = Option<S>>, S: Clone + Component> Transition
+    for RequestTransition<Trig, S>
+{
+    fn trigger_name(&self) -> &'static str {
+        type_name::<Trig>()
     }
 
-    /// Adds an on-enter command to the state machine. Whenever the state machine transitions into
-    /// the given state, it will run the command.
-    pub fn command_on_enter<S: EntityState>(
-        mut self,
-        command: impl Clone + Command + Sync,
-    ) -> Self {
-        self.metadata_mut::<S>()
-            .on_enter
-            .push(OnEvent::Command(Box::new(command)));
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
+    }
 
-        self
+    fn check(&mut self, world: &World, entity: Entity, _: Entity) -> Option<NextState> {
+        let state = self.trigger.check(entity, world)?;
+        Some(NextState::Go(Box::new(state), TypeId::of::<S>()))
     }
+}
 
-    /// Adds an on-exit command to the state machine. Whenever the state machine transitions from
-    /// the given state, it will run the command.
-    pub fn command_on_exit<S: EntityState>(mut self, command: impl Clone + Command + Sync) -> Self {
-        self.metadata_mut::<S>()
-            .on_exit
-            .push(OnEvent::Command(Box::new(command)));
+/// A transition between states that are only known at runtime, such as those of a
+/// `StateMachineAsset`. Like [`StateTransition`], it always goes to the same state, but the trigger
+/// and state are type-erased.
+struct DynTransition<Trig: Trigger> {
+    trigger: Trig,
+    trigger_name: &'static str,
+    to: TypeId,
+    state: Box<dyn Fn() -> Box<dyn Insert> + Send + Sync>,
+}
 
-        self
+impl<Trig: Trigger> Debug for DynTransition<Trig> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynTransition")
+            .field("trigger", &self.trigger_name)
+            .field("to", &self.to)
+            .finish()
     }
+}
 
-    /// Sets whether transitions are logged to the console
-    pub fn set_trans_logging(mut self, log_transitions: bool) -> Self {
-        self.log_transitions = log_transitions;
-        self
+impl<Trig: Trigger> Transition for DynTransition<Trig> {
+    fn trigger_name(&self) -> &'static str {
+        self.trigger_name
     }
 
-    /// Initialize all transitions. Must be executed before `run`. This is separate because `run` is
-    /// parallelizable (takes a `&World`) but this isn't (takes a `&mut World`).
-    fn init_transitions(&mut self, world: &mut World) {
-        if !self.init_transitions {
-            return;
-        }
-
-        for (_, transition) in &mut self.transitions {
-            transition.init(world);
-        }
-
-        self.init_transitions = false;
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
     }
 
-    /// Runs all transitions until one is actually taken. If one is taken, logs the transition and
-    /// runs `on_enter/on_exit` triggers.
-    fn run(&mut self, world: &World, entity: Entity, commands: &mut Commands) {
-        let mut states = self.states.keys();
-        let current = states.find(|&&state| world.entity(entity).contains_type_id(state));
-
-        let Some(&current) = current else {
-            panic!("Entity {entity:?} is in no state");
-        };
-
-        let from = &self.states[&current];
-        if let Some(&other) = states.find(|&&state| world.entity(entity).contains_type_id(state)) {
-            let state = &from.name;
-            let other = &self.states[&other].name;
-            panic!("{entity:?} is in multiple states: {state} and {other}");
-        }
-
-        let Some((insert, next_state)) = self
-            .transitions
-            .iter_mut()
-            .filter(|(type_id, _)| *type_id == current || *type_id == TypeId::of::<AnyState>())
-            .find_map(|(_, transition)| transition.check(world, entity))
-        else {
-            return;
-        };
-        let to = &self.states[&next_state];
+    fn check(&mut self, world: &World, _: Entity, context: Entity) -> Option<NextState> {
+        self.trigger
+            .check(context, world)
+            .occurred()
+            .then(|| NextState::Go((self.state)(), self.to))
+    }
+}
 
-        for event in from.on_exit.iter() {
-            event.trigger(entity, commands);
-        }
+/// A transition whose trigger and state builder are only known at runtime, constructed by a
+/// [`TriggerRegistry`] and [`StateBuilderRegistry`]. See
+/// [`StateMachine::trans_builder_registered`].
+struct DynBuilderTransition {
+    trigger: DynTrigger,
+    builder: DynStateBuilder,
+}
 
-        insert.insert(&mut commands.entity(entity));
-        for event in to.on_enter.iter() {
-            event.trigger(entity, commands);
-        }
+impl Debug for DynBuilderTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynBuilderTransition")
+            .field("trigger", &self.trigger.name())
+            .field("to", &self.builder.to)
+            .finish()
+    }
+}
 
-        if self.log_transitions {
-            info!("{entity:?} transitioned from {} to {}", from.name, to.name);
-        }
+impl Transition for DynBuilderTransition {
+    fn trigger_name(&self) -> &'static str {
+        self.trigger.name()
+    }
 
-        self.init_transitions = true;
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
     }
 
-    /// When running the transition system, we replace all StateMachines in the world with their
-    /// stub.
-    fn stub(&self) -> Self {
-        Self {
-            states: default(),
-            transitions: default(),
-            init_transitions: false,
-            log_transitions: false,
-        }
+    fn check(&mut self, world: &World, _: Entity, context: Entity) -> Option<NextState> {
+        let output = self.trigger.check(context, world)?;
+        Some(NextState::Go(
+            (self.builder.build)(&*output)?,
+            self.builder.to,
+        ))
     }
 }
 
-/// Runs all transitions on all entities.
-pub(crate) fn transition(
-    world: &mut World,
-    system_state: &mut SystemState<ParallelCommands>,
-    machine_query: &mut QueryState<(Entity, &mut StateMachine)>,
-) {
-    // Pull the machines out of the world so we can invoke mutable methods on them. The alternative
-    // would be to wrap the entire `StateMachine` in an `Arc<Mutex>`, but that would complicate the
-    // API surface and you wouldn't be able to do anything more anyway (since you'd need to lock the
-    // mutex anyway).
-    let mut borrowed_machines: Vec<(Entity, StateMachine)> = machine_query
-        .iter_mut(world)
-        .map(|(entity, mut machine)| {
-            let stub = machine.stub();
-            (entity, std::mem::replace(machine.as_mut(), stub))
-        })
-        .collect();
+/// A transition whose builder also returns commands to run on the entity. See
+/// [`StateMachine::trans_builder_with_commands`].
+struct CommandsBuilderTransition<Trig: Trigger, Prev: EntityState, Build> {
+    trigger: Trig,
+    builder: Build,
+    phantom: PhantomData<Prev>,
+}
 
-    // `world` is mutable here, since initialization requires mutating the world
-    for (_, machine) in borrowed_machines.iter_mut() {
-        machine.init_transitions(world);
+impl<Trig: Trigger, Prev: EntityState, Build: 'static> Debug
+    for CommandsBuilderTransition<Trig, Prev, Build>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandsBuilderTransition")
+            .field("trigger", &self.trigger.type_id())
+            .field("builder", &self.builder.type_id())
+            .field("phantom", &self.phantom)
+            .finish()
     }
+}
 
-    // `world` is not mutated here; the state machines are not in the world, and the Commands don't
-    // mutate until application
-    let par_commands = system_state.get(world);
-    let task_pool = ComputeTaskPool::get();
-    // chunk size of None means to automatically pick
-    borrowed_machines.par_splat_map_mut(task_pool, None, |chunk| {
-        for (entity, machine) in chunk {
-            par_commands.command_scope(|mut commands| machine.run(world, *entity, &mut commands));
-        }
-    });
+impl<Trig, Prev, Build, Next, Extra> Transition for CommandsBuilderTransition<Trig, Prev, Build>
+where
+    Trig: Trigger,
+    Prev: EntityState,
+    Build: 'static
+        + Fn(&Prev, <<Trig as Trigger>::Out as TriggerOut>::Ok) -> Option<(Next, Extra)>
+        + Send
+        + Sync,
+    Next: Clone + Component,
+    Extra: 'static + FnOnce(&mut EntityCommands) + Send + Sync,
+{
+    fn trigger_name(&self) -> &'static str {
+        type_name::<Trig>()
+    }
 
-    // put the borrowed machines back
-    for (entity, machine) in borrowed_machines {
-        *machine_query.get_mut(world, entity).unwrap().1 = machine;
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
     }
 
-    // necessary to actually *apply* the commands we've enqueued
-    system_state.apply(world);
+    fn check(&mut self, world: &World, entity: Entity, context: Entity) -> Option<NextState> {
+        let res = self.trigger.check(context, world).into_result().ok()?;
+        let (next, commands) = (self.builder)(Prev::from_entity(entity, world), res)?;
+        Some(NextState::Go(
+            Box::new(WithCommands(next, commands)),
+            TypeId::of::<Next>(),
+        ))
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// A transition whose next state is built by a system. See
+/// [`StateMachine::trans_builder_system`].
+struct SystemBuilderTransition<Trig: Trigger, Prev: EntityState, Build: ReadOnlySystem> {
+    trigger: Trig,
+    builder: Build,
+    phantom: PhantomData<Prev>,
+}
+
+impl<Trig: Trigger, Prev: EntityState, Build: ReadOnlySystem> Debug
+    for SystemBuilderTransition<Trig, Prev, Build>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SystemBuilderTransition")
+            .field("trigger", &self.trigger.type_id())
+            .field("builder", &self.builder.name())
+            .field("phantom", &self.phantom)
+            .finish()
+    }
+}
+
+impl<Trig, Prev, Build, Next> Transition for SystemBuilderTransition<Trig, Prev, Build>
+where
+    Trig: Trigger,
+    Prev: EntityState,
+    Build: ReadOnlySystem<
+        In = (Entity, <<Trig as Trigger>::Out as TriggerOut>::Ok),
+        Out = Option<Next>,
+    >,
+    Next: Clone + Component,
+{
+    fn trigger_name(&self) -> &'static str {
+        type_name::<Trig>()
+    }
+
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
+        self.builder.initialize(world);
+    }
+
+    fn check(&mut self, world: &World, entity: Entity, context: Entity) -> Option<NextState> {
+        let res = self.trigger.check(context, world).into_result().ok()?;
+        let next = self.builder.run_readonly((entity, res), world)?;
+        Some(NextState::Go(Box::new(next), TypeId::of::<Next>()))
+    }
+}
+
+/// A transition between variants of a [`MachineStates`] enum. See
+/// [`StateMachine::trans_variant`].
+struct VariantTransition<Trig: Trigger, S: MachineStates> {
+    trigger: Trig,
+    to: S,
+}
+
+impl<Trig: Trigger, S: MachineStates> Debug for VariantTransition<Trig, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VariantTransition")
+            .field("trigger", &self.trigger.type_id())
+            .field("to", &self.to.state_id())
+            .finish()
+    }
+}
+
+impl<Trig: Trigger, S: MachineStates> Transition for VariantTransition<Trig, S> {
+    fn trigger_name(&self) -> &'static str {
+        type_name::<Trig>()
+    }
+
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
+    }
+
+    fn check(&mut self, world: &World, _: Entity, context: Entity) -> Option<NextState> {
+        self.trigger
+            .check(context, world)
+            .occurred()
+            .then_some(NextState::Stored(self.to.state_id()))
+    }
+
+    fn insert_stored(&self, entity: &mut EntityCommands) {
+        self.to.insert(entity);
+    }
+}
+
+/// A transition that returns to the state on top of the stack. See [`StateMachine::trans_pop`].
+struct PopTransition<Trig: Trigger>(Trig);
+
+impl<Trig: Trigger> Debug for PopTransition<Trig> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PopTransition")
+            .field(&self.0.type_id())
+            .finish()
+    }
+}
+
+impl<Trig: Trigger> Transition for PopTransition<Trig> {
+    fn trigger_name(&self) -> &'static str {
+        type_name::<Trig>()
+    }
+
+    fn init(&mut self, world: &mut World) {
+        self.0.init(world);
+    }
+
+    fn check(&mut self, world: &World, _: Entity, context: Entity) -> Option<NextState> {
+        self.0
+            .check(context, world)
+            .occurred()
+            .then_some(NextState::Pop)
+    }
+}
+
+/// A transition to whichever candidate state scores highest. See [`StateMachine::trans_utility`].
+#[derive(Debug)]
+struct UtilityTransition {
+    /// Candidates must score above this to be chosen
+    threshold: f32,
+    candidates: UtilityCandidates,
+}
+
+impl Transition for UtilityTransition {
+    fn trigger_name(&self) -> &'static str {
+        "utility scores"
+    }
+
+    fn init(&mut self, world: &mut World) {
+        for candidate in &mut self.candidates.candidates {
+            candidate.scorer.init(world);
+        }
+    }
+
+    fn check(&mut self, world: &World, _: Entity, context: Entity) -> Option<NextState> {
+        let mut best = None;
+        let mut best_score = self.threshold;
+
+        for (i, candidate) in self.candidates.candidates.iter_mut().enumerate() {
+            let score = candidate.scorer.score(world, context);
+            if score > best_score {
+                best = Some(i);
+                best_score = score;
+            }
+        }
+
+        let candidate = &self.candidates.candidates[best?];
+        Some(NextState::Go((candidate.insert)(), candidate.state))
+    }
+
+    fn candidates(&self) -> Vec<TypeId> {
+        self.candidates
+            .candidates
+            .iter()
+            .map(|candidate| candidate.state)
+            .collect()
+    }
+}
+
+/// Scores a candidate state. We have a trait for this so we can erase [`SystemScorer`]'s generics.
+trait Scorer: Send + Sync + 'static {
+    /// Called before any call to `score`
+    fn init(&mut self, world: &mut World);
+    /// Scores the candidate for the given entity
+    fn score(&mut self, world: &World, entity: Entity) -> f32;
+}
+
+/// The scorer form of a system
+struct SystemScorer<T: ReadOnlySystem>(T);
+
+impl<T: ReadOnlySystem<Out = f32>> Scorer for SystemScorer<T>
+where
+    T::In: TriggerIn,
+{
+    fn init(&mut self, world: &mut World) {
+        self.0.initialize(world);
+    }
+
+    fn score(&mut self, world: &World, entity: Entity) -> f32 {
+        self.0.run_readonly(T::In::from_entity(entity), world)
+    }
+}
+
+/// A state that a utility transition may go to, and how it's scored
+struct UtilityCandidate {
+    scorer: Box<dyn Scorer>,
+    insert: Box<dyn Fn() -> Box<dyn Insert> + Send + Sync>,
+    state: TypeId,
+    /// Adds the state's metadata to a machine
+    register: fn(StateMachine) -> StateMachine,
+}
+
+impl Debug for UtilityCandidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UtilityCandidate")
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// Candidate states for [`StateMachine::trans_utility`], each with a system that scores it
+#[derive(Debug, Default)]
+pub struct UtilityCandidates {
+    candidates: Vec<UtilityCandidate>,
+}
+
+impl UtilityCandidates {
+    /// Creates an empty set of candidates
+    pub fn new() -> Self {
+        default()
+    }
+
+    /// Adds a candidate state. The scorer is a read-only system that returns the candidate's score,
+    /// and may take the entity as input, like a trigger. Candidates are scored in the order they
+    /// are added, and the first of tied candidates is chosen.
+    pub fn candidate<In: TriggerIn, Marker, T: IntoSystem<In, f32, Marker>, S: Clone + Component>(
+        mut self,
+        scorer: T,
+        state: S,
+    ) -> Self
+    where
+        T::System: ReadOnlySystem,
+    {
+        self.candidates.push(UtilityCandidate {
+            scorer: Box::new(SystemScorer(IntoSystem::into_system(scorer))),
+            insert: Box::new(move || Box::new(state.clone())),
+            state: TypeId::of::<S>(),
+            register: StateMachine::with_state::<S>,
+        });
+        self
+    }
+}
+
+/// Information about a state
+#[derive(Debug)]
+struct StateMetadata {
+    /// For debug information
+    name: &'static str,
+    on_enter: Vec<OnEvent>,
+    on_exit: Vec<OnEvent>,
+    /// Clones the entity's state of this type, for pushing it onto the stack
+    clone_state: fn(Entity, &World) -> Box<dyn Insert>,
+    /// Removes the entity's state of this type, for resyncing. See [`Desync::Resync`].
+    remove: fn(&mut EntityCommands),
+    /// Nested state machine that runs while the entity is in this state
+    substates: Option<Substates>,
+    /// Overrides the machine's [`Selection`] in this state
+    selection: Option<Selection>,
+}
+
+impl StateMetadata {
+    fn new<S: EntityState>() -> Self {
+        Self {
+            name: type_name::<S>(),
+            on_enter: default(),
+            on_exit: vec![OnEvent::Entity(Box::new(|entity: &mut EntityCommands| {
+                S::remove(entity);
+            }))],
+            clone_state: S::clone_from_entity,
+            remove: S::remove,
+            substates: None,
+            selection: None,
+        }
+    }
+
+    /// Exits the entity's current substate of this state, and that substate's substates,
+    /// recursively. Remembers the substate if this state has history, or if `deep` is set.
+    fn exit_substates(
+        &mut self,
+        world: &World,
+        entity: Entity,
+        commands: &mut Commands,
+        deep: bool,
+    ) {
+        let Some(substates) = &mut self.substates else {
+            return;
+        };
+
+        let Some((&state, substate)) = substates
+            .machine
+            .states
+            .iter_mut()
+            .find(|(&state, _)| world.entity(entity).contains_type_id(state))
+        else {
+            return;
+        };
+
+        if deep || substates.history != History::None {
+            substates.last = Some((state, (substate.clone_state)(entity, world)));
+        }
+
+        substate.exit_substates(
+            world,
+            entity,
+            commands,
+            deep || substates.history == History::Deep,
+        );
+        for event in substate.on_exit.iter() {
+            event.trigger(entity, commands);
+        }
+    }
+
+    /// Enters this state's initial substate, and that substate's initial substate, recursively.
+    /// Resumes the last substate instead if this state has history, or if `deep` is set.
+    fn enter_substates(&mut self, entity: Entity, commands: &mut Commands, deep: bool) {
+        let Some(substates) = &mut self.substates else {
+            return;
+        };
+
+        let state = match substates
+            .last
+            .take()
+            .filter(|_| deep || substates.history != History::None)
+        {
+            Some((state, insert)) => {
+                insert.insert(&mut commands.entity(entity));
+                state
+            }
+            None => {
+                (substates.insert_initial)(&mut commands.entity(entity));
+                substates.initial
+            }
+        };
+
+        // The substate is entered here, so the nested machine mustn't enter it again
+        substates.machine.entered = true;
+        let substate = substates.machine.states.get_mut(&state).unwrap();
+        for event in substate.on_enter.iter() {
+            event.trigger(entity, commands);
+        }
+
+        substate.enter_substates(entity, commands, deep || substates.history == History::Deep);
+    }
+}
+
+/// Which substates a state with substates resumes when it is re-entered. See
+/// [`StateMachine::with_history`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum History {
+    /// Enter the initial substate
+    #[default]
+    None,
+    /// Resume the substate that was active when the state was exited, but enter that substate's
+    /// initial substates
+    Shallow,
+    /// Resume the substate that was active when the state was exited, and its substates,
+    /// recursively
+    Deep,
+}
+
+/// A state's nested state machine. See [`StateMachine::with_substates`].
+struct Substates {
+    machine: StateMachine,
+    /// The substate to enter whenever the parent state is entered
+    initial: TypeId,
+    insert_initial: Box<dyn Fn(&mut EntityCommands) + Send + Sync>,
+    history: History,
+    /// The substate that was active when the parent state was last exited, if it's remembered
+    last: Option<(TypeId, Box<dyn Insert>)>,
+}
+
+impl Debug for Substates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Substates")
+            .field("states", &self.machine.states)
+            .field("initial", &self.initial)
+            .field("history", &self.history)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Creates an event that sends the event built from the entity's state of type `S`, if it's in that
+/// state
+fn state_event<S: Clone + Component, E: Event>(
+    build: fn(Entity, S, Option<Arc<str>>) -> E,
+) -> OnEvent {
+    OnEvent::Entity(Box::new(move |entity: &mut EntityCommands| {
+        let id = entity.id();
+        entity.commands().add(move |world: &mut World| {
+            if let Some(state) = world.get::<S>(id).cloned() {
+                let machine = world
+                    .get::<StateMachine>(id)
+                    .and_then(|machine| machine.name.clone());
+                world.send_event(build(id, state, machine));
+            }
+        });
+    }))
+}
+
+/// A transition in a [`StateMachine`]
+#[derive(Debug)]
+struct TransitionEntry {
+    /// The state that this transition applies in, or [`AnyState`]
+    from: TypeId,
+    /// The state that this transition goes to, or `None` if it pops the stack or chooses between
+    /// states
+    to: Option<TypeId>,
+    /// States that this transition doesn't apply in, for [`AnyState`] transitions. See
+    /// [`StateMachine::trans_from_any_except`].
+    except: Vec<TypeId>,
+    /// Transitions with higher priorities are checked first
+    priority: i32,
+    /// Overrides the machine's [`SelfTransition`]
+    self_transition: Option<SelfTransition>,
+    /// Conditions that must hold for the transition to be taken, after its trigger occurs
+    guards: Vec<Box<dyn Guard>>,
+    /// If set, the transition is scheduled when its trigger occurs, and taken after this delay
+    delay: Option<Delay>,
+    /// Relative chance that this transition is chosen. See [`Selection::Weighted`].
+    weight: f32,
+    /// Whether the trigger and guards have been initialized since the machine last transitioned
+    initialized: bool,
+    /// If not empty, the trigger is only checked if one of these detects a change. See
+    /// [`StateMachine::when_changed`].
+    changes: Vec<ChangeDetector>,
+    /// When the trigger was last checked, if it was checked since it was initialized
+    last_checked: Option<Tick>,
+    /// Whether the trigger occurred when it was last checked, if it was checked since the machine
+    /// last transitioned
+    last_result: Option<bool>,
+    transition: Box<dyn Transition>,
+}
+
+/// Detects whether something changed on the given entity, or in the world, between the given ticks
+type ChangeDetector = fn(&World, Entity, Tick, Tick) -> bool;
+
+fn component_changed<C: Component>(
+    world: &World,
+    entity: Entity,
+    last_run: Tick,
+    this_run: Tick,
+) -> bool {
+    world
+        .get_entity(entity)
+        .and_then(|entity| entity.get_change_ticks::<C>())
+        .is_some_and(|ticks| ticks.is_changed(last_run, this_run))
+}
+
+fn resource_changed<R: Resource>(world: &World, _: Entity, last_run: Tick, this_run: Tick) -> bool {
+    world
+        .get_resource_ref::<R>()
+        .is_some_and(|resource| resource.last_changed().is_newer_than(last_run, this_run))
+}
+
+/// How long a delayed transition waits. See [`StateMachine::after_delay`].
+#[derive(Clone, Copy, Debug)]
+struct Delay {
+    duration: Duration,
+    /// Whether other transitions may be taken while the transition is pending
+    cancellable: bool,
+}
+
+/// A delayed transition whose trigger has occurred
+struct PendingTransition {
+    /// The state that the transition was scheduled in
+    from: TypeId,
+    /// Elapsed [`Time`] at which the transition will be taken
+    at: Duration,
+    cancellable: bool,
+    next: NextState,
+    self_transition: SelfTransition,
+    /// Index of the transition in the machine's transitions
+    index: usize,
+}
+
+/// A condition that must hold for a transition to be taken, or for a machine to run. See
+/// [`StateMachine::guard`] and [`StateMachine::run_if`]. We have a trait for this so we can erase
+/// the [`Trigger`]'s type.
+trait Guard: Send + Sync + 'static {
+    /// Called before any call to `check`
+    fn init(&mut self, world: &mut World);
+    /// Checks whether the transition may be taken
+    fn check(&mut self, world: &World, entity: Entity) -> bool;
+}
+
+impl Debug for dyn Guard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Guard")
+    }
+}
+
+/// The guard form of a [`Trigger`]
+struct TriggerGuard<T: Trigger>(T);
+
+impl<T: Trigger> Guard for TriggerGuard<T> {
+    fn init(&mut self, world: &mut World) {
+        self.0.init(world);
+    }
+
+    fn check(&mut self, world: &World, entity: Entity) -> bool {
+        self.0.check(entity, world).occurred()
+    }
+}
+
+/// What happens when a state machine transitions from a state to another state of the same type.
+/// See [`StateMachine::set_self_transition`] and [`StateMachine::self_transition`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SelfTransition {
+    /// Exit and re-enter the state, replacing its data, and running `on_exit` and `on_enter` events
+    /// and other hooks, and resetting triggers, like any other transition
+    #[default]
+    Reenter,
+    /// Don't take the transition, and keep checking other transitions
+    Ignore,
+    /// Replace the state's data, without exiting or entering it, so no events or hooks are run, and
+    /// triggers are not reset
+    Update,
+}
+
+/// The entity that a state machine's triggers are checked on. See [`StateMachine::set_context`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TriggerContext {
+    /// The entity with the state machine
+    #[default]
+    Machine,
+    /// The parent of the entity with the state machine
+    Parent,
+    /// The root of the hierarchy that the entity with the state machine is in
+    Root,
+    /// The given entity
+    Entity(Entity),
+}
+
+impl TriggerContext {
+    /// Gets the context entity for the state machine on the given entity, or `None` if it doesn't
+    /// exist, in which case the state machine is skipped
+    fn entity(self, world: &World, entity: Entity) -> Option<Entity> {
+        match self {
+            Self::Machine => Some(entity),
+            Self::Parent => world.get::<Parent>(entity).map(Parent::get),
+            Self::Root => {
+                let mut root = entity;
+                while let Some(parent) = world.get::<Parent>(root) {
+                    root = parent.get();
+                }
+
+                Some(root)
+            }
+            Self::Entity(context) => world.get_entity(context).map(|_| context),
+        }
+    }
+}
+
+/// What a state machine does if its entity's states are changed outside of its control, such as by
+/// another system inserting or removing a state, so that the entity is in no state or multiple
+/// states. See [`StateMachine::set_desync`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Desync {
+    /// Panic
+    #[default]
+    Panic,
+    /// Log a warning, and skip the state machine until the entity is in exactly one state
+    Warn,
+    /// If a state was inserted, remove the state that the machine was in, and continue from the
+    /// inserted state, without running any events. Otherwise, same as [`Desync::Warn`].
+    Resync,
+}
+
+/// Information about a transition. See [`StateMachine::transition_order`].
+#[derive(Clone, Copy, Debug)]
+pub struct TransitionInfo<'a> {
+    /// The state that the transition applies in, or [`AnyState`]
+    pub from: TypeId,
+    /// The type name of `from`
+    pub from_name: &'a str,
+    /// The state that the transition goes to, or `None` if it returns to the state on top of the
+    /// stack (see [`StateMachine::trans_pop`]) or chooses between states (see
+    /// [`StateMachine::trans_utility`]).
+    pub to: Option<TypeId>,
+    /// The type name of `to`
+    pub to_name: Option<&'a str>,
+    /// The type name of the transition's trigger, for diagnostics
+    pub trigger_name: &'a str,
+    /// Whether the trigger occurred when it was last checked, or `None` if it hasn't been checked
+    /// since the machine last transitioned. Transitions after the one that's taken aren't checked.
+    pub last_result: Option<bool>,
+    /// See [`StateMachine::priority`]
+    pub priority: i32,
+}
+
+/// How a state machine chooses between transitions whose triggers occur in the same frame. See
+/// [`StateMachine::set_selection`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Selection {
+    /// Take the first transition, in the order described in [`StateMachine`]
+    #[default]
+    Priority,
+    /// Randomly choose a transition, using their weights (see [`StateMachine::weight`]). Every
+    /// transition's trigger is checked. Useful for variety, such as choosing an idle behavior.
+    Weighted,
+}
+
+/// A transition that was taken. See [`TransitionHistory`].
+#[derive(Clone, Debug)]
+pub struct TransitionRecord {
+    /// The state that the entity transitioned from
+    pub from: TypeId,
+    /// The type name of `from`
+    pub from_name: &'static str,
+    /// The state that the entity transitioned to
+    pub to: TypeId,
+    /// The type name of `to`
+    pub to_name: &'static str,
+    /// The world's change tick when the transition was taken
+    pub tick: Tick,
+    /// The index of the transition among all of its state machine's transitions, in the order
+    /// they're checked, or `None` if the state was forced (see [`StateMachine::force_state`])
+    pub transition: Option<usize>,
+}
+
+/// Add to an entity with a [`StateMachine`] to record its last few transitions, including those of
+/// its regions and substates, for debugging
+#[derive(Clone, Component, Debug)]
+pub struct TransitionHistory {
+    records: VecDeque<TransitionRecord>,
+    capacity: usize,
+}
+
+impl TransitionHistory {
+    /// Creates a history that keeps the given number of transitions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Iterates over the recorded transitions, from oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = &TransitionRecord> {
+        self.records.iter()
+    }
+
+    /// Gets the newest recorded transition
+    pub fn last(&self) -> Option<&TransitionRecord> {
+        self.records.back()
+    }
+
+    /// Forgets the recorded transitions
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    fn push(&mut self, record: TransitionRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+
+        self.records.push_back(record);
+    }
+}
+
+/// A likely mistake in a state machine. See [`StateMachine::validate`]. State names are type
+/// names.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValidationIssue {
+    /// The state can't be reached from the initial state
+    Unreachable {
+        /// The state
+        state: String,
+    },
+    /// The state has no outgoing transitions, so entities stay in it forever. This is fine for
+    /// terminal states.
+    NoOutgoing {
+        /// The state
+        state: String,
+    },
+    /// The transition's source state can't be reached, so it's never taken
+    UnreachableTransition {
+        /// The transition's source state
+        from: String,
+        /// The transition's target state, if known
+        to: Option<String>,
+    },
+    /// The transition duplicates an earlier transition with the same states and trigger
+    Duplicate {
+        /// The transition's source state
+        from: String,
+        /// The transition's target state, if known
+        to: Option<String>,
+    },
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let to = |to: &Option<String>| to.as_deref().unwrap_or("its next state").to_owned();
+        match self {
+            Self::Unreachable { state } => write!(f, "{state} is unreachable"),
+            Self::NoOutgoing { state } => write!(f, "{state} has no outgoing transitions"),
+            Self::UnreachableTransition { from, to: next } => write!(
+                f,
+                "the transition from {from} to {} is never taken, since {from} is unreachable",
+                to(next),
+            ),
+            Self::Duplicate { from, to: next } => {
+                write!(
+                    f,
+                    "the transition from {from} to {} is duplicated",
+                    to(next)
+                )
+            }
+        }
+    }
+}
+
+/// The input map that an entity had before entering a state with its own input map. See
+/// [`StateMachine::with_input_map`].
+#[cfg(feature = "leafwing_input")]
+#[derive(Component)]
+struct PreviousInputMap<A: Actionlike>(Option<InputMap<A>>);
+
+/// Displays an entity, optionally its [`Name`], and the name of its state machine. See
+/// [`StateMachine::display`].
+pub(crate) struct MachineDisplay<'a>(Entity, Option<&'a str>, Option<&'a str>);
+
+impl Display for MachineDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)?;
+        if let Some(name) = self.1 {
+            write!(f, " \"{name}\"")?;
+        }
+        if let Some(name) = self.2 {
+            write!(f, " ({name})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Logs the message at the given level. `tracing`'s macros need levels known at compile time.
+fn log_at(level: Level, message: std::fmt::Arguments) {
+    if level == Level::ERROR {
+        error!("{message}");
+    } else if level == Level::WARN {
+        warn!("{message}");
+    } else if level == Level::INFO {
+        info!("{message}");
+    } else if level == Level::DEBUG {
+        debug!("{message}");
+    } else {
+        trace!("{message}");
+    }
+}
+
+/// Hook that runs on every transition. See [`StateMachine::on_transition`].
+type TransitionHook =
+    Box<dyn Fn(&mut EntityCommands, Option<&dyn Reflect>, Option<&dyn Reflect>) + Send + Sync>;
+
+/// Marker component that pauses the entity's [`StateMachine`]. While paused, its triggers aren't
+/// checked, it doesn't transition, and its [`Done`] markers aren't removed. Triggers keep their
+/// state, so the machine resumes where it left off when this is removed. Time-based transitions,
+/// like [`StateMachine::after_delay`], still measure [`Time`] while paused.
+#[derive(Clone, Copy, Component, Debug, Default)]
+pub struct Paused;
+
+/// Resource that enables or disables all state machines. Set it to `false` to suspend them, such as
+/// in the main menu, on loading screens, or while the simulation is paused. While disabled, it's as
+/// if every entity were [`Paused`]. Enabled by default.
+#[derive(Clone, Copy, Debug, Deref, DerefMut, Resource)]
+pub struct StateMachinesEnabled(pub bool);
+
+impl Default for StateMachinesEnabled {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Resource that limits how many state machines are evaluated each frame. The rest are carried over
+/// to the following frames, round-robin, so every machine is still evaluated regularly. Useful for
+/// keeping frame times stable when there are many entities with expensive triggers, at the cost of
+/// the machines reacting a few frames late. The limit applies separately to [`StateMachine`]s,
+/// each type of [`LabeledMachine`], and machines that run in `FixedUpdate`. Machines that aren't
+/// evaluated keep their [`Done`] markers. Not present by default, which means there is no limit.
+#[derive(Clone, Copy, Debug, Deref, DerefMut, Resource)]
+pub struct MachineBudget(pub usize);
+
+/// Resource that makes state machines evaluate every given number of frames, instead of every
+/// frame. Entities are staggered, so a large crowd's machines don't all run on the same frame. Use
+/// [`StateMachine::set_every_frame`] for important machines, such as the player's. Machines that
+/// aren't evaluated keep their [`Done`] markers. Not present by default, which means every machine
+/// is evaluated every frame.
+#[derive(Clone, Copy, Debug, Deref, DerefMut, Resource)]
+pub struct MachineInterval(pub u32);
+
+/// Resource that makes state machines evaluate deterministically, for lockstep and rollback
+/// networking. Machines are evaluated one at a time, in order of [`Entity`], so their commands are
+/// applied in the same order every time, at the cost of parallelism. For fully deterministic
+/// machines, also run them in `FixedUpdate` (see [`StateMachine::set_fixed_update`]), so
+/// time-based transitions count fixed timesteps, and seed weighted selection with
+/// [`StateMachine::set_seed`], since it's seeded from the entity by default. Not present by
+/// default.
+#[derive(Clone, Copy, Debug, Default, Resource)]
+pub struct DeterministicMachines;
+
+/// State machine component. Entities with this component will have components (the states) added
+/// and removed based on the transitions that you add. Build one with `StateMachine::default`,
+/// `StateMachine::trans`, and other methods.
+///
+/// Each frame, the state machine checks the transitions that apply in the entity's current state,
+/// including [`AnyState`] transitions, and takes the first one whose trigger occurs and whose
+/// guards accept. Transitions are checked in order of descending priority (see
+/// [`StateMachine::priority`]), and transitions with the same priority are checked in the order
+/// they were added, whether they're from the current state or [`AnyState`]. This order is stable,
+/// and may be inspected with [`StateMachine::transition_order`]. If none are taken, the current
+/// state's substates are checked next.
+#[derive(Component)]
+pub struct StateMachine {
+    states: HashMap<TypeId, StateMetadata>,
+    /// Each transition and the state it should apply in (or [`AnyState`]). We store the transitions
+    /// in a flat list so that we ensure we always check them in the right order; storing them in
+    /// each StateMetadata would mean that e.g. we'd have to check every AnyState trigger before any
+    /// state-specific trigger or vice versa. Sorted by descending priority, and then by the order
+    /// they were added.
+    transitions: Vec<TransitionEntry>,
+    /// Indices into `transitions`, in order, by the state they apply in (or [`AnyState`]), so only
+    /// the transitions that apply in the current state are iterated
+    transitions_by_state: HashMap<TypeId, Vec<usize>>,
+    /// Index of the transition that was added last, for modifiers like
+    /// [`StateMachine::priority`]
+    last_transition: Option<usize>,
+    /// Hooks that run on every transition
+    on_transition: Vec<TransitionHook>,
+    /// Nested state machines that transition independently of this one
+    regions: Vec<StateMachine>,
+    /// States that were pushed by [`StateMachine::trans_push`], to be returned to by
+    /// [`StateMachine::trans_pop`]
+    stack: Vec<(TypeId, Box<dyn Insert>)>,
+    /// Transitions must be initialized whenever a transition is added or a transition occurs
+    init_transitions: bool,
+    /// The level that transitions are logged at, if they're logged. See
+    /// [`StateMachine::log_transitions_at`].
+    log_transitions: Option<Level>,
+    /// Human-readable name, for logs and tooling. See [`StateMachine::named`].
+    name: Option<Arc<str>>,
+    /// Whether the machine runs in `FixedUpdate`. See [`StateMachine::set_fixed_update`].
+    fixed_update: bool,
+    /// Whether the machine ignores the [`MachineInterval`]
+    every_frame: bool,
+    /// Whether the machine only takes forced transitions. See [`StateMachine::set_follow_only`].
+    follow_only: bool,
+    /// Whether the machine wasn't evaluated this frame because of the [`MachineInterval`] or
+    /// [`MachineBudget`]
+    skipped: bool,
+    /// Whether to warn when a transition shadows others, in debug builds. See
+    /// [`StateMachine::set_conflict_warnings`].
+    warn_conflicts: bool,
+    /// Whether to validate the machine when it first runs, in debug builds. See
+    /// [`StateMachine::set_validate`].
+    validate: bool,
+    /// How many more transitions may be taken in the same frame after a transition
+    chain_depth: u32,
+    /// What happens when transitioning to the same state type, unless the transition overrides it
+    self_transition: SelfTransition,
+    /// Delayed transition that has been scheduled, but not taken yet
+    pending: Option<PendingTransition>,
+    /// The machine is skipped unless all of these occur. See [`StateMachine::run_if`].
+    run_conditions: Vec<Box<dyn Guard>>,
+    /// The state that the entity was in, or is transitioning to, when the machine last ran
+    current: Option<TypeId>,
+    /// The entity that triggers are checked on
+    context: TriggerContext,
+    /// Transition that will be taken the next time the machine runs. See
+    /// [`StateMachine::force_state`].
+    forced: Option<NextState>,
+    /// Whether to run the initial state's `on_enter` events
+    enter_initial: bool,
+    /// Whether the machine has entered its initial state
+    entered: bool,
+    /// How the machine chooses between transitions, unless the current state overrides it
+    selection: Selection,
+    /// Random number generator state for [`Selection::Weighted`], or `0` if it isn't seeded yet
+    rng: u64,
+    /// The states that the machine has been in this frame, in order, if it transitioned
+    chain: Vec<TypeId>,
+    /// Buffer for the transitions that [`Selection::Weighted`] chooses between, reused so checking
+    /// transitions doesn't allocate
+    weighted: Vec<(NextState, SelfTransition, Option<Delay>, f32, usize)>,
+    /// How many triggers were checked since this was last taken, for diagnostics
+    triggers_checked: u32,
+    /// What the machine does if the entity's states are changed outside of its control
+    desync: Desync,
+    /// Registers states as implementations of traits. See [`StateMachine::with_state_trait`].
+    #[cfg(feature = "trait_query")]
+    state_traits: Vec<fn(&mut World)>,
+}
+
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self {
+            states: HashMap::from([(
+                TypeId::of::<AnyState>(),
+                StateMetadata {
+                    name: "AnyState",
+                    on_enter: vec![],
+                    on_exit: vec![],
+                    ..StateMetadata::new::<AnyState>()
+                },
+            )]),
+            transitions: vec![],
+            transitions_by_state: default(),
+            last_transition: None,
+            on_transition: vec![],
+            regions: vec![],
+            stack: vec![],
+            init_transitions: true,
+            log_transitions: None,
+            name: None,
+            fixed_update: false,
+            every_frame: false,
+            follow_only: false,
+            skipped: false,
+            warn_conflicts: false,
+            validate: false,
+            chain_depth: 0,
+            self_transition: default(),
+            pending: None,
+            run_conditions: vec![],
+            current: None,
+            context: default(),
+            forced: None,
+            enter_initial: true,
+            entered: false,
+            selection: default(),
+            rng: 0,
+            chain: vec![],
+            weighted: vec![],
+            triggers_checked: 0,
+            desync: default(),
+            #[cfg(feature = "trait_query")]
+            state_traits: default(),
+        }
+    }
+}
+
+impl StateMachine {
+    /// Registers a state. This is only necessary for states that are not used in any transitions.
+    pub fn with_state<S: Clone + Component>(mut self) -> Self {
+        self.metadata_mut::<S>();
+        self
+    }
+
+    /// Adds a transition to the state machine. When the entity is in the state given as a
+    /// type parameter, and the given trigger occurs, it will transition to the state given as a
+    /// function parameter. Elide the `Marker` type parameter with `_`. Transitions have priority
+    /// in the order they are added, unless given a priority with [`StateMachine::priority`].
+    pub fn trans<S: EntityState, Marker>(
+        self,
+        trigger: impl IntoTrigger<Marker>,
+        state: impl Clone + Component,
+    ) -> Self {
+        self.add_state_transition::<S, _, _>(trigger.into_trigger(), state)
+    }
+
+    /// Adds a transition between variants of an enum that derives [`MachineStates`]. When the
+    /// entity is in the `from` variant's state, and the given trigger occurs, it will transition to
+    /// the `to` variant's state. Only `from`'s variant matters, not its fields.
+    pub fn trans_variant<S: MachineStates, Marker>(
+        self,
+        from: S,
+        trigger: impl IntoTrigger<Marker>,
+        to: S,
+    ) -> Self {
+        let mut machine = S::register(self);
+        machine.add_transition(
+            from.state_id(),
+            Some(to.state_id()),
+            Box::new(VariantTransition {
+                trigger: trigger.into_trigger(),
+                to,
+            }),
+        );
+        machine
+    }
+
+    /// Adds a transition that is taken once the entity has been in the state given as a type
+    /// parameter for the given duration, according to [`Time`]. The timer restarts whenever this
+    /// machine transitions. Shorthand for a timer trigger passed to [`StateMachine::trans`].
+    pub fn trans_after<S: EntityState>(
+        self,
+        duration: Duration,
+        state: impl Clone + Component,
+    ) -> Self {
+        let elapsed =
+            move |In(_): In<Entity>, time: Res<Time>, mut start: Local<Option<Duration>>| {
+                let start = *start.get_or_insert(time.elapsed());
+                time.elapsed() - start >= duration
+            };
+
+        self.trans::<S, _>(elapsed, state)
+    }
+
+    /// Adds a transition from any state, except for the given states, to the state machine. When
+    /// the entity is in any state besides those in the set given as a type parameter, and the given
+    /// trigger occurs, it will transition to the state given as a function parameter. For example,
+    /// `trans_from_any_except::<(Dead, Cutscene), _>(hit, Stunned)`. Otherwise, this is the same as
+    /// `trans::<AnyState, _>`.
+    pub fn trans_from_any_except<Except: EntityStates, Marker>(
+        self,
+        trigger: impl IntoTrigger<Marker>,
+        state: impl Clone + Component,
+    ) -> Self {
+        let mut machine = self.trans::<AnyState, _>(trigger, state);
+        machine.last_transition_mut("trans_from_any_except").except = Except::type_ids();
+        machine
+    }
+
+    /// Get the metadata for the given state, creating it if necessary.
+    fn metadata_mut<S: EntityState>(&mut self) -> &mut StateMetadata {
+        self.states
+            .entry(TypeId::of::<S>())
+            .or_insert(StateMetadata::new::<S>())
+    }
+
+    /// Adds a transition to whichever of the given candidate states scores highest, if any scores
+    /// above the threshold. When the entity is in the state given as a type parameter, each
+    /// candidate's scorer is run, and the transition is taken if the best score is above the
+    /// threshold. This is useful for choosing between several states by utility, without comparing
+    /// scores in triggers.
+    pub fn trans_utility<S: EntityState>(
+        mut self,
+        threshold: f32,
+        candidates: UtilityCandidates,
+    ) -> Self {
+        self.metadata_mut::<S>();
+        for candidate in &candidates.candidates {
+            self = (candidate.register)(self);
+        }
+
+        self.add_transition(
+            TypeId::of::<S>(),
+            None,
+            Box::new(UtilityTransition {
+                threshold,
+                candidates,
+            }),
+        );
+        self
+    }
+
+    /// Adds a transition that is taken when a [`TransitionRequest`] for the entity is received.
+    /// When the entity is in the state given as the first type parameter, and a request for a state
+    /// of the type given as the second is received, it will transition to the requested state. Use
+    /// [`AnyState`] as the first type parameter to accept requests in any state. Requests are
+    /// matched against the entity that contains the state machine, even if the machine's triggers
+    /// are checked on another entity (see [`StateMachine::set_context`]).
+    pub fn trans_on_request<Prev: EntityState, S: Clone + Component>(mut self) -> Self {
+        self.metadata_mut::<Prev>();
+        self.metadata_mut::<S>();
+        self.add_transition(
+            TypeId::of::<Prev>(),
+            Some(TypeId::of::<S>()),
+            Box::new(RequestTransition {
+                trigger: requested::<S>(),
+                phantom: PhantomData,
+            }),
+        );
+        self
+    }
+
+    /// Adds a transition builder to the state machine. When the entity is in `Prev` state, and
+    /// `Trig` occurs, the given builder will be run on `Trig::Ok`. If the builder returns
+    /// `Some(Next)`, the machine will transition to that `Next` state.
+    pub fn trans_builder<
+        Prev: EntityState,
+        Trig: IntoTrigger<Marker>,
+        Next: Clone + Component,
+        Marker,
+    >(
+        mut self,
+        trigger: Trig,
+        builder: impl 'static
+            + Clone
+            + Fn(&Prev, <<Trig::Trigger as Trigger>::Out as TriggerOut>::Ok) -> Option<Next>
+            + Send
+            + Sync,
+    ) -> Self {
+        self.add_trans_builder(trigger, builder, false)
+    }
+
+    /// Adds a transition builder that also returns commands to run on the entity, such as inserting
+    /// an `Invulnerable` marker or spawning a hitbox child. When the entity is in `Prev` state, and
+    /// `Trig` occurs, the given builder will be run on `Trig::Ok`. If the builder returns
+    /// `Some((Next, commands))`, the machine will transition to that `Next` state, and the commands
+    /// will be run right after the state is inserted, in the same frame, before `on_enter` events.
+    pub fn trans_builder_with_commands<
+        Prev: EntityState,
+        Trig: IntoTrigger<Marker>,
+        Next: Clone + Component,
+        Extra: 'static + FnOnce(&mut EntityCommands) + Send + Sync,
+        Marker,
+    >(
+        mut self,
+        trigger: Trig,
+        builder: impl 'static
+            + Fn(&Prev, <<Trig::Trigger as Trigger>::Out as TriggerOut>::Ok) -> Option<(Next, Extra)>
+            + Send
+            + Sync,
+    ) -> Self {
+        self.metadata_mut::<Prev>();
+        self.metadata_mut::<Next>();
+        self.add_transition(
+            TypeId::of::<Prev>(),
+            Some(TypeId::of::<Next>()),
+            Box::new(CommandsBuilderTransition {
+                trigger: trigger.into_trigger(),
+                builder,
+                phantom: PhantomData::<Prev>,
+            }),
+        );
+        self
+    }
+
+    /// Adds a transition builder that is a read-only system, so it may read components and
+    /// resources to build the next state, such as copying the target's position into a `Chasing`
+    /// state. When the entity is in `Prev` state, and `Trig` occurs, the builder system will be run
+    /// with the entity and `Trig::Ok` as input. If it returns `Some(Next)`, the machine will
+    /// transition to that `Next` state. The builder is reset along with the triggers.
+    pub fn trans_builder_system<
+        Prev: EntityState,
+        Trig: IntoTrigger<TrigMarker>,
+        Next: Clone + Component,
+        Build: IntoSystem<
+            (Entity, <<Trig::Trigger as Trigger>::Out as TriggerOut>::Ok),
+            Option<Next>,
+            BuildMarker,
+        >,
+        TrigMarker,
+        BuildMarker,
+    >(
+        mut self,
+        trigger: Trig,
+        builder: Build,
+    ) -> Self
+    where
+        Build::System: ReadOnlySystem,
+    {
+        self.metadata_mut::<Prev>();
+        self.metadata_mut::<Next>();
+        self.add_transition(
+            TypeId::of::<Prev>(),
+            Some(TypeId::of::<Next>()),
+            Box::new(SystemBuilderTransition {
+                trigger: trigger.into_trigger(),
+                builder: IntoSystem::into_system(builder),
+                phantom: PhantomData::<Prev>,
+            }),
+        );
+        self
+    }
+
+    /// Adds a transition that always goes to the given state. See [`StateMachine::trans`].
+    fn add_state_transition<Prev: EntityState, Trig: Trigger, Next: Clone + Component>(
+        mut self,
+        trigger: Trig,
+        state: Next,
+    ) -> Self {
+        self.metadata_mut::<Prev>();
+        self.metadata_mut::<Next>();
+        self.add_transition(
+            TypeId::of::<Prev>(),
+            Some(TypeId::of::<Next>()),
+            Box::new(StateTransition { trigger, state }),
+        );
+        self
+    }
+
+    /// Like [`StateMachine::trans`], but for a trigger constructed by a [`TriggerRegistry`], so the
+    /// transition is described by the registered trigger's type name, rather than [`DynTrigger`].
+    pub fn trans_registered<S: EntityState, Next: Clone + Component>(
+        mut self,
+        trigger: DynTrigger,
+        state: Next,
+    ) -> Self {
+        self.metadata_mut::<S>();
+        self.metadata_mut::<Next>();
+        let trigger_name = trigger.name();
+        self.trans_dyn(
+            TypeId::of::<S>(),
+            trigger,
+            trigger_name,
+            TypeId::of::<Next>(),
+            move || Box::new(state.clone()),
+        )
+    }
+
+    /// Like [`StateMachine::trans_builder`], but for a trigger constructed by a [`TriggerRegistry`]
+    /// and a state builder constructed by a [`StateBuilderRegistry`]. When the entity is in the
+    /// state given as a type parameter, and the trigger occurs, the builder builds the next state
+    /// from the trigger's output. If it returns `None`, or the output isn't the type that the
+    /// builder takes, the transition isn't taken.
+    pub fn trans_builder_registered<S: EntityState>(
+        mut self,
+        trigger: DynTrigger,
+        builder: DynStateBuilder,
+    ) -> Self {
+        self.metadata_mut::<S>();
+        self.trans_builder_dyn(TypeId::of::<S>(), trigger, builder)
+    }
+
+    /// Like [`StateMachine::trans_builder_registered`], but from a state that's only known at
+    /// runtime, which, unless it's [`AnyState`], must already be registered with
+    /// [`StateMachine::with_state`]
+    pub(crate) fn trans_builder_dyn(
+        mut self,
+        from: TypeId,
+        trigger: DynTrigger,
+        builder: DynStateBuilder,
+    ) -> Self {
+        if from == TypeId::of::<AnyState>() {
+            self.metadata_mut::<AnyState>();
+        }
+
+        self = (builder.register)(self);
+        let to = builder.to;
+        self.add_transition(
+            from,
+            Some(to),
+            Box::new(DynBuilderTransition { trigger, builder }),
+        );
+        self
+    }
+
+    /// Adds a transition between states that are only known at runtime. The states, unless `from`
+    /// is [`AnyState`], must already be registered with [`StateMachine::with_state`].
+    /// `trigger_name` describes the trigger for diagnostics, and `state` creates the state to
+    /// transition to, of the type `to`.
+    pub(crate) fn trans_dyn(
+        mut self,
+        from: TypeId,
+        trigger: impl Trigger,
+        trigger_name: &'static str,
+        to: TypeId,
+        state: impl Fn() -> Box<dyn Insert> + Send + Sync + 'static,
+    ) -> Self {
+        if from == TypeId::of::<AnyState>() {
+            self.metadata_mut::<AnyState>();
+        }
+
+        self.add_transition(
+            from,
+            Some(to),
+            Box::new(DynTransition {
+                trigger,
+                trigger_name,
+                to,
+                state: Box::new(state),
+            }),
+        );
+        self
+    }
+
+    /// Adds a transition builder, which pushes the previous state onto the stack if `push` is set.
+    /// See [`StateMachine::trans_builder`].
+    fn add_trans_builder<
+        Prev: EntityState,
+        Trig: IntoTrigger<Marker>,
+        Next: Clone + Component,
+        Marker,
+    >(
+        mut self,
+        trigger: Trig,
+        builder: impl 'static
+            + Fn(&Prev, <<Trig::Trigger as Trigger>::Out as TriggerOut>::Ok) -> Option<Next>
+            + Send
+            + Sync,
+        push: bool,
+    ) -> Self {
+        self.metadata_mut::<Prev>();
+        self.metadata_mut::<Next>();
+        let mut transition = TransitionImpl::<_, Prev, _, _>::new(trigger.into_trigger(), builder);
+        transition.push = push;
+        self.add_transition(
+            TypeId::of::<Prev>(),
+            Some(TypeId::of::<Next>()),
+            Box::new(transition),
+        );
+        self
+    }
+
+    /// Adds a transition without any modifiers
+    fn add_transition(
+        &mut self,
+        from: TypeId,
+        to: Option<TypeId>,
+        transition: Box<dyn Transition>,
+    ) {
+        self.insert_transition(TransitionEntry {
+            from,
+            to,
+            except: vec![],
+            priority: 0,
+            self_transition: None,
+            guards: vec![],
+            delay: None,
+            weight: 1.,
+            initialized: false,
+            changes: vec![],
+            last_checked: None,
+            last_result: None,
+            transition,
+        });
+    }
+
+    /// Inserts a transition in order of priority
+    fn insert_transition(&mut self, entry: TransitionEntry) {
+        let index = self
+            .transitions
+            .partition_point(|other| other.priority >= entry.priority);
+        self.transitions.insert(index, entry);
+        self.last_transition = Some(index);
+        self.init_transitions = true;
+        self.index_transitions();
+    }
+
+    /// Rebuilds `transitions_by_state`. Must be called whenever transitions are added or removed.
+    fn index_transitions(&mut self) {
+        self.transitions_by_state.clear();
+        for (index, entry) in self.transitions.iter().enumerate() {
+            self.transitions_by_state
+                .entry(entry.from)
+                .or_default()
+                .push(index);
+        }
+    }
+
+    /// Gets the transition that was added last, so a modifier may change it
+    fn last_transition_mut(&mut self, modifier: &str) -> &mut TransitionEntry {
+        let Some(index) = self.last_transition else {
+            panic!("called `StateMachine::{modifier}` before adding a transition");
+        };
+
+        &mut self.transitions[index]
+    }
+
+    /// Removes the transition that was added last, so a modifier may change it and add it back
+    fn take_last_transition(&mut self, modifier: &str) -> TransitionEntry {
+        let Some(index) = self.last_transition.take() else {
+            panic!("called `StateMachine::{modifier}` before adding a transition");
+        };
+
+        let entry = self.transitions.remove(index);
+        self.index_transitions();
+        entry
+    }
+
+    /// Adds a guard to the transition that was added last. A guard is checked like a trigger, but
+    /// only after the transition's trigger occurs, and the transition is only taken if every guard
+    /// accepts. This separates whether something happened from whether the transition is currently
+    /// allowed, without changing the trigger's output, like [`IntoTrigger::and`] would.
+    pub fn guard<Marker>(mut self, guard: impl IntoTrigger<Marker>) -> Self {
+        self.last_transition_mut("guard")
+            .guards
+            .push(Box::new(TriggerGuard(guard.into_trigger())));
+        self.init_transitions = true;
+        self
+    }
+
+    /// Only checks the trigger of the transition that was added last if the given component, on the
+    /// entity that triggers are checked on (see [`StateMachine::set_context`]), was added or
+    /// changed since the trigger was last checked. Triggers are always checked after the machine
+    /// transitions. Skipping checks is cheaper for triggers that only depend on a few components,
+    /// but the transition's trigger and guards must only depend on the given components and
+    /// resources, or it may miss changes. Call this and [`StateMachine::when_resource_changed`]
+    /// multiple times to check the trigger when any of them changes.
+    pub fn when_changed<C: Component>(mut self) -> Self {
+        self.last_transition_mut("when_changed")
+            .changes
+            .push(component_changed::<C>);
+        self
+    }
+
+    /// Same as [`StateMachine::when_changed`], but for a resource
+    pub fn when_resource_changed<R: Resource>(mut self) -> Self {
+        self.last_transition_mut("when_resource_changed")
+            .changes
+            .push(resource_changed::<R>);
+        self
+    }
+
+    /// Sets the weight of the transition that was added last, for states that use
+    /// [`Selection::Weighted`]. Transitions have weight `1` by default.
+    pub fn weight(mut self, weight: f32) -> Self {
+        self.last_transition_mut("weight").weight = weight;
+        self
+    }
+
+    /// Sets the priority of the transition that was added last. Transitions with higher priorities
+    /// are checked first, and transitions with the same priority are checked in the order they were
+    /// added. Transitions have priority `0` by default. Only the first transition whose trigger
+    /// occurs is taken.
+    pub fn priority(mut self, priority: i32) -> Self {
+        let entry = self.take_last_transition("priority");
+        self.insert_transition(TransitionEntry { priority, ..entry });
+        self
+    }
+
+    /// Sets what happens when the transition that was added last goes from a state to another state
+    /// of the same type, overriding [`StateMachine::set_self_transition`]
+    pub fn self_transition(mut self, self_transition: SelfTransition) -> Self {
+        let entry = self.take_last_transition("self_transition");
+        self.insert_transition(TransitionEntry {
+            self_transition: Some(self_transition),
+            ..entry
+        });
+        self
+    }
+
+    /// Delays the transition that was added last. When its trigger occurs, the transition is
+    /// scheduled, and it is taken once the given duration has passed, according to [`Time`]. Until
+    /// then, other transitions may still be taken, which cancels it, and the transition is also
+    /// cancelled if the entity leaves the state some other way. While a delayed transition is
+    /// pending, other delayed transitions are not scheduled. Useful for telegraphed attacks and
+    /// wind-up or recovery timing.
+    pub fn after_delay(mut self, delay: Duration) -> Self {
+        self.last_transition_mut("after_delay").delay = Some(Delay {
+            duration: delay,
+            cancellable: true,
+        });
+        self
+    }
+
+    /// Same as [`StateMachine::after_delay`], but while the transition is pending, no other
+    /// transitions are taken, so it can only be cancelled if the entity leaves the state some other
+    /// way
+    pub fn after_delay_uncancellable(mut self, delay: Duration) -> Self {
+        self.last_transition_mut("after_delay_uncancellable").delay = Some(Delay {
+            duration: delay,
+            cancellable: false,
+        });
+        self
+    }
+
+    /// Adds a transition that pushes the current state onto the state machine's stack before
+    /// transitioning, so that a [`StateMachine::trans_pop`] transition may later return to it, with
+    /// the same data. Useful for states like `Dialogue` or `Stagger` that interrupt other states.
+    /// Otherwise, this is the same as [`StateMachine::trans`].
+    pub fn trans_push<S: EntityState, Marker>(
+        self,
+        trigger: impl IntoTrigger<Marker>,
+        state: impl Clone + Component,
+    ) -> Self {
+        self.add_trans_builder(trigger, move |_: &S, _| Some(state.clone()), true)
+    }
+
+    /// Adds a transition that returns to the state on top of the state machine's stack, which was
+    /// pushed there by a [`StateMachine::trans_push`] transition. When the entity is in the state
+    /// given as a type parameter, and the given trigger occurs, it will transition to that state,
+    /// as long as the stack isn't empty.
+    pub fn trans_pop<S: EntityState, Marker>(mut self, trigger: impl IntoTrigger<Marker>) -> Self {
+        self.metadata_mut::<S>();
+        self.add_transition(
+            TypeId::of::<S>(),
+            None,
+            Box::new(PopTransition(trigger.into_trigger())),
+        );
+        self
+    }
+
+    /// Adds an on-enter event to the state machine. Whenever the state machine transitions into the
+    /// given state, it will run the event. The event receives the entity's [`EntityCommands`], so
+    /// use `entity.id()` to get the entity and `entity.commands()` to queue any other commands.
+    pub fn on_enter<S: EntityState>(
+        mut self,
+        on_enter: impl 'static + Fn(&mut EntityCommands) + Send + Sync,
+    ) -> Self {
+        self.metadata_mut::<S>()
+            .on_enter
+            .push(OnEvent::Entity(Box::new(on_enter)));
+
+        self
+    }
+
+    /// Adds an on-exit event to the state machine. Whenever the state machine transitions from the
+    /// given state, it will run the event. See [`StateMachine::on_enter`] for what the event
+    /// receives.
+    pub fn on_exit<S: EntityState>(
+        mut self,
+        on_exit: impl 'static + Fn(&mut EntityCommands) + Send + Sync,
+    ) -> Self {
+        self.metadata_mut::<S>()
+            .on_exit
+            .push(OnEvent::Entity(Box::new(on_exit)));
+
+        self
+    }
+
+    /// Adds an on-enter command to the state machine. Whenever the state machine transitions into
+    /// the given state, it will run the command.
+    pub fn command_on_enter<S: EntityState>(
+        mut self,
+        command: impl Clone + Command + Sync,
+    ) -> Self {
+        self.metadata_mut::<S>()
+            .on_enter
+            .push(OnEvent::Command(Box::new(command)));
+
+        self
+    }
+
+    /// Adds an on-exit command to the state machine. Whenever the state machine transitions from
+    /// the given state, it will run the command.
+    pub fn command_on_exit<S: EntityState>(mut self, command: impl Clone + Command + Sync) -> Self {
+        self.metadata_mut::<S>()
+            .on_exit
+            .push(OnEvent::Command(Box::new(command)));
+
+        self
+    }
+
+    /// Inserts the given bundle whenever the state machine transitions into the given state. Pair
+    /// it with [`StateMachine::remove_on_exit`] for components that should only exist in that
+    /// state, like an attack's hitbox. Like other on-enter events, this also runs for the state
+    /// that the entity is spawned in, unless the machine is given
+    /// [`StateMachine::set_enter_initial`]`(false)`.
+    pub fn insert_on_enter<S: EntityState>(self, bundle: impl Bundle + Clone) -> Self {
+        self.on_enter::<S>(move |entity| {
+            entity.insert(bundle.clone());
+        })
+    }
+
+    /// Removes the given bundle whenever the state machine transitions from the given state,
+    /// including through [`AnyState`] transitions that interrupt it
+    pub fn remove_on_exit<S: EntityState, B: Bundle>(self) -> Self {
+        self.on_exit::<S>(|entity| {
+            entity.remove::<B>();
+        })
+    }
+
+    /// Sets Bevy's global [`States`] whenever the entity enters the given state, so the app's state
+    /// follows this machine, such as a director entity's machine that manages game flow. The global
+    /// state changes through [`bevy::prelude::NextState`], so it's applied in the next
+    /// [`bevy::prelude::StateTransition`]. To transition when the global state is set instead, see
+    /// [`next_global_state`].
+    pub fn drive_global_state<S: EntityState, T: States>(self, value: T) -> Self {
+        self.command_on_enter::<S>(move |world: &mut World| {
+            world
+                .resource_mut::<bevy::prelude::NextState<T>>()
+                .set(value);
+        })
+    }
+
+    /// Gives the entity the given [`InputMap`] while it's in the given state, such as to limit the
+    /// player's actions while a menu is open. The entity's previous input map, if any, is restored
+    /// when it exits the state.
+    #[cfg(feature = "leafwing_input")]
+    pub fn with_input_map<S: EntityState, A: Actionlike>(self, input_map: InputMap<A>) -> Self {
+        self.on_enter::<S>(move |entity| {
+            let input_map = input_map.clone();
+            entity.add(move |entity: Entity, world: &mut World| {
+                let mut entity = world.entity_mut(entity);
+                let previous = entity.take::<InputMap<A>>();
+                entity.insert((input_map, PreviousInputMap(previous)));
+            });
+        })
+        .on_exit::<S>(|entity| {
+            entity.add(|entity: Entity, world: &mut World| {
+                let mut entity = world.entity_mut(entity);
+                entity.remove::<InputMap<A>>();
+                if let Some(PreviousInputMap(Some(previous))) = entity.take::<PreviousInputMap<A>>()
+                {
+                    entity.insert(previous);
+                }
+            });
+        })
+    }
+
+    /// Gives the given state a nested state machine of substates. Whenever the entity enters the
+    /// parent state, it also enters `initial`, and whenever it exits the parent state, it also
+    /// exits its current substate. While the entity is in the parent state, the parent state's
+    /// transitions (including [`AnyState`] transitions) are checked first, and the nested machine's
+    /// transitions are only checked if none of those are taken. When spawning an entity directly
+    /// into the parent state, include a substate too.
+    pub fn with_substates<S: Clone + Component, I: Clone + Component>(
+        mut self,
+        mut machine: StateMachine,
+        initial: I,
+    ) -> Self {
+        machine.metadata_mut::<I>();
+        self.metadata_mut::<S>().substates = Some(Substates {
+            machine,
+            initial: TypeId::of::<I>(),
+            insert_initial: Box::new(move |entity: &mut EntityCommands| {
+                entity.insert(initial.clone());
+            }),
+            history: History::None,
+            last: None,
+        });
+        self.init_transitions = true;
+
+        self
+    }
+
+    /// Sets which substates the given state resumes when it is re-entered. The state must have been
+    /// given substates with [`StateMachine::with_substates`]. Resumed substates keep their data.
+    pub fn with_history<S: EntityState>(mut self, history: History) -> Self {
+        let Some(substates) = &mut self.metadata_mut::<S>().substates else {
+            panic!(
+                "tried to set the history of {}, which has no substates",
+                type_name::<S>()
+            );
+        };
+
+        substates.history = history;
+        self
+    }
+
+    /// Adds an orthogonal region to the state machine. A region is a nested state machine that
+    /// keeps its own current state, and transitions independently of this machine and its other
+    /// regions, so the entity is in one state from each. For example, you may have a movement
+    /// region and a weapon region. Since states are components, a region's triggers may check the
+    /// states of other regions through queries. A state machine that has regions, but no states of
+    /// its own, is allowed.
+    pub fn with_region(mut self, region: StateMachine) -> Self {
+        self.regions.push(region);
+        self
+    }
+
+    /// Makes the state machine send [`StateEntered<S>`] and [`StateExited<S>`] events whenever it
+    /// enters or exits the given state. Remember to register both events with `App::add_event`.
+    pub fn send_events<S: Clone + Component>(mut self) -> Self {
+        let metadata = self.metadata_mut::<S>();
+        metadata
+            .on_enter
+            .push(state_event(|entity, state: S, machine| StateEntered {
+                entity,
+                state,
+                machine,
+            }));
+        // Exit events must be sent before the state is removed, which is the first on-exit event
+        metadata.on_exit.insert(
+            0,
+            state_event(|entity, state: S, machine| StateExited {
+                entity,
+                state,
+                machine,
+            }),
+        );
+
+        self
+    }
+
+    /// Adds a transition hook to the state machine. Whenever the state machine transitions, it will
+    /// run the hook with the entity's [`EntityCommands`], the previous state, and the next state.
+    /// The states are only provided if their types are registered with `App::register_type`, so
+    /// derive [`Reflect`] for your states to use them here.
+    pub fn on_transition(
+        mut self,
+        hook: impl 'static
+            + Fn(&mut EntityCommands, Option<&dyn Reflect>, Option<&dyn Reflect>)
+            + Send
+            + Sync,
+    ) -> Self {
+        self.on_transition.push(Box::new(hook));
+        self
+    }
+
+    /// Names the state machine, such as `"goblin_ai"`, so it can be told apart from other machines.
+    /// The name appears in logs and in [`StateEntered`] and [`StateExited`] events.
+    pub fn named(mut self, name: impl Into<Arc<str>>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Gets the state machine's name. See [`StateMachine::named`].
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Sets whether the state machine is validated with [`StateMachine::validate`] when it first
+    /// runs, using the entity's state as the initial state. In debug builds, it panics if any
+    /// issues are found. Disabled by default.
+    pub fn set_validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Sets whether to warn, in debug builds, when the transition that's taken shadows other
+    /// transitions out of the current state whose triggers also occurred that frame. Once one
+    /// transition's trigger occurs, the rest usually aren't checked, so this checks them, which may
+    /// affect triggers that read events. Disabled by default.
+    pub fn set_conflict_warnings(mut self, warn_conflicts: bool) -> Self {
+        self.warn_conflicts = warn_conflicts;
+        self
+    }
+
+    /// Sets whether the state machine runs in `FixedUpdate`, instead of the schedule given to
+    /// [`StateMachinePlugin`]. Useful for gameplay machines that should stay in step with
+    /// fixed-timestep movement, alongside machines that run every frame, such as for UI. The
+    /// entity's [`Done`] markers are then removed in `FixedUpdate` too. Disabled by default.
+    pub fn set_fixed_update(mut self, fixed_update: bool) -> Self {
+        self.fixed_update = fixed_update;
+        self
+    }
+
+    /// Whether the state machine runs in `FixedUpdate`. See [`StateMachine::set_fixed_update`].
+    pub fn is_fixed_update(&self) -> bool {
+        self.fixed_update
+    }
+
+    /// Sets whether the state machine is evaluated every frame, regardless of the
+    /// [`MachineInterval`]. Useful for machines that must react immediately, like the player's or a
+    /// boss's. Disabled by default.
+    pub fn set_every_frame(mut self, every_frame: bool) -> Self {
+        self.every_frame = every_frame;
+        self
+    }
+
+    /// Sets whether the state machine only follows transitions forced from outside, such as by
+    /// [`StateMachine::force_state`] or state replication, instead of checking its own triggers.
+    /// Forced transitions still run `on_enter` and `on_exit` events and other hooks. Useful for
+    /// client-side copies of machines whose authoritative state lives elsewhere. Disabled by
+    /// default.
+    pub fn set_follow_only(mut self, follow_only: bool) -> Self {
+        self.follow_only = follow_only;
+        self
+    }
+
+    /// Whether the state machine only takes forced transitions. See
+    /// [`StateMachine::set_follow_only`].
+    pub fn is_follow_only(&self) -> bool {
+        self.follow_only
+    }
+
+    /// Whether the state machine wasn't evaluated this frame because of the [`MachineInterval`] or
+    /// [`MachineBudget`]
+    pub(crate) fn is_skipped(&self) -> bool {
+        self.skipped
+    }
+
+    /// Logs this machine's transitions at the given level, such as `Level::DEBUG`, so verbose
+    /// machines can be logged while others stay quiet. Log lines include the entity, its [`Name`],
+    /// if it has one, and the machine's name (see [`StateMachine::named`]). Not logged by default.
+    pub fn log_transitions_at(mut self, level: Level) -> Self {
+        self.log_transitions = Some(level);
+        self
+    }
+
+    /// Sets whether the state machine runs the `on_enter` events of the state that the entity is
+    /// spawned in, the first time the machine runs, as if it had transitioned into it. This
+    /// includes events sent by [`StateMachine::send_events`]. It doesn't enter the initial state's
+    /// substates or run [`StateMachine::on_transition`] hooks. By default, this is `true`.
+    pub fn set_enter_initial(mut self, enter_initial: bool) -> Self {
+        self.enter_initial = enter_initial;
+        self
+    }
+
+    /// Sets how the state machine chooses between transitions whose triggers occur in the same
+    /// frame. States may override this with [`StateMachine::with_selection`]. By default, this is
+    /// [`Selection::Priority`].
+    pub fn set_selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Sets how the state machine chooses between transitions in the given state. See
+    /// [`StateMachine::set_selection`].
+    pub fn with_selection<S: EntityState>(mut self, selection: Selection) -> Self {
+        self.metadata_mut::<S>().selection = Some(selection);
+        self
+    }
+
+    /// Seeds the random number generator used by [`Selection::Weighted`], for determinism. By
+    /// default, it's seeded from the entity.
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        // Xorshift can't be seeded with `0`
+        self.rng = seed.max(1);
+        self
+    }
+
+    /// Sets what the state machine does if the entity's states are changed outside of its control,
+    /// so that it's in no state or multiple states. By default, this is [`Desync::Panic`].
+    pub fn set_desync(mut self, desync: Desync) -> Self {
+        self.desync = desync;
+        self
+    }
+
+    /// Sets how many more transitions the state machine may take in the same frame after taking a
+    /// transition. By default, this is `0`, so a state machine takes at most one transition per
+    /// frame, and each state lasts at least one frame. Raise it so that pass-through states, such
+    /// as decision states that transition on [`always`], resolve within the same frame. Each extra
+    /// transition applies the commands queued so far, so this has a cost. If a state machine uses
+    /// its whole chain depth while transitioning in a loop, such as between two states that
+    /// transition to each other on [`always`], it logs a warning with the states it looped through.
+    pub fn set_chain_depth(mut self, chain_depth: u32) -> Self {
+        self.chain_depth = chain_depth;
+        self
+    }
+
+    /// Sets what happens when the state machine transitions from a state to another state of the
+    /// same type. Transitions may override this with [`StateMachine::self_transition`]. By default,
+    /// this is [`SelfTransition::Reenter`].
+    pub fn set_self_transition(mut self, self_transition: SelfTransition) -> Self {
+        self.self_transition = self_transition;
+        self
+    }
+
+    /// Adds the states, transitions, and other behavior of another state machine to this one, so
+    /// common behavior, like transitions to `Stunned` or `Dead` from any state, may be defined once
+    /// and merged into many machines. Transitions keep their priorities, and this machine's
+    /// transitions are checked before the other machine's transitions of the same priority. Events
+    /// and hooks of both machines are run, and if both machines have substates for the same state,
+    /// this machine's substates are kept. Settings, like [`StateMachine::set_chain_depth`], are
+    /// taken from this machine.
+    pub fn merge(mut self, other: StateMachine) -> Self {
+        let StateMachine {
+            states,
+            transitions,
+            on_transition,
+            regions,
+            run_conditions,
+            ..
+        } = other;
+
+        for (id, state) in states {
+            let Some(existing) = self.states.get_mut(&id) else {
+                self.states.insert(id, state);
+                continue;
+            };
+
+            existing.on_enter.extend(state.on_enter);
+            // The first on-exit event removes the state, and the existing state already has one
+            let skip = usize::from(id != TypeId::of::<AnyState>());
+            existing
+                .on_exit
+                .extend(state.on_exit.into_iter().skip(skip));
+            if existing.substates.is_none() {
+                existing.substates = state.substates;
+            }
+        }
+
+        for entry in transitions {
+            self.insert_transition(entry);
+        }
+
+        self.on_transition.extend(on_transition);
+        self.regions.extend(regions);
+        self.run_conditions.extend(run_conditions);
+        self
+    }
+
+    /// Sets which entity the state machine's triggers, guards, and run conditions are checked on,
+    /// so a machine on an "AI brain" child entity may read its parent's components, for example. By
+    /// default, this is the entity with the state machine. States are always on the entity with the
+    /// state machine, and builders are given its states. Substates and regions have their own
+    /// contexts.
+    pub fn set_context(mut self, context: TriggerContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Adds a run condition to the state machine. It is checked like a trigger, before any of the
+    /// machine's transitions, and if it doesn't occur, the machine, including its regions and
+    /// substates, is skipped that frame. Useful for cheaply deactivating machines, such as those of
+    /// entities far from the camera. If you add multiple run conditions, all of them must occur.
+    pub fn run_if<Marker>(mut self, condition: impl IntoTrigger<Marker>) -> Self {
+        self.run_conditions
+            .push(Box::new(TriggerGuard(condition.into_trigger())));
+        self.init_transitions = true;
+        self
+    }
+
+    /// Adds a transition to a state machine that may already be in use, such as from a system with
+    /// a `Query<&mut StateMachine>`, to unlock abilities after the entity is spawned. The
+    /// transition is the same as one added with [`StateMachine::trans`].
+    pub fn add_trans<S: EntityState, Marker>(
+        &mut self,
+        trigger: impl IntoTrigger<Marker>,
+        state: impl Clone + Component,
+    ) {
+        *self = std::mem::take(self).trans::<S, _>(trigger, state);
+    }
+
+    /// Changes a state machine that may already be in use, using any of the builder methods, like
+    /// `machine.edit(|machine| machine.trans::<Idle, _>(jump, Jumping).priority(1))`
+    pub fn edit(&mut self, edit: impl FnOnce(Self) -> Self) {
+        *self = edit(std::mem::take(self));
+    }
+
+    /// Removes every transition from the state given as the first type parameter to the state given
+    /// as the second, from a state machine that may already be in use. Use [`AnyState`] as the
+    /// first type parameter to remove transitions added with `trans::<AnyState, _>` or
+    /// [`StateMachine::trans_from_any_except`]. If a removed transition was delayed and pending,
+    /// it's cancelled. Returns whether any transitions were removed.
+    pub fn remove_trans<From: EntityState, To: Clone + Component>(&mut self) -> bool {
+        let len = self.transitions.len();
+        let mut pending = self.pending.take();
+        let mut index = 0;
+        let mut removed = 0;
+        self.transitions.retain(|entry| {
+            let keep = entry.from != TypeId::of::<From>() || entry.to != Some(TypeId::of::<To>());
+            if let Some(scheduled) = pending.as_mut().filter(|pending| pending.index == index) {
+                // A pending delayed transition is dropped with its transition
+                if keep {
+                    scheduled.index -= removed;
+                } else {
+                    pending = None;
+                }
+            }
+
+            index += 1;
+            removed += usize::from(!keep);
+            keep
+        });
+        self.pending = pending;
+
+        // The indices of the transitions may have changed
+        self.last_transition = None;
+        self.index_transitions();
+        self.transitions.len() != len
+    }
+
+    /// Replaces every transition from the state given as a type parameter to the type of the given
+    /// state with a single transition with the given trigger. See [`StateMachine::remove_trans`]
+    /// and [`StateMachine::add_trans`].
+    pub fn replace_trans<From: EntityState, To: Clone + Component, Marker>(
+        &mut self,
+        trigger: impl IntoTrigger<Marker>,
+        state: To,
+    ) {
+        self.remove_trans::<From, To>();
+        self.add_trans::<From, _>(trigger, state);
+    }
+
+    /// Makes the state machine transition to the given state the next time it runs, like any other
+    /// transition, running `on_exit` and `on_enter` events and other hooks, and resetting triggers.
+    /// The transition is taken regardless of triggers, guards, and run conditions, even if the
+    /// entity is already in the given state, but not while the machine is [`Paused`]. Also see
+    /// [`StateMachineCommandsExt::set_state`].
+    pub fn force_state<S: Clone + Component>(&mut self, state: S) {
+        self.metadata_mut::<S>();
+        self.forced = Some(NextState::Go(Box::new(state), TypeId::of::<S>()));
+    }
+
+    /// Like [`StateMachine::force_state`], but for a reflected state, such as from a debug UI. The
+    /// state's type must be one of the machine's states, and be registered in the
+    /// [`AppTypeRegistry`] with `#[reflect(Component)]`.
+    pub fn force_state_reflect(&mut self, state: Box<dyn Reflect>) {
+        let id = state.as_any().type_id();
+        if !self.states.contains_key(&id) {
+            warn!(
+                "can't force {}, since it isn't one of the state machine's states",
+                state.reflect_type_path(),
+            );
+            return;
+        }
+
+        self.forced = Some(NextState::Go(
+            Box::new(ReflectInsert {
+                state: id,
+                value: state,
+            }),
+            id,
+        ));
+    }
+
+    /// Gets the [`TypeId`] of the entity's current state. This is known once the state machine has
+    /// run, and is updated whenever it transitions, so it's `None` on the frame the machine is
+    /// added. Substates and regions aren't included.
+    pub fn current_state_id(&self) -> Option<TypeId> {
+        self.current
+    }
+
+    /// Gets the type name of the entity's current state. See [`StateMachine::current_state_id`].
+    pub fn current_state_name(&self) -> Option<&str> {
+        Some(self.states[&self.current?].name)
+    }
+
+    /// Checks whether the entity is in the given state. See [`StateMachine::current_state_id`] for
+    /// when this is known. Use a query with the state instead if you need the state's data.
+    pub fn is_in_state<S: Clone + Component>(&self) -> bool {
+        self.current == Some(TypeId::of::<S>())
+    }
+
+    /// Iterates over the [`TypeId`]s and type names of the states that this state machine was built
+    /// with, in no particular order. Substates and the states of regions aren't included.
+    pub fn states(&self) -> impl Iterator<Item = (TypeId, &str)> {
+        self.states
+            .iter()
+            .filter(|(&id, _)| id != TypeId::of::<AnyState>())
+            .map(|(&id, state)| (id, state.name))
+    }
+
+    /// Iterates over the transitions that apply in the given state, including [`AnyState`]
+    /// transitions, in the order that they are checked. See [`StateMachine`] for details on the
+    /// order. Pass [`AnyState`] to get only [`AnyState`] transitions.
+    pub fn transition_order<S: EntityState>(&self) -> impl Iterator<Item = TransitionInfo> {
+        let state = TypeId::of::<S>();
+        self.transitions
+            .iter()
+            .filter(move |entry| {
+                (entry.from == state || entry.from == TypeId::of::<AnyState>())
+                    && !entry.except.contains(&state)
+            })
+            .map(move |entry| self.transition_info(entry))
+    }
+
+    /// Iterates over all of the state machine's transitions, in the order that they are checked.
+    /// Transitions of substates and regions aren't included.
+    pub fn transitions(&self) -> impl Iterator<Item = TransitionInfo> {
+        self.transitions
+            .iter()
+            .map(move |entry| self.transition_info(entry))
+    }
+
+    fn transition_info(&self, entry: &TransitionEntry) -> TransitionInfo {
+        TransitionInfo {
+            from: entry.from,
+            from_name: self.states[&entry.from].name,
+            to: entry.to,
+            to_name: entry.to.map(|to| self.states[&to].name),
+            trigger_name: entry.transition.trigger_name(),
+            last_result: entry.last_result,
+            priority: entry.priority,
+        }
+    }
+
+    /// Checks the state machine for likely mistakes, given the state that entities start in.
+    /// Reports states that can't be reached, states with no outgoing transitions (which is fine for
+    /// terminal states), transitions from states that can't be reached, and duplicate transitions.
+    /// Also checks the substates of each state. Regions aren't checked, since their initial states
+    /// aren't known. See [`StateMachine::set_validate`].
+    pub fn validate<S: Clone + Component>(&self) -> Vec<ValidationIssue> {
+        self.validate_from(TypeId::of::<S>())
+    }
+
+    fn validate_from(&self, initial: TypeId) -> Vec<ValidationIssue> {
+        let any = TypeId::of::<AnyState>();
+        let name = |state: TypeId| {
+            self.states
+                .get(&state)
+                .map_or_else(|| format!("{state:?}"), |metadata| metadata.name.to_owned())
+        };
+        let applies = |entry: &TransitionEntry, state: TypeId| {
+            (entry.from == state || entry.from == any) && !entry.except.contains(&state)
+        };
+
+        let mut reachable = HashSet::from([initial]);
+        let mut unvisited = vec![initial];
+        while let Some(state) = unvisited.pop() {
+            for entry in self
+                .transitions
+                .iter()
+                .filter(|entry| applies(entry, state))
+            {
+                for next in entry.to.into_iter().chain(entry.transition.candidates()) {
+                    if reachable.insert(next) {
+                        unvisited.push(next);
+                    }
+                }
+            }
+        }
+
+        let mut states = self
+            .states
+            .keys()
+            .copied()
+            .filter(|&state| state != any)
+            .collect::<Vec<_>>();
+        states.sort_by_key(|&state| name(state));
+
+        let mut issues = vec![];
+        for &state in &states {
+            if !reachable.contains(&state) {
+                issues.push(ValidationIssue::Unreachable { state: name(state) });
+            } else if !self.transitions.iter().any(|entry| applies(entry, state)) {
+                issues.push(ValidationIssue::NoOutgoing { state: name(state) });
+            }
+        }
+
+        for (i, entry) in self.transitions.iter().enumerate() {
+            if entry.from != any && !reachable.contains(&entry.from) {
+                issues.push(ValidationIssue::UnreachableTransition {
+                    from: name(entry.from),
+                    to: entry.to.map(name),
+                });
+            }
+
+            let duplicate = |other: &TransitionEntry| {
+                other.from == entry.from
+                    && other.to == entry.to
+                    && other.except == entry.except
+                    && other.guards.is_empty()
+                    && entry.guards.is_empty()
+                    && other.transition.transition_type() == entry.transition.transition_type()
+            };
+            if entry.to.is_some() && self.transitions[..i].iter().any(duplicate) {
+                issues.push(ValidationIssue::Duplicate {
+                    from: name(entry.from),
+                    to: entry.to.map(name),
+                });
+            }
+        }
+
+        for state in states {
+            if let Some(substates) = &self.states[&state].substates {
+                issues.extend(substates.machine.validate_from(substates.initial));
+            }
+        }
+
+        issues
+    }
+
+    /// Gets the entity's current state, for debug UIs, logging, and saving, without knowing every
+    /// state's type. The state must be registered in the [`AppTypeRegistry`] with
+    /// `#[reflect(Component)]`. `entity` is the entity with this state machine. See
+    /// [`StateMachine::current_state_id`].
+    pub fn current_state<'w>(&self, world: &'w World, entity: Entity) -> Option<&'w dyn Reflect> {
+        world
+            .resource::<AppTypeRegistry>()
+            .read()
+            .get_type_data::<ReflectComponent>(self.current?)?
+            .reflect(world.get_entity(entity)?)
+    }
+
+    /// Initialize the transitions that may be checked. Must be executed before `run`. This is
+    /// separate because `run` is parallelizable (takes a `&World`) but this isn't (takes a
+    /// `&mut World`).
+    fn init_transitions(&mut self, world: &mut World, entity: Entity) {
+        let init = self.init_transitions;
+        if init {
+            for entry in &mut self.transitions {
+                entry.initialized = false;
+                entry.last_checked = None;
+                entry.last_result = None;
+            }
+
+            for condition in &mut self.run_conditions {
+                condition.init(world);
+            }
+
+            self.init_transitions = false;
+        }
+
+        // Transitions are only initialized once the entity is in a state they apply in, so big
+        // machines don't reinitialize every trigger whenever they transition. `run` determines the
+        // current state from the entity's components, so this checks those too.
+        for TransitionEntry {
+            from,
+            guards,
+            initialized,
+            transition,
+            ..
+        } in &mut self.transitions
+        {
+            if *initialized
+                || (*from != TypeId::of::<AnyState>()
+                    && !world
+                        .get_entity(entity)
+                        .is_some_and(|entity| entity.contains_type_id(*from)))
+            {
+                continue;
+            }
+
+            transition.init(world);
+            for guard in guards {
+                guard.init(world);
+            }
+            *initialized = true;
+        }
+
+        for region in &mut self.regions {
+            region.init_transitions(world, entity);
+        }
+
+        for substates in self
+            .states
+            .values_mut()
+            .filter_map(|state| state.substates.as_mut())
+        {
+            // Substates may have been entered or exited if this machine transitioned
+            substates.machine.init_transitions |= init;
+            substates.machine.init_transitions(world, entity);
+        }
+    }
+
+    /// Checks the transitions that apply in the current state until one is taken, and returns where
+    /// it goes. If that transition is delayed, schedules it instead, and returns `None`.
+    fn check_transitions(
+        &mut self,
+        world: &World,
+        entity: Entity,
+        current: TypeId,
+    ) -> Option<(NextState, SelfTransition, Option<usize>)> {
+        let context = self.context.entity(world, entity)?;
+        let pending = self.pending.is_some();
+        let stack_top = self.stack.last().map(|&(state, _)| state);
+        let default_self_transition = self.self_transition;
+        let warn_conflicts = cfg!(debug_assertions) && self.warn_conflicts;
+        let selection = self.states[&current].selection.unwrap_or(self.selection);
+
+        // Merges the transitions from the current state with those from `AnyState`, in order
+        let indices = |state: TypeId| {
+            self.transitions_by_state
+                .get(&state)
+                .map_or(&[][..], Vec::as_slice)
+                .iter()
+                .copied()
+                .peekable()
+        };
+        let (mut from_current, mut from_any) =
+            (indices(current), indices(TypeId::of::<AnyState>()));
+        let indices = std::iter::from_fn(move || match (from_current.peek(), from_any.peek()) {
+            (Some(state), Some(any)) if any < state => from_any.next(),
+            (Some(_), _) => from_current.next(),
+            (None, _) => from_any.next(),
+        });
+
+        let transitions = &mut self.transitions;
+        let triggers_checked = &mut self.triggers_checked;
+        let mut candidates = indices.filter_map(|index| {
+            let entry = &mut transitions[index];
+            if entry.except.contains(&current) {
+                return None;
+            }
+
+            // Delayed transitions aren't rescheduled while one is pending
+            if entry.delay.is_some() && pending {
+                return None;
+            }
+
+            if !entry.changes.is_empty() {
+                let this_run = world.read_change_tick();
+                if entry.last_checked.is_some_and(|last_run| {
+                    !entry
+                        .changes
+                        .iter()
+                        .any(|changed| changed(world, context, last_run, this_run))
+                }) {
+                    return None;
+                }
+
+                // Changes that commands make during this tick are seen the next time
+                entry.last_checked = Some(Tick::new(this_run.get().wrapping_sub(1)));
+            }
+
+            let next = {
+                #[cfg(feature = "trace")]
+                let _span = info_span!("check_trigger", trigger = entry.transition.trigger_name())
+                    .entered();
+                entry.transition.check(world, entity, context)
+            };
+            *triggers_checked += 1;
+            entry.last_result = Some(next.is_some());
+            let next = next?;
+            if !entry
+                .guards
+                .iter_mut()
+                .all(|guard| guard.check(world, context))
+            {
+                return None;
+            }
+
+            let next_state = match &next {
+                NextState::Go(_, next_state)
+                | NextState::Stored(next_state)
+                | NextState::Push(_, next_state) => *next_state,
+                // Pop transitions aren't taken if there's nothing to pop
+                NextState::Pop => stack_top?,
+            };
+
+            let self_transition = entry.self_transition.unwrap_or(default_self_transition);
+            (next_state != current || self_transition != SelfTransition::Ignore).then_some((
+                next,
+                self_transition,
+                entry.delay,
+                entry.weight,
+                index,
+            ))
+        });
+
+        let (next, self_transition, delay, _, index) = match selection {
+            Selection::Priority => {
+                let taken = candidates.next()?;
+                if warn_conflicts {
+                    let shadowed = candidates.map(|(.., index)| index).collect::<Vec<_>>();
+                    if !shadowed.is_empty() {
+                        self.warn_shadowed(entity, taken.4, &shadowed);
+                    }
+                }
+
+                taken
+            }
+            Selection::Weighted => {
+                let mut weighted = std::mem::take(&mut self.weighted);
+                weighted.extend(candidates);
+                if weighted.is_empty() {
+                    self.weighted = weighted;
+                    return None;
+                }
+
+                let total = weighted
+                    .iter()
+                    .map(|&(_, _, _, weight, _)| weight)
+                    .sum::<f32>();
+                let mut roll = self.random(entity) * total;
+                let index = weighted
+                    .iter()
+                    .position(|&(_, _, _, weight, _)| {
+                        roll -= weight;
+                        roll < 0.
+                    })
+                    .unwrap_or(0);
+                let taken = weighted.swap_remove(index);
+                weighted.clear();
+                self.weighted = weighted;
+                taken
+            }
+        };
+
+        let Some(Delay {
+            duration,
+            cancellable,
+        }) = delay
+        else {
+            return Some((next, self_transition, Some(index)));
+        };
+
+        self.pending = Some(PendingTransition {
+            from: current,
+            at: world.resource::<Time>().elapsed() + duration,
+            cancellable,
+            next,
+            self_transition,
+            index,
+        });
+
+        None
+    }
+
+    /// Runs all transitions until one is actually taken. If one is taken, logs the transition and
+    /// runs `on_enter/on_exit` triggers. Returns whether this machine, one of its regions, or the
+    /// current state's substates transitioned.
+    fn run(&mut self, world: &World, entity: Entity, commands: &mut Commands) -> bool {
+        #[cfg(feature = "trace")]
+        let _span = info_span!(
+            "state_machine",
+            name = self.name.as_deref().unwrap_or("unnamed"),
+            entity = ?entity,
+        )
+        .entered();
+
+        // Forced transitions are taken regardless of run conditions
+        if self.forced.is_none()
+            && !self.context.entity(world, entity).is_some_and(|context| {
+                self.run_conditions
+                    .iter_mut()
+                    .all(|condition| condition.check(world, context))
+            })
+        {
+            return false;
+        }
+
+        let mut transitioned = false;
+        for region in &mut self.regions {
+            transitioned |= region.run(world, entity, commands);
+        }
+
+        // This machine only contains `AnyState`, so it only exists for its regions
+        if !self.regions.is_empty() && self.states.len() == 1 {
+            return transitioned;
+        }
+
+        let mut states = self
+            .states
+            .keys()
+            .copied()
+            .filter(|&state| world.entity(entity).contains_type_id(state));
+        let (first, second) = (states.next(), states.next());
+        let more = states.next().is_some();
+
+        let current = match (first, second) {
+            (Some(current), None) => current,
+            (None, _) => {
+                if self.desync == Desync::Panic {
+                    panic!("Entity {} is in no state", self.display(entity));
+                }
+
+                warn!(
+                    "{} is in no state, so its state machine is skipped",
+                    self.display(entity),
+                );
+                return transitioned;
+            }
+            (Some(first), Some(second)) => {
+                let state = &self.states[&first].name;
+                let other = &self.states[&second].name;
+                // The state that was inserted outside of the machine's control
+                let untracked = match self.current {
+                    Some(current) if current == first => Some(second),
+                    Some(current) if current == second => Some(first),
+                    _ => None,
+                };
+
+                match (self.desync, untracked) {
+                    (Desync::Panic, _) => {
+                        panic!(
+                            "{} is in multiple states: {state} and {other}",
+                            self.display(entity),
+                        )
+                    }
+                    (Desync::Resync, Some(untracked)) if !more => {
+                        let tracked = &self.states[&self.current.unwrap()];
+                        (tracked.remove)(&mut commands.entity(entity));
+                        warn!(
+                            "{} resynced its state machine from {} to {}",
+                            self.display(entity),
+                            tracked.name,
+                            self.states[&untracked].name,
+                        );
+                        untracked
+                    }
+                    _ => {
+                        warn!(
+                            "{} is in multiple states: {state} and {other}, so its state \
+                            machine is skipped",
+                            self.display(entity),
+                        );
+                        return transitioned;
+                    }
+                }
+            }
+        };
+        self.current = Some(current);
+
+        let from = &self.states[&current];
+
+        // The entity was spawned in its current state, so enter it like any other state
+        if !self.entered {
+            self.entered = true;
+
+            if cfg!(debug_assertions) && self.validate {
+                let issues = self.validate_from(current);
+                if !issues.is_empty() {
+                    let issues = issues
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("; ");
+                    panic!(
+                        "{}'s state machine is invalid: {issues}",
+                        self.display(entity)
+                    );
+                }
+            }
+
+            if self.enter_initial {
+                for event in from.on_enter.iter() {
+                    event.trigger(entity, commands);
+                }
+            }
+        }
+
+        // A pending delayed transition is dropped if the entity left the state it was scheduled in
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.from != current)
+        {
+            self.pending = None;
+        }
+
+        let next = if let Some(forced) = self.forced.take() {
+            Some((forced, SelfTransition::Reenter, None))
+        } else if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| world.resource::<Time>().elapsed() >= pending.at)
+        {
+            let PendingTransition {
+                next,
+                self_transition,
+                index,
+                ..
+            } = self.pending.take().unwrap();
+            Some((next, self_transition, Some(index)))
+        } else if self.follow_only
+            || world.get::<AuthoritativeState>(entity).is_some()
+            || self
+                .pending
+                .as_ref()
+                .is_some_and(|pending| !pending.cancellable)
+        {
+            None
+        } else {
+            self.check_transitions(world, entity, current)
+        };
+
+        let Some((next, self_transition, index)) = next else {
+            // This machine isn't transitioning, so the current state's substates may
+            if let Some(substates) = &mut self.states.get_mut(&current).unwrap().substates {
+                transitioned |= substates.machine.run(world, entity, commands);
+            }
+
+            return transitioned;
+        };
+
+        // Taking a transition cancels any pending delayed transition
+        self.pending = None;
+        let from = &self.states[&current];
+
+        let (insert, next_state) = match next {
+            NextState::Go(insert, next_state) => (NextInsert::Boxed(insert), next_state),
+            NextState::Stored(next_state) => (
+                NextInsert::Stored(&*self.transitions[index.unwrap()].transition),
+                next_state,
+            ),
+            NextState::Push(insert, next_state) => {
+                self.stack
+                    .push((current, (from.clone_state)(entity, world)));
+                (NextInsert::Boxed(insert), next_state)
+            }
+            NextState::Pop => {
+                let (next_state, insert) = self.stack.pop().unwrap();
+                (NextInsert::Boxed(insert), next_state)
+            }
+        };
+
+        if self.chain.is_empty() {
+            self.chain.push(current);
+        }
+        self.chain.push(next_state);
+
+        if world.entity(entity).contains::<TransitionHistory>() {
+            let record = TransitionRecord {
+                from: current,
+                from_name: from.name,
+                to: next_state,
+                to_name: self.states[&next_state].name,
+                tick: world.read_change_tick(),
+                transition: index,
+            };
+
+            commands
+                .entity(entity)
+                .add(move |entity: Entity, world: &mut World| {
+                    if let Some(mut history) = world.get_mut::<TransitionHistory>(entity) {
+                        history.push(record);
+                    }
+                });
+        }
+
+        if next_state == current && self_transition == SelfTransition::Update {
+            insert.insert(&mut commands.entity(entity));
+
+            if let Some(level) = self.log_transitions {
+                log_at(
+                    level,
+                    format_args!(
+                        "{} updated its {} state",
+                        self.display_named(world, entity),
+                        from.name,
+                    ),
+                );
+            }
+
+            return true;
+        }
+
+        self.states
+            .get_mut(&current)
+            .unwrap()
+            .exit_substates(world, entity, commands, false);
+        let from = &self.states[&current];
+        for event in from.on_exit.iter() {
+            event.trigger(entity, commands);
+        }
+
+        if !self.on_transition.is_empty() {
+            let registry = world.resource::<AppTypeRegistry>().read();
+            let prev = registry
+                .get_type_data::<ReflectComponent>(current)
+                .and_then(|reflect| reflect.reflect(world.entity(entity)));
+            let next = insert.reflect(&registry);
+
+            for hook in &self.on_transition {
+                hook(&mut commands.entity(entity), prev, next);
+            }
+        }
+
+        insert.insert(&mut commands.entity(entity));
+        let to = &self.states[&next_state];
+        for event in to.on_enter.iter() {
+            event.trigger(entity, commands);
+        }
+
+        if let Some(level) = self.log_transitions {
+            log_at(
+                level,
+                format_args!(
+                    "{} transitioned from {} to {}",
+                    self.display_named(world, entity),
+                    from.name,
+                    to.name,
+                ),
+            );
+        }
+
+        self.states
+            .get_mut(&next_state)
+            .unwrap()
+            .enter_substates(entity, commands, false);
+
+        self.current = Some(next_state);
+        self.init_transitions = true;
+        true
+    }
+
+    /// Gets a random number in `[0, 1)`. The generator is seeded from the entity the first time,
+    /// unless it was seeded with [`StateMachine::set_seed`].
+    fn random(&mut self, entity: Entity) -> f32 {
+        if self.rng == 0 {
+            // SplitMix64, so similar entities get unrelated seeds
+            let mut seed = entity.to_bits().wrapping_add(0x9E37_79B9_7F4A_7C15);
+            seed = (seed ^ (seed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            seed = (seed ^ (seed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            self.rng = (seed ^ (seed >> 31)).max(1);
+        }
+
+        // Xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let bits = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 40;
+        bits as f32 / (1u64 << 24) as f32
+    }
+
+    /// Displays the entity, and the machine's name if it has one, for logs
+    pub(crate) fn display(&self, entity: Entity) -> MachineDisplay {
+        MachineDisplay(entity, None, self.name())
+    }
+
+    /// Like [`StateMachine::display`], but also shows the entity's [`Name`], if it has one
+    fn display_named<'a>(&'a self, world: &'a World, entity: Entity) -> MachineDisplay<'a> {
+        let name = world.get::<Name>(entity).map(Name::as_str);
+        MachineDisplay(entity, name, self.name())
+    }
+
+    /// Warns that the transition at index `taken` shadowed the transitions at the `shadowed`
+    /// indices
+    fn warn_shadowed(&self, entity: Entity, taken: usize, shadowed: &[usize]) {
+        let describe = |index: usize| {
+            let entry = &self.transitions[index];
+            let to = entry
+                .to
+                .map_or("its next state", |to| self.states[&to].name);
+            format!(
+                "{} to {to} (triggered by {})",
+                self.states[&entry.from].name,
+                entry.transition.trigger_name(),
+            )
+        };
+
+        let shadowed = shadowed
+            .iter()
+            .map(|&index| describe(index))
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(
+            "{} took the transition from {}, which shadowed: {shadowed}",
+            self.display(entity),
+            describe(taken),
+        );
+    }
+
+    /// Warns if the machine used its whole chain depth while transitioning in a loop, since it
+    /// would likely have kept transitioning
+    fn warn_chain_loop(&self, entity: Entity) {
+        let looped = self
+            .chain
+            .iter()
+            .enumerate()
+            .any(|(i, state)| self.chain[..i].contains(state));
+        if !looped {
+            return;
+        }
+
+        let chain = self
+            .chain
+            .iter()
+            .map(|state| self.states[state].name)
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        warn!(
+            "{} reached its chain depth of {} while transitioning in a loop: {chain}",
+            self.display(entity),
+            self.chain_depth,
+        );
+    }
+
+    /// Takes how many triggers this machine, its regions, and its substates checked since this was
+    /// last called
+    fn take_triggers_checked(&mut self) -> u32 {
+        std::mem::take(&mut self.triggers_checked)
+            + self
+                .regions
+                .iter_mut()
+                .map(StateMachine::take_triggers_checked)
+                .sum::<u32>()
+            + self
+                .states
+                .values_mut()
+                .filter_map(|state| state.substates.as_mut())
+                .map(|substates| substates.machine.take_triggers_checked())
+                .sum::<u32>()
+    }
+
+    /// When running the transition system, we replace all StateMachines in the world with their
+    /// stub.
+    fn stub(&self) -> Self {
+        Self {
+            states: default(),
+            transitions: default(),
+            transitions_by_state: default(),
+            last_transition: None,
+            on_transition: default(),
+            regions: default(),
+            stack: default(),
+            init_transitions: false,
+            log_transitions: None,
+            // Kept so state events sent while the machine is out of the world can get its name.
+            // It's shared, so this doesn't allocate.
+            name: self.name.clone(),
+            // Kept so `Done` markers are removed in the right schedule
+            fixed_update: self.fixed_update,
+            every_frame: false,
+            follow_only: false,
+            skipped: false,
+            warn_conflicts: false,
+            validate: false,
+            chain_depth: 0,
+            self_transition: default(),
+            pending: None,
+            run_conditions: default(),
+            current: None,
+            context: default(),
+            forced: None,
+            enter_initial: false,
+            entered: false,
+            selection: default(),
+            rng: 0,
+            chain: default(),
+            weighted: default(),
+            triggers_checked: 0,
+            desync: default(),
+            #[cfg(feature = "trait_query")]
+            state_traits: default(),
+        }
+    }
+}
+
+/// Builds a [`StateMachine`] from a compact description of its states, transitions, and events.
+/// States are unit structs, and each state used in a transition or event must be listed in
+/// `states`, so typos are caught at compile time. [`AnyState`] may be used without listing it. The
+/// `on_enter` and `on_exit` sections are optional. Expands to [`StateMachine`]'s builder methods,
+/// so call them on the result for anything else.
+///
+/// ```ignore
+/// let machine = state_machine! {
+///     states: [Idle, Chasing, Attacking],
+///     transitions: [
+///         Idle => Chasing: near_player,
+///         Chasing => Idle: far_from_player,
+///         Chasing => Attacking: in_range,
+///         Attacking => Chasing: done(None),
+///     ],
+///     on_enter: [Attacking => |entity| { entity.insert(Swing); }],
+/// };
+/// ```
+#[macro_export]
+macro_rules! state_machine {
+    (
+        states: [$($state:ident),* $(,)?],
+        transitions: [$($from:ident => $to:ident: $trigger:expr),* $(,)?]
+        $(, on_enter: [$($enter_state:ident => $on_enter:expr),* $(,)?])?
+        $(, on_exit: [$($exit_state:ident => $on_exit:expr),* $(,)?])?
+        $(,)?
+    ) => {{
+        // Each state that is used must be a variant of this enum, so an unlisted state is an error
+        #[allow(dead_code, non_camel_case_types, clippy::enum_variant_names)]
+        enum ListedStates {
+            AnyState,
+            $($state,)*
+        }
+
+        $(
+            let _ = ListedStates::$from;
+            let _ = ListedStates::$to;
+        )*
+        $($(let _ = ListedStates::$enter_state;)*)?
+        $($(let _ = ListedStates::$exit_state;)*)?
+
+        $crate::prelude::StateMachine::default()
+            $(.with_state::<$state>())*
+            $(.trans::<$from, _>($trigger, $to))*
+            $($(.on_enter::<$enter_state>($on_enter))*)?
+            $($(.on_exit::<$exit_state>($on_exit))*)?
+    }};
+}
+
+/// A factory that builds a new [`StateMachine`] for each entity, from a function that's defined
+/// once. It's cheap to clone, so store it in a [`Resource`] or share it between spawners, instead
+/// of repeating the machine's builder calls at every call site. The machine itself isn't shared:
+/// each instance is built from scratch, with its own transitions and triggers, since triggers keep
+/// per-entity state, such as `Local`s and event readers.
+#[derive(Clone, Resource)]
+pub struct MachineDefinition(Arc<dyn Fn() -> StateMachine + Send + Sync>);
+
+impl Debug for MachineDefinition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MachineDefinition")
+    }
+}
+
+impl MachineDefinition {
+    /// Creates a state machine definition from a function that builds the machine
+    pub fn new(build: impl Fn() -> StateMachine + Send + Sync + 'static) -> Self {
+        Self(Arc::new(build))
+    }
+
+    /// Builds a new instance of the state machine, to be inserted into an entity
+    pub fn instantiate(&self) -> StateMachine {
+        (self.0)()
+    }
+}
+
+/// [`MachineDefinition`]s by name, for [`SceneMachine`]s to refer to
+#[derive(Clone, Debug, Default, Resource)]
+pub struct MachineDefinitions(HashMap<String, MachineDefinition>);
+
+impl MachineDefinitions {
+    /// Adds a definition under the given name, replacing any definition with the same name
+    pub fn insert(&mut self, name: impl Into<String>, definition: MachineDefinition) -> &mut Self {
+        self.0.insert(name.into(), definition);
+        self
+    }
+
+    /// Gets the definition with the given name
+    pub fn get(&self, name: &str) -> Option<&MachineDefinition> {
+        self.0.get(name)
+    }
+
+    /// Iterates over the definitions and their names, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MachineDefinition)> {
+        self.0
+            .iter()
+            .map(|(name, definition)| (&**name, definition))
+    }
+}
+
+/// A serializable stand-in for a [`StateMachine`], for entities in scenes. When an entity gets this
+/// component, such as when a `.scn.ron` prefab is spawned, and doesn't have a `StateMachine`, it
+/// gets one instantiated from the [`MachineDefinition`] with the given name in
+/// [`MachineDefinitions`]. Save the entity's states along with it, and with the `snapshot` feature,
+/// its [`MachineSnapshot`] too, to restore its runtime data.
+#[derive(Clone, Component, Debug, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct SceneMachine(pub String);
+
+fn instantiate_scene_machines(
+    mut commands: Commands,
+    machines: Query<(Entity, &SceneMachine), (Added<SceneMachine>, Without<StateMachine>)>,
+    definitions: Res<MachineDefinitions>,
+) {
+    for (entity, SceneMachine(name)) in &machines {
+        match definitions.get(name) {
+            Some(definition) => {
+                commands.entity(entity).insert(definition.instantiate());
+            }
+            None => warn!("no state machine definition named {name} for entity {entity:?}"),
+        }
+    }
+}
+
+/// Builds [`StateMachine`]s. Unlike a `StateMachine`, which is also a component, a builder may be
+/// stored, cloned, and extended, so a function can return a builder that's parameterized, such as
+/// with an enemy's aggro radius, and build it into each entity's state machine.
+#[derive(Clone, Default)]
+pub struct MachineBuilder {
+    steps: Vec<Arc<dyn Fn(StateMachine) -> StateMachine + Send + Sync>>,
+}
+
+impl Debug for MachineBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MachineBuilder")
+            .field("steps", &self.steps.len())
+            .finish()
+    }
+}
+
+impl MachineBuilder {
+    /// Creates an empty builder
+    pub fn new() -> Self {
+        default()
+    }
+
+    /// Adds a transition. See [`StateMachine::trans`].
+    pub fn trans<S: EntityState, Marker>(
+        self,
+        trigger: impl 'static + IntoTrigger<Marker> + Clone + Send + Sync,
+        state: impl Clone + Component,
+    ) -> Self {
+        self.with(move |machine| machine.trans::<S, _>(trigger.clone(), state.clone()))
+    }
+
+    /// Adds a step that calls any of [`StateMachine`]'s builder methods, like
+    /// `builder.with(|machine| machine.on_enter::<Idle>(play_idle_animation))`. The step runs
+    /// every time the builder is built.
+    pub fn with(
+        mut self,
+        step: impl Fn(StateMachine) -> StateMachine + Send + Sync + 'static,
+    ) -> Self {
+        self.steps.push(Arc::new(step));
+        self
+    }
+
+    /// Adds another builder's steps to this one. See [`StateMachine::merge`] to merge state
+    /// machines that are already built.
+    pub fn merge(mut self, other: MachineBuilder) -> Self {
+        self.steps.extend(other.steps);
+        self
+    }
+
+    /// Builds the state machine, without an initial state
+    pub fn build_machine(&self) -> StateMachine {
+        self.steps
+            .iter()
+            .fold(StateMachine::default(), |machine, step| step(machine))
+    }
+
+    /// Builds the state machine, and returns it with the given initial state, to be inserted into
+    /// an entity
+    pub fn build<S: Clone + Component>(&self, initial: S) -> (StateMachine, S) {
+        (self.build_machine().with_state::<S>(), initial)
+    }
+}
+
+/// Extension trait for [`EntityCommands`] to control an entity's [`StateMachine`]
+pub trait StateMachineCommandsExt {
+    /// Makes the entity's state machine transition to the given state. Prefer this to inserting
+    /// and removing states yourself, which skips events and desyncs the machine. See
+    /// [`StateMachine::force_state`].
+    fn set_state(&mut self, state: impl Clone + Component) -> &mut Self;
+}
+
+impl StateMachineCommandsExt for EntityCommands<'_, '_, '_> {
+    fn set_state(&mut self, state: impl Clone + Component) -> &mut Self {
+        let entity = self.id();
+        self.commands().add(move |world: &mut World| {
+            if let Some(mut machine) = world.get_mut::<StateMachine>(entity) {
+                machine.force_state(state);
+            }
+        });
+        self
+    }
+}
+
+/// System parameter for controlling state machines from gameplay systems, without changing their
+/// components directly
+#[derive(SystemParam)]
+pub struct StateMachines<'w, 's> {
+    commands: Commands<'w, 's>,
+    machines: Query<'w, 's, (&'static StateMachine, Has<Paused>)>,
+}
+
+impl<'w, 's> StateMachines<'w, 's> {
+    /// Gets the entity's state machine
+    pub fn get(&self, entity: Entity) -> Option<&StateMachine> {
+        self.machines.get(entity).ok().map(|(machine, _)| machine)
+    }
+
+    /// Checks whether the entity's state machine is in the given state. See
+    /// [`StateMachine::is_in_state`].
+    pub fn is_in_state<S: Clone + Component>(&self, entity: Entity) -> bool {
+        self.get(entity)
+            .is_some_and(|machine| machine.is_in_state::<S>())
+    }
+
+    /// Gets the type name of the entity's current state. See
+    /// [`StateMachine::current_state_name`].
+    pub fn current_state_name(&self, entity: Entity) -> Option<&str> {
+        self.get(entity)?.current_state_name()
+    }
+
+    /// Makes the entity's state machine transition to the given state. See
+    /// [`StateMachineCommandsExt::set_state`].
+    pub fn force(&mut self, entity: Entity, state: impl Clone + Component) {
+        self.commands.entity(entity).set_state(state);
+    }
+
+    /// Pauses the entity's state machine. See [`Paused`].
+    pub fn pause(&mut self, entity: Entity) {
+        self.commands.entity(entity).insert(Paused);
+    }
+
+    /// Resumes the entity's state machine, if it's paused
+    pub fn resume(&mut self, entity: Entity) {
+        self.commands.entity(entity).remove::<Paused>();
+    }
+
+    /// Checks whether the entity's state machine is paused
+    pub fn is_paused(&self, entity: Entity) -> bool {
+        self.machines.get(entity).is_ok_and(|(_, paused)| paused)
+    }
+}
+
+/// Query for an entity's state of type `S`, which only gives the state if it's the entity's
+/// [`StateMachine`]'s current state. This guards against stale state components, such as one that
+/// was inserted outside of the machine's control. Use it like `Query<CurrentState<Jumping>>`, and
+/// get the state with `CurrentStateItem::get`.
+#[derive(WorldQuery)]
+pub struct CurrentState<'w, S: Clone + Component> {
+    state: &'w S,
+    machine: Option<&'w StateMachine>,
+}
+
+impl<'w, S: Clone + Component> CurrentStateItem<'w, S> {
+    /// Gets the state, if it's the current state. If the entity doesn't have a [`StateMachine`], or
+    /// its machine hasn't run yet, the state is assumed to be current.
+    pub fn get(&self) -> Option<&'w S> {
+        match self.machine.and_then(StateMachine::current_state_id) {
+            Some(current) => (current == TypeId::of::<S>()).then_some(self.state),
+            None => Some(self.state),
+        }
+    }
+}
+
+/// Run condition that's true if the given entity is in a state of type `S`. Useful for gating
+/// systems on a particular entity's state, such as the player's. Also works as a trigger.
+pub fn in_entity_state<S: Clone + Component>(
+    entity: Entity,
+) -> impl FnMut(Query<(), With<S>>) -> bool + Clone {
+    move |states: Query<(), With<S>>| states.contains(entity)
+}
+
+/// Run condition that's true if any entity is in a state of type `S`. Also works as a trigger.
+pub fn any_in_state<S: Clone + Component>(states: Query<(), With<S>>) -> bool {
+    !states.is_empty()
+}
+
+/// Label that distinguishes multiple state machines on the same entity. Automatically implemented.
+/// See [`LabeledMachine`].
+pub trait MachineLabel: 'static + Send + Sync {}
+
+impl<T: 'static + Send + Sync> MachineLabel for T {}
+
+/// A [`StateMachine`] with a label, so an entity may have several state machines that transition
+/// independently, such as `LabeledMachine<Movement>` and `LabeledMachine<Combat>`, with one
+/// component for each. Each label must be added to the app with [`labeled_machine_plugin`], and the
+/// machines on an entity must not share any states. Alternatively, see
+/// [`StateMachine::with_region`].
+#[derive(Component, Deref, DerefMut)]
+pub struct LabeledMachine<L: MachineLabel> {
+    #[deref]
+    machine: StateMachine,
+    label: PhantomData<L>,
+}
+
+impl<L: MachineLabel> LabeledMachine<L> {
+    /// Labels the given state machine
+    pub fn new(machine: StateMachine) -> Self {
+        Self {
+            machine,
+            label: PhantomData,
+        }
+    }
+}
+
+/// Adds [`LabeledMachine`]s with the given label to the app. Add it after [`StateMachinePlugin`],
+/// once for each label. It runs in the same schedule as [`StateMachinePlugin`].
+pub fn labeled_machine_plugin<L: MachineLabel>(app: &mut App) {
+    let schedule = app.world.get_resource::<MachineSchedule>().map_or_else(
+        || Box::new(PostUpdate) as BoxedScheduleLabel,
+        |schedule| schedule.0.dyn_clone(),
+    );
+    app.add_systems(
+        schedule.dyn_clone(),
+        transition_machines::<LabeledMachine<L>, false>.in_set(StateSet::Transition),
+    )
+    .add_systems(
+        schedule,
+        collect_done_machines::<LabeledMachine<L>, false>
+            .in_set(StateSet::RemoveDoneMarkers)
+            .before(remove_done_markers::<false>),
+    )
+    .add_systems(
+        FixedUpdate,
+        transition_machines::<LabeledMachine<L>, true>.in_set(StateSet::Transition),
+    )
+    .add_systems(
+        FixedUpdate,
+        collect_done_machines::<LabeledMachine<L>, true>
+            .in_set(StateSet::RemoveDoneMarkers)
+            .before(remove_done_markers::<true>),
+    );
+}
+
+/// A component that contains a [`StateMachine`]
+pub(crate) trait MachineComponent: Component {
+    fn machine(&self) -> &StateMachine;
+    fn machine_mut(&mut self) -> &mut StateMachine;
+}
+
+impl MachineComponent for StateMachine {
+    fn machine(&self) -> &StateMachine {
+        self
+    }
+
+    fn machine_mut(&mut self) -> &mut StateMachine {
+        self
+    }
+}
+
+impl<L: MachineLabel> MachineComponent for LabeledMachine<L> {
+    fn machine(&self) -> &StateMachine {
+        &self.machine
+    }
+
+    fn machine_mut(&mut self) -> &mut StateMachine {
+        &mut self.machine
+    }
+}
+
+/// Which state machines a transition system evaluates next. See [`MachineInterval`] and
+/// [`MachineBudget`].
+#[derive(Default)]
+pub(crate) struct Evaluation {
+    /// Index among the due machines of the first machine to evaluate this frame
+    cursor: usize,
+    /// Frames since the system first ran, wrapping
+    frame: u32,
+}
+
+/// Runs all transitions on all entities, except for those whose machines run in `FixedUpdate`
+pub(crate) fn transition(
+    world: &mut World,
+    system_state: &mut SystemState<ParallelCommands>,
+    machine_query: &mut QueryState<(Entity, &mut StateMachine), Without<Paused>>,
+    evaluation: Local<Evaluation>,
+) {
+    transition_machines::<_, false>(world, system_state, machine_query, evaluation);
+}
+
+/// Runs all transitions of the state machines in the given component on all entities. `FIXED` is
+/// whether to run the machines that run in `FixedUpdate` or the rest. See
+/// [`StateMachine::set_fixed_update`].
+fn transition_machines<M: MachineComponent, const FIXED: bool>(
+    world: &mut World,
+    system_state: &mut SystemState<ParallelCommands>,
+    machine_query: &mut QueryState<(Entity, &mut M), Without<Paused>>,
+    mut evaluation: Local<Evaluation>,
+) {
+    let start_time = Instant::now();
+
+    // With an interval, each entity's machine is due on a different frame
+    let interval = world
+        .get_resource::<MachineInterval>()
+        .map_or(1, |&MachineInterval(interval)| interval.max(1));
+    let frame = evaluation.frame;
+    evaluation.frame = frame.wrapping_add(1);
+    let due = |entity: Entity, machine: &StateMachine| {
+        machine.fixed_update == FIXED
+            && (machine.every_frame || entity.index().wrapping_add(frame) % interval == 0)
+    };
+
+    // With a budget, only evaluate the due machines starting at the cursor, wrapping around
+    let (budget, start, len) = match world.get_resource::<MachineBudget>() {
+        Some(&MachineBudget(budget)) => {
+            let len = machine_query
+                .iter(world)
+                .filter(|(entity, machine)| due(*entity, machine.machine()))
+                .count();
+            let start = match len {
+                0 => 0,
+                _ => evaluation.cursor % len,
+            };
+            evaluation.cursor = start.saturating_add(budget);
+            (budget, start, len)
+        }
+        None => (usize::MAX, 0, 0),
+    };
+
+    // Pull the machines out of the world so we can invoke mutable methods on them. The alternative
+    // would be to wrap the entire `StateMachine` in an `Arc<Mutex>`, but that would complicate the
+    // API surface and you wouldn't be able to do anything more anyway (since you'd need to lock the
+    // mutex anyway).
+    let mut i = 0;
+    let mut borrowed_machines: Vec<(Entity, StateMachine)> = machine_query
+        .iter_mut(world)
+        .filter(|(_, machine)| machine.machine().fixed_update == FIXED)
+        .filter_map(|(entity, mut machine)| {
+            let skipped = !due(entity, machine.machine()) || {
+                i += 1;
+                (i - 1 + len - start) % len.max(1) >= budget
+            };
+            if machine.machine().skipped != skipped {
+                machine.machine_mut().skipped = skipped;
+            }
+            if skipped {
+                return None;
+            }
+
+            let machine = machine.machine_mut();
+            machine.chain.clear();
+            let stub = machine.stub();
+            Some((entity, std::mem::replace(machine, stub)))
+        })
+        .collect();
+
+    let deterministic = world.contains_resource::<DeterministicMachines>();
+    if deterministic {
+        borrowed_machines.sort_unstable_by_key(|&(entity, _)| entity);
+    }
+
+    // Machines may chain multiple transitions in a frame, so we keep running the machines that
+    // transitioned, applying the commands in between
+    let mut running = borrowed_machines.iter_mut().collect::<Vec<_>>();
+    let mut pass = 0;
+    let mut transitions = 0;
+    loop {
+        // `world` is mutable here, since initialization requires mutating the world
+        for (entity, machine) in running.iter_mut() {
+            machine.init_transitions(world, *entity);
+        }
+
+        // `world` is not mutated here; the state machines are not in the world, and the Commands
+        // don't mutate until application
+        let par_commands = system_state.get(world);
+        let run = |(entity, machine): &mut &mut (Entity, StateMachine)| {
+            par_commands.command_scope(|mut commands| machine.run(world, *entity, &mut commands))
+        };
+
+        // Later passes often only run one machine, which isn't worth spawning tasks for. In
+        // parallel, each thread queues its own commands, so the order they're applied in varies.
+        let transitioned = match running.len() {
+            _ if deterministic => running.iter_mut().map(run).collect::<Vec<_>>(),
+            0..=1 => running.iter_mut().map(run).collect::<Vec<_>>(),
+            _ => {
+                let task_pool = ComputeTaskPool::get();
+                // chunk size of None means to automatically pick
+                running
+                    .par_splat_map_mut(task_pool, None, |chunk| {
+                        chunk.iter_mut().map(run).collect::<Vec<_>>()
+                    })
+                    .into_iter()
+                    .flatten()
+                    .collect()
+            }
+        };
+
+        transitions += transitioned
+            .iter()
+            .filter(|&&transitioned| transitioned)
+            .count() as u32;
+        let mut transitioned = transitioned.into_iter();
+        running.retain(|(entity, machine)| {
+            let transitioned = transitioned.next().unwrap();
+            if transitioned && pass == machine.chain_depth && machine.chain_depth > 0 {
+                machine.warn_chain_loop(*entity);
+            }
+
+            transitioned && pass < machine.chain_depth
+        });
+        if running.is_empty() {
+            break;
+        }
+
+        // The next pass must see this pass's transitions
+        system_state.apply(world);
+        running.retain(|(entity, _)| world.get_entity(*entity).is_some());
+        pass += 1;
+    }
+
+    let machines = borrowed_machines.len() as u32;
+    let mut triggers = 0;
+
+    // put the borrowed machines back. The commands applied between passes may have despawned some
+    // entities or paused their machines.
+    for (entity, mut machine) in borrowed_machines {
+        triggers += machine.take_triggers_checked();
+        if let Some(mut stub) = world.get_mut::<M>(entity) {
+            let stub = stub.machine_mut();
+            // Hooks may have forced a state between passes, which landed on the stub
+            if let Some(forced) = stub.forced.take() {
+                for (id, state) in stub.states.drain() {
+                    machine.states.entry(id).or_insert(state);
+                }
+                machine.forced = Some(forced);
+            }
+            *stub = machine;
+        }
+    }
+
+    // necessary to actually *apply* the commands we've enqueued
+    system_state.apply(world);
+
+    if let Some(mut stats) = world.get_resource_mut::<MachineStats>() {
+        stats.transitions += transitions;
+        stats.machines += machines;
+        stats.triggers += triggers;
+        stats.time += start_time.elapsed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use bevy::ecs::system::{CommandQueue, RunSystemOnce};
+
+    use crate::trigger::DoneMachines;
+
+    use super::*;
 
     // Test states to transition between.
-    #[derive(Component, Clone)]
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component)]
     struct StateOne;
-    #[derive(Component, Clone)]
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component)]
     struct StateTwo;
-    #[derive(Component, Clone)]
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component)]
     struct StateThree;
 
-    #[derive(Resource)]
-    struct SomeResource;
+    #[derive(Resource)]
+    struct SomeResource;
+
+    /// Triggers when `SomeResource` is present
+    fn resource_present(res: Option<Res<SomeResource>>) -> bool {
+        res.is_some()
+    }
+
+    #[test]
+    fn test_sets_initial_state() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+        let machine = StateMachine::default().with_state::<StateOne>();
+        let entity = app.world.spawn((machine, StateOne)).id();
+        app.update();
+        // should have moved to state two
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "StateMachine should have the initial component"
+        );
+    }
+
+    #[test]
+    fn test_machine() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateTwo, _>(resource_present, StateThree);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        assert!(app.world.get::<StateOne>(entity).is_some());
+
+        app.update();
+        // should have moved to state two
+        assert!(app.world.get::<StateOne>(entity).is_none());
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        app.update();
+        // not yet...
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(app.world.get::<StateThree>(entity).is_none());
+
+        app.world.insert_resource(SomeResource);
+        app.update();
+        // okay, *now*
+        assert!(app.world.get::<StateTwo>(entity).is_none());
+        assert!(app.world.get::<StateThree>(entity).is_some());
+    }
+
+    #[test]
+    fn test_self_transition() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let entity = app
+            .world
+            .spawn((
+                StateMachine::default().trans::<StateOne, _>(always, StateOne),
+                StateOne,
+            ))
+            .id();
+        app.update();
+        // the sort of bug this is trying to catch: if you insert the new state and then remove the
+        // old state, self-transitions will leave you without the state
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "transitioning from a state to itself should work"
+        );
+    }
+
+    #[test]
+    fn test_on_transition() {
+        let mut app = App::new();
+        app.register_type::<StateOne>()
+            .register_type::<StateTwo>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .on_transition(|entity, prev, next| {
+                assert!(prev.is_some_and(|prev| prev.is::<StateOne>()));
+                assert!(next.is_some_and(|next| next.is::<StateTwo>()));
+                entity.commands().insert_resource(SomeResource);
+            });
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(
+            app.world.contains_resource::<SomeResource>(),
+            "transition hooks should run"
+        );
+    }
+
+    #[test]
+    fn test_stored_states() {
+        #[derive(Clone, Component)]
+        struct Speed(f32);
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, Speed(4.5))
+            .trans::<Speed, _>(always, StateOne);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        for _ in 0..2 {
+            app.update();
+            assert_eq!(app.world.get::<Speed>(entity).unwrap().0, 4.5);
+
+            app.update();
+            assert!(app.world.get::<StateOne>(entity).is_some());
+        }
+    }
+
+    #[test]
+    fn test_state_events() {
+        let mut app = App::new();
+        app.add_event::<StateEntered<StateOne>>()
+            .add_event::<StateEntered<StateTwo>>()
+            .add_event::<StateExited<StateOne>>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .send_events::<StateOne>()
+            .send_events::<StateTwo>();
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        let exited = app.world.resource::<Events<StateExited<StateOne>>>();
+        assert_eq!(
+            exited
+                .get_reader()
+                .read(exited)
+                .map(|event| event.entity)
+                .collect::<Vec<_>>(),
+            [entity],
+        );
+        let entered = app.world.resource::<Events<StateEntered<StateTwo>>>();
+        assert_eq!(
+            entered
+                .get_reader()
+                .read(entered)
+                .map(|event| event.entity)
+                .collect::<Vec<_>>(),
+            [entity],
+        );
+    }
+
+    #[test]
+    fn test_substates() {
+        #[derive(Component, Clone)]
+        struct SubstateOne;
+        #[derive(Component, Clone)]
+        struct SubstateTwo;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(resource_present, StateTwo)
+            .trans::<StateTwo, _>(always, StateOne)
+            .with_substates::<StateOne, _>(
+                StateMachine::default().trans::<SubstateOne, _>(always, SubstateTwo),
+                SubstateOne,
+            );
+        let entity = app.world.spawn((machine, StateOne, SubstateOne)).id();
+
+        app.update();
+        // the substate should transition within the parent state
+        assert!(app.world.get::<StateOne>(entity).is_some());
+        assert!(app.world.get::<SubstateTwo>(entity).is_some());
+
+        app.world.insert_resource(SomeResource);
+        app.update();
+        // exiting the parent state should exit the substate too
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(app.world.get::<SubstateTwo>(entity).is_none());
+
+        app.world.remove_resource::<SomeResource>();
+        app.update();
+        // entering the parent state should enter the initial substate
+        assert!(app.world.get::<StateOne>(entity).is_some());
+        assert!(app.world.get::<SubstateOne>(entity).is_some());
+    }
+
+    #[test]
+    fn test_regions() {
+        #[derive(Component, Clone)]
+        struct RegionStateOne;
+        #[derive(Component, Clone)]
+        struct RegionStateTwo;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .with_region(StateMachine::default().trans::<StateOne, _>(always, StateTwo))
+            .with_region(
+                StateMachine::default()
+                    .trans::<RegionStateOne, _>(resource_present, RegionStateTwo),
+            );
+        let entity = app.world.spawn((machine, StateOne, RegionStateOne)).id();
+
+        app.update();
+        // each region should transition independently
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(app.world.get::<RegionStateOne>(entity).is_some());
+
+        app.world.insert_resource(SomeResource);
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(app.world.get::<RegionStateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_push_pop() {
+        #[derive(Component, Clone)]
+        struct Counter(u32);
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans_push::<Counter, _>(resource_present, StateOne)
+            .trans_pop::<StateOne, _>(resource_present.not());
+        let entity = app.world.spawn((machine, Counter(5))).id();
+
+        app.world.insert_resource(SomeResource);
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+
+        app.world.remove_resource::<SomeResource>();
+        app.update();
+        // should have returned to the pushed state, with its data
+        assert!(app.world.get::<StateOne>(entity).is_none());
+        assert_eq!(
+            app.world.get::<Counter>(entity).map(|counter| counter.0),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        #[derive(Component, Clone)]
+        struct SubstateOne;
+        #[derive(Component, Clone)]
+        struct SubstateTwo;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(resource_present, StateTwo)
+            .trans::<StateTwo, _>(resource_present.not(), StateOne)
+            .with_substates::<StateOne, _>(
+                StateMachine::default().trans::<SubstateOne, _>(always, SubstateTwo),
+                SubstateOne,
+            )
+            .with_history::<StateOne>(History::Shallow);
+        let entity = app.world.spawn((machine, StateOne, SubstateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<SubstateTwo>(entity).is_some());
+
+        app.world.insert_resource(SomeResource);
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        app.world.remove_resource::<SomeResource>();
+        app.update();
+        // should have resumed the last substate instead of the initial one
+        assert!(app.world.get::<StateOne>(entity).is_some());
+        assert!(app.world.get::<SubstateOne>(entity).is_none());
+        assert!(app.world.get::<SubstateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_trans_from_any_except() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans_from_any_except::<(StateTwo,), _>(always, StateThree);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        // earlier transitions have priority
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        app.update();
+        // `StateTwo` is excepted
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateOne, _>(always, StateThree)
+            .priority(1);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateThree>(entity).is_some(),
+            "transitions with higher priority should be checked first"
+        );
+    }
+
+    #[test]
+    fn test_chain_depth() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateTwo, _>(always, StateThree)
+            .set_chain_depth(1);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateThree>(entity).is_some(),
+            "chained transitions should be taken in the same frame"
+        );
+    }
+
+    #[test]
+    fn test_self_transition_policy() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateOne)
+            .self_transition(SelfTransition::Update)
+            .on_enter::<StateOne>(|entity| entity.commands().insert_resource(SomeResource))
+            .set_enter_initial(false);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+        assert!(
+            !app.world.contains_resource::<SomeResource>(),
+            "updating a state shouldn't enter it"
+        );
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateOne)
+            .trans::<StateOne, _>(always, StateTwo)
+            .set_self_transition(SelfTransition::Ignore);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateTwo>(entity).is_some(),
+            "ignored self-transitions shouldn't be taken"
+        );
+    }
+
+    #[test]
+    fn test_guard() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .guard(resource_present);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "transitions shouldn't be taken while their guards fail"
+        );
+
+        app.world.insert_resource(SomeResource);
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_after_delay() {
+        let mut app = App::new();
+        app.init_resource::<Time>().add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .after_delay(Duration::from_secs(1));
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "delayed transitions shouldn't be taken before their delay"
+        );
+
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs(1));
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_trans_after() {
+        let mut app = App::new();
+        app.init_resource::<Time>().add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans_after::<StateOne>(Duration::from_secs(1), StateTwo)
+            .trans::<StateTwo, _>(always, StateOne);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs(1));
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        app.update();
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "the timer should restart when the state is reentered"
+        );
+    }
+
+    #[test]
+    fn test_paused() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+        let entity = app.world.spawn((machine, StateOne, Paused)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "paused machines shouldn't transition"
+        );
+
+        app.world.entity_mut(entity).remove::<Paused>();
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_state_machines_enabled() {
+        let mut app = App::new();
+        machine_plugin(&mut app, Box::new(Update));
+        app.insert_resource(StateMachinesEnabled(false));
+
+        let machine = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "disabled machines shouldn't transition"
+        );
+
+        app.insert_resource(StateMachinesEnabled(true));
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_machine_budget() {
+        let mut app = App::new();
+        app.insert_resource(MachineBudget(2))
+            .add_systems(Update, transition);
+
+        let entities = (0..3)
+            .map(|_| {
+                let machine = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+                app.world.spawn((machine, StateOne)).id()
+            })
+            .collect::<Vec<_>>();
+
+        let transitioned = |app: &App| {
+            entities
+                .iter()
+                .filter(|&&entity| app.world.get::<StateTwo>(entity).is_some())
+                .count()
+        };
+
+        app.update();
+        assert_eq!(transitioned(&app), 2);
+        app.update();
+        assert_eq!(
+            transitioned(&app),
+            3,
+            "skipped machines should run next frame"
+        );
+    }
+
+    #[test]
+    fn test_machine_interval() {
+        let mut app = App::new();
+        app.insert_resource(MachineInterval(2))
+            .add_systems(Update, transition);
+
+        let spawn = |app: &mut App, every_frame| {
+            let machine = StateMachine::default()
+                .trans::<StateOne, _>(always, StateTwo)
+                .trans::<StateTwo, _>(always, StateOne)
+                .set_every_frame(every_frame);
+            app.world.spawn((machine, StateOne)).id()
+        };
+        let staggered = [spawn(&mut app, false), spawn(&mut app, false)];
+        let every_frame = spawn(&mut app, true);
+
+        app.update();
+        assert_eq!(
+            staggered
+                .iter()
+                .filter(|&&entity| app.world.get::<StateTwo>(entity).is_some())
+                .count(),
+            1,
+            "staggered machines should run on different frames"
+        );
+        assert!(app.world.get::<StateTwo>(every_frame).is_some());
+
+        app.update();
+        assert!(staggered
+            .iter()
+            .all(|&entity| app.world.get::<StateTwo>(entity).is_some()));
+        assert!(app.world.get::<StateOne>(every_frame).is_some());
+    }
+
+    #[derive(Resource)]
+    struct Flag(bool);
+
+    #[test]
+    fn test_when_resource_changed() {
+        let mut app = App::new();
+        app.insert_resource(Flag(false))
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(|flag: Res<Flag>| flag.0, StateTwo)
+            .when_resource_changed::<Flag>();
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        app.world.resource_mut::<Flag>().bypass_change_detection().0 = true;
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "triggers shouldn't be checked if nothing changed"
+        );
+
+        app.world.resource_mut::<Flag>().set_changed();
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    #[derive(Default, Resource)]
+    struct Entered(Vec<Entity>);
+
+    #[test]
+    fn test_deterministic_machines() {
+        let mut app = App::new();
+        app.init_resource::<DeterministicMachines>()
+            .init_resource::<Entered>()
+            .add_systems(Update, transition);
+
+        let machine = || {
+            StateMachine::default()
+                .trans::<StateOne, _>(always, StateTwo)
+                .on_enter::<StateTwo>(|entity| {
+                    let id = entity.id();
+                    entity.commands().add(move |world: &mut World| {
+                        world.resource_mut::<Entered>().0.push(id);
+                    });
+                })
+        };
+
+        #[derive(Component)]
+        struct MarkerOne;
+        #[derive(Component)]
+        struct MarkerTwo;
+
+        // Moves the first entity into a newer archetype, so the query visits the second one first
+        let first = app.world.spawn((machine(), StateOne)).id();
+        let second = app.world.spawn((machine(), StateOne, MarkerOne)).id();
+        app.world.entity_mut(first).insert(MarkerTwo);
+
+        app.update();
+        assert_eq!(app.world.resource::<Entered>().0, [first, second]);
+    }
+
+    #[derive(Resource)]
+    struct Active(bool);
+
+    #[test]
+    fn test_run_if() {
+        let mut app = App::new();
+        app.insert_resource(Active(false))
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .run_if(|In(_): In<Entity>, active: Res<Active>| active.0);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "machines should be skipped when their run condition doesn't occur"
+        );
+
+        app.world.resource_mut::<Active>().0 = true;
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_runtime_mutation() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        let mut machine = app.world.get_mut::<StateMachine>(entity).unwrap();
+        assert!(machine.remove_trans::<StateOne, StateTwo>());
+        assert!(!machine.remove_trans::<StateOne, StateTwo>());
+        machine.add_trans::<StateOne, _>(always, StateThree);
+
+        app.update();
+        assert!(app.world.get::<StateThree>(entity).is_some());
+
+        app.world
+            .get_mut::<StateMachine>(entity)
+            .unwrap()
+            .edit(|machine| machine.trans::<StateThree, _>(always, StateOne));
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+    }
+
+    #[test]
+    fn test_remove_pending_trans() {
+        let mut app = App::new();
+        app.init_resource::<Time>().add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(resource_exists::<SomeResource>(), StateThree)
+            .trans::<StateOne, _>(always, StateTwo)
+            .after_delay(Duration::from_secs(1));
+        let entity = app
+            .world
+            .spawn((machine, StateOne, TransitionHistory::new(1)))
+            .id();
+
+        app.update();
+        let mut machine = app.world.get_mut::<StateMachine>(entity).unwrap();
+        assert!(machine.remove_trans::<StateOne, StateThree>());
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs(1));
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        let history = app.world.get::<TransitionHistory>(entity).unwrap();
+        assert_eq!(
+            history.last().unwrap().transition,
+            Some(0),
+            "the pending transition's index should follow removed transitions"
+        );
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .after_delay(Duration::from_secs(1));
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        let mut machine = app.world.get_mut::<StateMachine>(entity).unwrap();
+        assert!(machine.remove_trans::<StateOne, StateTwo>());
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs(1));
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "removing a pending transition should cancel it"
+        );
+    }
+
+    #[test]
+    fn test_current_state() {
+        let mut app = App::new();
+        app.init_resource::<AppTypeRegistry>()
+            .register_type::<StateOne>()
+            .register_type::<StateTwo>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        let machine = app.world.get::<StateMachine>(entity).unwrap();
+        assert_eq!(machine.current_state_id(), None);
+
+        app.update();
+        let machine = app.world.get::<StateMachine>(entity).unwrap();
+        assert_eq!(machine.current_state_id(), Some(TypeId::of::<StateTwo>()));
+        assert_eq!(machine.current_state_name(), Some(type_name::<StateTwo>()));
+        assert!(machine
+            .current_state(&app.world, entity)
+            .is_some_and(|state| state.is::<StateTwo>()));
+    }
+
+    #[test]
+    fn test_is_in_state() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .with_state::<StateThree>();
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        let machine = app.world.get::<StateMachine>(entity).unwrap();
+        assert!(machine.is_in_state::<StateTwo>());
+        assert!(!machine.is_in_state::<StateOne>());
+
+        let mut states = machine.states().map(|(id, _)| id).collect::<Vec<_>>();
+        states.sort();
+        let mut expected = vec![
+            TypeId::of::<StateOne>(),
+            TypeId::of::<StateTwo>(),
+            TypeId::of::<StateThree>(),
+        ];
+        expected.sort();
+        assert_eq!(states, expected);
+    }
+
+    #[test]
+    fn test_machine_definition() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let definition = MachineDefinition::new(|| {
+            StateMachine::default().trans::<StateOne, _>(always, StateTwo)
+        });
+        let entities = [(); 2].map(|_| app.world.spawn((definition.instantiate(), StateOne)).id());
+
+        app.update();
+        for entity in entities {
+            assert!(app.world.get::<StateTwo>(entity).is_some());
+        }
+    }
+
+    #[test]
+    fn test_scene_machine() {
+        let mut app = App::new();
+        app.init_resource::<MachineDefinitions>()
+            .add_systems(Update, (instantiate_scene_machines, transition).chain());
+        app.world.resource_mut::<MachineDefinitions>().insert(
+            "one_to_two",
+            MachineDefinition::new(|| {
+                StateMachine::default().trans::<StateOne, _>(always, StateTwo)
+            }),
+        );
+
+        // As if spawned from a scene
+        let entity = app
+            .world
+            .spawn((SceneMachine("one_to_two".to_string()), StateOne))
+            .id();
+
+        app.update();
+        assert!(app.world.get::<StateMachine>(entity).is_some());
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    #[test]
+    fn test_machine_builder() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let builder = MachineBuilder::new().trans::<StateOne, _>(always, StateTwo);
+        let extended = builder.clone().trans::<StateTwo, _>(always, StateThree);
+        let entity = app.world.spawn(builder.build(StateOne)).id();
+        let extended_entity = app.world.spawn(extended.build(StateOne)).id();
+
+        app.update();
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(app.world.get::<StateThree>(extended_entity).is_some());
+    }
+
+    #[test]
+    fn test_merge() {
+        #[derive(Component)]
+        struct Merged;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let common = StateMachine::default()
+            .trans::<AnyState, _>(always, StateThree)
+            .on_enter::<StateTwo>(|entity| {
+                entity.insert(Merged);
+            });
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .merge(common);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateTwo>(entity).is_some(),
+            "the machine's transitions should be checked before the merged machine's"
+        );
+        assert!(
+            app.world.get::<Merged>(entity).is_some(),
+            "the merged machine's events should run"
+        );
+    }
+
+    #[derive(Clone, MachineStates)]
+    enum Enemy {
+        Idle,
+        Chasing(Entity),
+        Attacking { damage: u32 },
+    }
+
+    #[test]
+    fn test_machine_states() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let target = app.world.spawn_empty().id();
+        let machine = StateMachine::default()
+            .trans_variant(Enemy::Idle, always, Enemy::Chasing(target))
+            .trans_variant(
+                Enemy::Chasing(target),
+                always,
+                Enemy::Attacking { damage: 3 },
+            );
+        let entity = app.world.spawn((machine, enemy::Idle)).id();
+
+        app.update();
+        assert_eq!(app.world.get::<enemy::Chasing>(entity).unwrap().0, target);
+        assert!(app.world.get::<enemy::Idle>(entity).is_none());
+
+        app.update();
+        assert_eq!(app.world.get::<enemy::Attacking>(entity).unwrap().damage, 3);
+        assert!(matches!(
+            Enemy::from(enemy::Attacking { damage: 3 }),
+            Enemy::Attacking { damage: 3 }
+        ));
+    }
+
+    #[test]
+    fn test_state_machine_macro() {
+        #[derive(Component)]
+        struct Entered;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = state_machine! {
+            states: [StateOne, StateTwo, StateThree],
+            transitions: [
+                StateOne => StateTwo: always,
+                AnyState => StateThree: always.not(),
+            ],
+            on_enter: [StateTwo => |entity: &mut EntityCommands| {
+                entity.insert(Entered);
+            }],
+        };
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(app.world.get::<Entered>(entity).is_some());
+    }
+
+    #[test]
+    fn test_trans_builder_system() {
+        #[derive(Resource)]
+        struct Target(u32);
+
+        #[derive(Clone, Component)]
+        struct Chasing(u32);
+
+        let mut app = App::new();
+        app.insert_resource(Target(7))
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default().trans_builder_system::<StateOne, _, _, _, _, _>(
+            always,
+            |In((_, ())): In<(Entity, ())>, target: Res<Target>| Some(Chasing(target.0)),
+        );
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert_eq!(app.world.get::<Chasing>(entity).unwrap().0, 7);
+    }
+
+    #[test]
+    fn test_trans_builder_with_commands() {
+        #[derive(Component)]
+        struct Invulnerable;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine =
+            StateMachine::default().trans_builder_with_commands(always, |_: &StateOne, _| {
+                Some((StateTwo, |entity: &mut EntityCommands| {
+                    entity.insert(Invulnerable);
+                }))
+            });
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(app.world.get::<Invulnerable>(entity).is_some());
+    }
+
+    #[test]
+    fn test_insert_on_enter_remove_on_exit() {
+        #[derive(Clone, Component)]
+        struct Hitbox;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<AnyState, _>(always, StateOne)
+            .insert_on_enter::<StateTwo>(Hitbox)
+            .remove_on_exit::<StateTwo, Hitbox>();
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<Hitbox>(entity).is_some());
+
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+        assert!(app.world.get::<Hitbox>(entity).is_none());
+    }
+
+    #[test]
+    fn test_labeled_machines() {
+        struct Movement;
+        struct Combat;
+
+        #[derive(Clone, Component)]
+        struct Idle;
+        #[derive(Clone, Component)]
+        struct Attacking;
+
+        let mut app = App::new();
+        app.add_systems(
+            Update,
+            (
+                transition_machines::<LabeledMachine<Movement>, false>,
+                transition_machines::<LabeledMachine<Combat>, false>,
+            ),
+        );
+
+        let movement = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+        let combat = StateMachine::default().trans::<Idle, _>(always, Attacking);
+        let entity = app
+            .world
+            .spawn((
+                LabeledMachine::<Movement>::new(movement),
+                LabeledMachine::<Combat>::new(combat),
+                StateOne,
+                Idle,
+            ))
+            .id();
+
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+        assert!(app.world.get::<Attacking>(entity).is_some());
+    }
+
+    #[test]
+    fn test_labeled_done_markers() {
+        struct Movement;
+
+        let mut app = App::new();
+        app.init_resource::<DoneMachines<false>>()
+            .init_resource::<DoneMachines<true>>()
+            .add_systems(
+                Update,
+                (
+                    collect_done_machines::<LabeledMachine<Movement>, false>,
+                    remove_done_markers::<false>,
+                )
+                    .chain(),
+            );
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .set_fixed_update(true);
+        let entity = app
+            .world
+            .spawn((
+                LabeledMachine::<Movement>::new(machine),
+                StateOne,
+                Done::Success,
+            ))
+            .id();
+
+        app.update();
+        assert!(
+            app.world.get::<Done>(entity).is_some(),
+            "markers shouldn't be removed outside of their labeled machine's schedule"
+        );
+
+        app.add_systems(
+            Update,
+            (
+                collect_done_machines::<LabeledMachine<Movement>, true>,
+                remove_done_markers::<true>,
+            )
+                .chain(),
+        );
+        app.update();
+        assert!(app.world.get::<Done>(entity).is_none());
+    }
+
+    #[test]
+    fn test_context() {
+        #[derive(Component)]
+        struct Alert;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let alert =
+            |In(entity): In<Entity>, alerts: Query<(), With<Alert>>| alerts.contains(entity);
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(alert, StateTwo)
+            .set_context(TriggerContext::Parent);
+        let brain = app.world.spawn((machine, StateOne)).id();
+        let body = app.world.spawn_empty().push_children(&[brain]).id();
+
+        app.update();
+        assert!(app.world.get::<StateOne>(brain).is_some());
+
+        app.world.entity_mut(body).insert(Alert);
+        app.update();
+        assert!(app.world.get::<StateTwo>(brain).is_some());
+    }
+
+    #[test]
+    fn test_set_state() {
+        #[derive(Component)]
+        struct Exited;
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always.not(), StateTwo)
+            .on_exit::<StateOne>(|entity| {
+                entity.insert(Exited);
+            });
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        let mut queue = CommandQueue::default();
+        Commands::new(&mut queue, &app.world)
+            .entity(entity)
+            .set_state(StateThree);
+        queue.apply(&mut app.world);
+
+        app.update();
+        assert!(app.world.get::<StateThree>(entity).is_some());
+        assert!(
+            app.world.get::<Exited>(entity).is_some(),
+            "forced transitions should run events"
+        );
+    }
+
+    #[test]
+    fn test_set_state_while_chaining() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateThree, _>(always.not(), StateOne)
+            .on_enter::<StateTwo>(|entity| {
+                entity.set_state(StateThree);
+            })
+            .set_chain_depth(1);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        app.update();
+        assert!(
+            app.world.get::<StateThree>(entity).is_some(),
+            "states forced by hooks between chained passes shouldn't be dropped"
+        );
+    }
+
+    #[test]
+    fn test_transition_request() {
+        let mut app = App::new();
+        app.add_event::<TransitionRequest<StateThree>>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always.not(), StateTwo)
+            .trans_on_request::<AnyState, StateThree>();
+        let entity = app.world.spawn((machine, StateOne)).id();
+        let other = app.world.spawn_empty().id();
+
+        app.world.send_event(TransitionRequest {
+            entity: other,
+            state: StateThree,
+        });
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "requests for other entities should be ignored"
+        );
+
+        app.world.send_event(TransitionRequest {
+            entity,
+            state: StateThree,
+        });
+        app.update();
+        assert!(app.world.get::<StateThree>(entity).is_some());
+
+        let machine = StateMachine::default()
+            .set_context(TriggerContext::Entity(other))
+            .trans_on_request::<AnyState, StateThree>();
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.world.send_event(TransitionRequest {
+            entity: other,
+            state: StateThree,
+        });
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "requests for the context entity shouldn't be taken"
+        );
+
+        app.world.send_event(TransitionRequest {
+            entity,
+            state: StateThree,
+        });
+        app.update();
+        assert!(
+            app.world.get::<StateThree>(entity).is_some(),
+            "requests should match the machine's entity, not its context"
+        );
+    }
+
+    #[test]
+    fn test_state_machines_param() {
+        #[derive(Resource)]
+        struct Target(Entity);
 
-    /// Triggers when `SomeResource` is present
-    fn resource_present(res: Option<Res<SomeResource>>) -> bool {
-        res.is_some()
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+        let entity = app.world.spawn((machine, StateOne)).id();
+        app.insert_resource(Target(entity));
+
+        app.update();
+        app.world
+            .run_system_once(|mut machines: StateMachines, target: Res<Target>| {
+                assert!(machines.is_in_state::<StateTwo>(target.0));
+                machines.force(target.0, StateThree);
+                machines.pause(target.0);
+            });
+
+        app.update();
+        assert!(
+            app.world.get::<StateTwo>(entity).is_some(),
+            "paused machines shouldn't take forced transitions"
+        );
+
+        app.world
+            .run_system_once(|mut machines: StateMachines, target: Res<Target>| {
+                assert!(machines.is_paused(target.0));
+                machines.resume(target.0);
+            });
+        app.update();
+        assert!(app.world.get::<StateThree>(entity).is_some());
     }
 
     #[test]
-    fn test_sets_initial_state() {
+    fn test_enter_initial() {
         let mut app = App::new();
         app.add_systems(Update, transition);
-        let machine = StateMachine::default().with_state::<StateOne>();
+
+        let machine = StateMachine::default()
+            .with_state::<StateOne>()
+            .on_enter::<StateOne>(|entity| entity.commands().insert_resource(SomeResource))
+            .set_enter_initial(false);
+        app.world.spawn((machine, StateOne));
+
+        app.update();
+        assert!(!app.world.contains_resource::<SomeResource>());
+
+        let machine = StateMachine::default()
+            .with_state::<StateOne>()
+            .on_enter::<StateOne>(|entity| entity.commands().insert_resource(SomeResource));
+        app.world.spawn((machine, StateOne));
+
+        app.update();
+        assert!(
+            app.world.contains_resource::<SomeResource>(),
+            "the initial state should be entered"
+        );
+    }
+
+    #[test]
+    fn test_desync() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always.not(), StateTwo)
+            .trans::<StateTwo, _>(always.not(), StateOne)
+            .set_desync(Desync::Resync);
         let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        app.world.entity_mut(entity).insert(StateTwo);
+        app.update();
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_none());
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        // The machine is skipped instead of panicking
+        app.world.entity_mut(entity).remove::<StateTwo>();
+        app.update();
+    }
+
+    #[test]
+    fn test_chain_loop() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateTwo, _>(always, StateOne)
+            .set_chain_depth(3);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
         app.update();
-        // should have moved to state two
         assert!(
             app.world.get::<StateOne>(entity).is_some(),
-            "StateMachine should have the initial component"
+            "looping machines should stop at their chain depth"
         );
     }
 
     #[test]
-    fn test_machine() {
+    fn test_transition_order() {
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<AnyState, _>(always, StateThree)
+            .trans::<StateTwo, _>(always, StateOne)
+            .trans::<StateOne, _>(always, StateOne)
+            .priority(1)
+            .trans_from_any_except::<(StateOne,), _>(always, StateTwo);
+
+        assert_eq!(
+            machine
+                .transition_order::<StateOne>()
+                .map(|info| info.to)
+                .collect::<Vec<_>>(),
+            [
+                Some(TypeId::of::<StateOne>()),
+                Some(TypeId::of::<StateTwo>()),
+                Some(TypeId::of::<StateThree>()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_last_result_and_force_state_reflect() {
+        let mut app = App::new();
+        app.register_type::<StateThree>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(|| false, StateTwo)
+            .trans::<StateTwo, _>(always, StateThree);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        let last_results = |app: &App| {
+            app.world
+                .get::<StateMachine>(entity)
+                .unwrap()
+                .transitions()
+                .map(|info| info.last_result)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(last_results(&app), [None, None]);
+
+        app.update();
+        assert_eq!(last_results(&app), [Some(false), None]);
+
+        app.world
+            .get_mut::<StateMachine>(entity)
+            .unwrap()
+            .force_state_reflect(Box::new(StateThree));
+        app.update();
+        assert!(app.world.get::<StateThree>(entity).is_some());
+    }
+
+    #[test]
+    fn test_follow_only() {
         let mut app = App::new();
         app.add_systems(Update, transition);
 
         let machine = StateMachine::default()
             .trans::<StateOne, _>(always, StateTwo)
-            .trans::<StateTwo, _>(resource_present, StateThree);
+            .set_follow_only(true);
         let entity = app.world.spawn((machine, StateOne)).id();
 
+        app.update();
         assert!(app.world.get::<StateOne>(entity).is_some());
 
+        app.world
+            .get_mut::<StateMachine>(entity)
+            .unwrap()
+            .force_state(StateTwo);
         app.update();
-        // should have moved to state two
-        assert!(app.world.get::<StateOne>(entity).is_none());
         assert!(app.world.get::<StateTwo>(entity).is_some());
+    }
+
+    /// Counts how many times it's initialized, and never occurs
+    struct CountInits(Arc<AtomicUsize>);
+
+    impl Trigger for CountInits {
+        type Out = bool;
+
+        fn init(&mut self, _: &mut World) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn check(&mut self, _: Entity, _: &World) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_lazy_init() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let inits = Arc::new(AtomicUsize::new(0));
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateTwo, _>(always, StateOne)
+            .trans::<StateThree, _>(CountInits(inits.clone()), StateOne)
+            .set_chain_depth(4);
+        app.world.spawn((machine, StateOne));
 
         app.update();
-        // not yet...
-        assert!(app.world.get::<StateTwo>(entity).is_some());
-        assert!(app.world.get::<StateThree>(entity).is_none());
+        assert_eq!(
+            inits.load(Ordering::Relaxed),
+            0,
+            "triggers of other states shouldn't be initialized"
+        );
+    }
+
+    #[test]
+    fn test_weighted_selection() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .weight(0.)
+            .trans::<StateOne, _>(always, StateThree)
+            .with_selection::<StateOne>(Selection::Weighted)
+            .set_seed(1);
+        let entity = app.world.spawn((machine, StateOne)).id();
 
-        app.world.insert_resource(SomeResource);
         app.update();
-        // okay, *now*
-        assert!(app.world.get::<StateTwo>(entity).is_none());
-        assert!(app.world.get::<StateThree>(entity).is_some());
+        assert!(
+            app.world.get::<StateThree>(entity).is_some(),
+            "transitions with no weight shouldn't be chosen"
+        );
     }
 
     #[test]
-    fn test_self_transition() {
+    fn test_trans_utility() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default().trans_utility::<StateOne>(
+            0.5,
+            UtilityCandidates::new()
+                .candidate(|| 0.6, StateTwo)
+                .candidate(|| 0.8, StateThree),
+        );
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateThree>(entity).is_some(),
+            "the highest-scoring candidate should be chosen"
+        );
+
+        let machine = StateMachine::default()
+            .trans_utility::<StateOne>(0.5, UtilityCandidates::new().candidate(|| 0.4, StateTwo));
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateOne>(entity).is_some(),
+            "candidates at or below the threshold shouldn't be chosen"
+        );
+    }
+
+    #[test]
+    fn test_named() {
+        let mut app = App::new();
+        app.add_event::<StateEntered<StateTwo>>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .send_events::<StateTwo>()
+            .named("test_machine");
+        assert_eq!(machine.name(), Some("test_machine"));
+        app.world.spawn((machine, StateOne));
+
+        app.update();
+        let events = app.world.resource::<Events<StateEntered<StateTwo>>>();
+        let event = events.get_reader().read(events).next().unwrap();
+        assert_eq!(event.machine.as_deref(), Some("test_machine"));
+    }
+
+    #[test]
+    fn test_transition_history() {
         let mut app = App::new();
         app.add_systems(Update, transition);
 
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateTwo, _>(always, StateThree)
+            .trans::<StateThree, _>(always, StateOne);
         let entity = app
             .world
-            .spawn((
-                StateMachine::default().trans::<StateOne, _>(always, StateOne),
-                StateOne,
-            ))
+            .spawn((machine, StateOne, TransitionHistory::new(2)))
             .id();
+
+        app.update();
+        app.update();
+        app.update();
+        let history = app.world.get::<TransitionHistory>(entity).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|record| (record.from, record.to, record.transition))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    TypeId::of::<StateTwo>(),
+                    TypeId::of::<StateThree>(),
+                    Some(1)
+                ),
+                (
+                    TypeId::of::<StateThree>(),
+                    TypeId::of::<StateOne>(),
+                    Some(2)
+                ),
+            ],
+            "only the last transitions should be kept"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<StateThree, _>(always, StateOne);
+
+        let name = |state| type_name::<StateOne>().replace("StateOne", state);
+        assert_eq!(
+            machine.validate::<StateOne>(),
+            [
+                ValidationIssue::Unreachable {
+                    state: name("StateThree"),
+                },
+                ValidationIssue::NoOutgoing {
+                    state: name("StateTwo"),
+                },
+                ValidationIssue::Duplicate {
+                    from: name("StateOne"),
+                    to: Some(name("StateTwo")),
+                },
+                ValidationIssue::UnreachableTransition {
+                    from: name("StateThree"),
+                    to: Some(name("StateOne")),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn test_conflict_warnings() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = |warn_conflicts| {
+            StateMachine::default()
+                .trans::<StateOne, _>(always, StateTwo)
+                .trans::<StateOne, _>(always, StateThree)
+                .set_conflict_warnings(warn_conflicts)
+        };
+        let warned = app.world.spawn((machine(true), StateOne)).id();
+        let unwarned = app.world.spawn((machine(false), StateOne)).id();
+
+        app.update();
+        assert!(
+            app.world.get::<StateTwo>(warned).is_some(),
+            "checking shadowed transitions shouldn't change which is taken"
+        );
+
+        let last_results = |entity| {
+            app.world
+                .get::<StateMachine>(entity)
+                .unwrap()
+                .transitions()
+                .map(|info| info.last_result)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(last_results(unwarned), [Some(true), None]);
+        // Conflicts are only checked in debug builds
+        if cfg!(debug_assertions) {
+            assert_eq!(
+                last_results(warned),
+                [Some(true), Some(true)],
+                "the shadowed transition should be checked"
+            );
+        }
+    }
+
+    #[test]
+    fn test_log_transitions_at() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .named("boss_ai")
+            .trans::<StateOne, _>(always, StateTwo)
+            .log_transitions_at(Level::DEBUG);
+        let entity = app.world.spawn((machine, StateOne, Name::new("Boss"))).id();
+
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        let machine = app.world.get::<StateMachine>(entity).unwrap();
+        assert_eq!(machine.log_transitions, Some(Level::DEBUG));
+        assert_eq!(
+            machine.display_named(&app.world, entity).to_string(),
+            format!("{entity:?} \"Boss\" (boss_ai)"),
+        );
+        assert_eq!(
+            StateMachine::default().display(entity).to_string(),
+            format!("{entity:?}"),
+        );
+    }
+
+    #[test]
+    fn test_in_entity_state() {
+        #[derive(Default, Resource)]
+        struct Ran(u32);
+
+        let mut app = App::new();
+        let entity = app.world.spawn(StateOne).id();
+        app.init_resource::<Ran>().add_systems(
+            Update,
+            (|mut ran: ResMut<Ran>| ran.0 += 1)
+                .run_if(in_entity_state::<StateOne>(entity).and_then(any_in_state::<StateOne>)),
+        );
+
+        app.update();
+        app.world.entity_mut(entity).remove::<StateOne>();
+        app.update();
+        assert_eq!(app.world.resource::<Ran>().0, 1);
+    }
+
+    #[test]
+    fn test_current_state_query() {
+        fn current(states: Query<CurrentState<StateOne>>) -> bool {
+            states.single().get().is_some()
+        }
+
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .with_state::<StateTwo>();
+        let entity = app.world.spawn((machine, StateOne)).id();
+        assert!(app.world.run_system_once(current));
+
+        app.update();
+        app.world.entity_mut(entity).insert(StateOne);
+        assert!(
+            !app.world.run_system_once(current),
+            "states that aren't current shouldn't be given"
+        );
+    }
+
+    #[test]
+    fn test_global_state() {
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, States)]
+        enum GameState {
+            #[default]
+            Menu,
+            Playing,
+        }
+
+        let mut app = App::new();
+        app.init_resource::<bevy::prelude::NextState<GameState>>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(next_global_state(GameState::Playing), StateTwo)
+            .drive_global_state::<StateThree, _>(GameState::Menu)
+            .trans::<StateTwo, _>(always, StateThree);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+
+        app.world
+            .resource_mut::<bevy::prelude::NextState<GameState>>()
+            .set(GameState::Playing);
+        app.update();
+        assert!(app.world.get::<StateTwo>(entity).is_some());
+
+        app.update();
+        assert_eq!(
+            app.world
+                .resource::<bevy::prelude::NextState<GameState>>()
+                .0,
+            Some(GameState::Menu),
+        );
+    }
+
+    #[test]
+    fn test_fixed_update() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .set_fixed_update(true);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
         app.update();
-        // the sort of bug this is trying to catch: if you insert the new state and then remove the
-        // old state, self-transitions will leave you without the state
         assert!(
             app.world.get::<StateOne>(entity).is_some(),
-            "transitioning from a state to itself should work"
+            "fixed update machines shouldn't run with the rest"
         );
+
+        app.world
+            .run_system_once(transition_machines::<StateMachine, true>);
+        assert!(app.world.get::<StateTwo>(entity).is_some());
     }
 }
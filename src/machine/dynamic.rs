@@ -0,0 +1,250 @@
+//! States that are defined at runtime, by string keys, for mods and data files. See
+//! [`DynamicState`].
+
+use std::{
+    any::{type_name, TypeId},
+    collections::HashSet,
+    fmt::{self, Debug, Formatter},
+    sync::{Mutex, OnceLock},
+};
+
+use bevy::{
+    ecs::system::EntityCommands,
+    reflect::{DynamicStruct, Struct, TypeRegistry},
+};
+
+use crate::{
+    prelude::*,
+    state::Insert,
+    trigger::{IntoTrigger, TriggerOut},
+};
+
+use super::{NextState, Transition};
+
+/// Gets the interned copy of the given key, so keys may be compared and copied cheaply. Interned
+/// keys are never freed, so avoid creating many distinct keys, such as one per entity.
+fn intern(key: &str) -> &'static str {
+    static KEYS: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    let mut keys = KEYS.get_or_init(default).lock().unwrap();
+    if let Some(&key) = keys.get(key) {
+        return key;
+    }
+
+    let key: &'static str = Box::leak(key.into());
+    keys.insert(key);
+    key
+}
+
+/// A state that's identified by a string key instead of a type, so mods and data files can define
+/// new states without Rust types. Its data is a bag of reflected fields. To the state machine,
+/// every dynamic state is the same state type, so transition between them with
+/// [`StateMachine::trans_dynamic`], which checks the key. Transitions from compiled states may go
+/// to a dynamic state with [`StateMachine::trans`] as usual, and compiled and dynamic states may
+/// be mixed freely in one machine. Since going from one dynamic state to another is a transition
+/// between states of the same type, it's affected by [`StateMachine::set_self_transition`], and
+/// [`SelfTransition::Ignore`] prevents it.
+#[derive(Component)]
+pub struct DynamicState {
+    key: &'static str,
+    fields: DynamicStruct,
+}
+
+impl Clone for DynamicState {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key,
+            fields: self.fields.clone_dynamic(),
+        }
+    }
+}
+
+impl Debug for DynamicState {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DynamicState")
+            .field("key", &self.key)
+            .field("fields", &self.fields)
+            .finish()
+    }
+}
+
+impl DynamicState {
+    /// Creates a dynamic state with the given key and no fields
+    pub fn new(key: impl AsRef<str>) -> Self {
+        Self {
+            key: intern(key.as_ref()),
+            fields: default(),
+        }
+    }
+
+    /// Adds a field with the given name and value, replacing the field of that name, if any
+    pub fn with_field(mut self, name: &str, value: impl Reflect) -> Self {
+        self.fields.insert(name, value);
+        self
+    }
+
+    /// Replaces all of the state's fields, such as with fields deserialized from a data file
+    pub fn with_fields(mut self, fields: DynamicStruct) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Gets the state's key
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+
+    /// Checks whether the state has the given key
+    pub fn is(&self, key: &str) -> bool {
+        self.key == key
+    }
+
+    /// Gets the value of the field with the given name, if it exists and has the type `T`
+    pub fn field<T: Reflect>(&self, name: &str) -> Option<&T> {
+        self.fields.field(name)?.downcast_ref()
+    }
+
+    /// Gets the value of the field with the given name mutably, if it exists and has the type `T`
+    pub fn field_mut<T: Reflect>(&mut self, name: &str) -> Option<&mut T> {
+        self.fields.field_mut(name)?.downcast_mut()
+    }
+
+    /// Gets the state's fields
+    pub fn fields(&self) -> &DynamicStruct {
+        &self.fields
+    }
+
+    /// Gets the state's fields mutably
+    pub fn fields_mut(&mut self) -> &mut DynamicStruct {
+        &mut self.fields
+    }
+}
+
+/// A transition from the [`DynamicState`] with the given key. See
+/// [`StateMachine::trans_dynamic`].
+struct DynamicTransition<Trig: Trigger, Next: Clone + Component> {
+    key: &'static str,
+    trigger: Trig,
+    state: Next,
+}
+
+impl<Trig: Trigger, Next: Clone + Component> Debug for DynamicTransition<Trig, Next> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DynamicTransition")
+            .field("key", &self.key)
+            .field("trigger", &type_name::<Trig>())
+            .field("state", &type_name::<Next>())
+            .finish()
+    }
+}
+
+impl<Trig: Trigger, Next: Clone + Component> Transition for DynamicTransition<Trig, Next> {
+    fn trigger_name(&self) -> &'static str {
+        type_name::<Trig>()
+    }
+
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
+    }
+
+    fn check(&mut self, world: &World, entity: Entity, context: Entity) -> Option<NextState> {
+        // The key is checked first, so triggers that consume events aren't checked needlessly
+        if !world.get::<DynamicState>(entity)?.is(self.key) {
+            return None;
+        }
+
+        self.trigger
+            .check(context, world)
+            .occurred()
+            .then_some(NextState::Stored(TypeId::of::<Next>()))
+    }
+
+    fn insert_stored(&self, entity: &mut EntityCommands) {
+        entity.insert(self.state.clone());
+    }
+
+    fn reflect_stored<'a>(&'a self, registry: &TypeRegistry) -> Option<&'a dyn Reflect> {
+        Insert::reflect(&self.state, registry)
+    }
+}
+
+impl StateMachine {
+    /// Adds a transition from the [`DynamicState`] with the given key. When the entity is in that
+    /// dynamic state, and the given trigger occurs, it will transition to the given state, which
+    /// may be a compiled state or another [`DynamicState`]. Elide the `Marker` type parameter with
+    /// `_`.
+    pub fn trans_dynamic<Marker, Next: Clone + Component>(
+        mut self,
+        from: impl AsRef<str>,
+        trigger: impl IntoTrigger<Marker>,
+        state: Next,
+    ) -> Self {
+        self.metadata_mut::<DynamicState>();
+        self.metadata_mut::<Next>();
+        self.add_transition(
+            TypeId::of::<DynamicState>(),
+            Some(TypeId::of::<Next>()),
+            Box::new(DynamicTransition {
+                key: intern(from.as_ref()),
+                trigger: trigger.into_trigger(),
+                state,
+            }),
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct Idle;
+
+    #[derive(Resource)]
+    struct SomeResource;
+
+    #[test]
+    fn test_intern() {
+        let key = String::from("glide");
+        assert!(std::ptr::eq(intern(&key), intern("glide")));
+    }
+
+    #[test]
+    fn test_dynamic_states() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<Idle, _>(
+                resource_exists::<SomeResource>(),
+                DynamicState::new("glide").with_field("speed", 4.5f32),
+            )
+            .trans_dynamic(
+                "glide",
+                resource_exists::<SomeResource>(),
+                DynamicState::new("dive"),
+            )
+            .trans_dynamic("dive", resource_exists::<SomeResource>(), Idle);
+        let entity = app.world.spawn((machine, Idle)).id();
+
+        app.update();
+        assert!(app.world.get::<Idle>(entity).is_some());
+
+        app.insert_resource(SomeResource);
+        app.update();
+        let state = app.world.get::<DynamicState>(entity).unwrap();
+        assert_eq!(state.key(), "glide");
+        assert_eq!(state.field::<f32>("speed"), Some(&4.5));
+        assert_eq!(state.field::<u32>("speed"), None);
+
+        app.update();
+        assert!(app.world.get::<DynamicState>(entity).unwrap().is("dive"));
+
+        app.update();
+        assert!(app.world.get::<Idle>(entity).is_some());
+        assert!(app.world.get::<DynamicState>(entity).is_none());
+    }
+}
@@ -0,0 +1,269 @@
+//! Saving and restoring a state machine's runtime data. See [`MachineSnapshot`].
+
+use std::{any::TypeId, hash::BuildHasher};
+
+#[cfg(feature = "snapshot")]
+use bevy::ecs::{query::ReadOnlyWorldQuery, schedule::BoxedScheduleLabel};
+use bevy::{
+    reflect::{std_traits::ReflectDefault, TypeRegistry},
+    utils::FixedState,
+};
+
+#[cfg(feature = "snapshot")]
+use crate::set::StateSet;
+use crate::{prelude::*, state::ReflectInsert};
+
+use super::StateMetadata;
+
+/// A state machine's runtime data that can't be derived from the entity's components, for save
+/// games. Get one with [`StateMachine::snapshot`], save it along with the entity's states, and
+/// restore it onto a freshly built machine with [`StateMachine::restore`]. States are identified by
+/// their type names. Pending delayed transitions (see [`StateMachine::after_delay`]) and the
+/// triggers' own data, like timers, aren't included, and neither are the stacks of the machine's
+/// regions and substates.
+///
+/// With the `snapshot` feature, this is also a component that [`StateMachinePlugin`] keeps up to
+/// date on each entity with a [`StateMachine`], so reflection-based world snapshots, like those of
+/// `bevy_save`, capture it along with the states. When an entity with a snapshot gets a new
+/// [`StateMachine`], or a new snapshot, the snapshot is restored onto the machine. With the `ggrs`
+/// feature, it's rolled back by `bevy_ggrs` (see `ggrs_machine_plugin`).
+#[derive(Clone, Component, Debug, Default, Eq, Hash, PartialEq, Reflect)]
+#[reflect(Component, Default)]
+pub struct MachineSnapshot {
+    /// The type name of the current state, if it's known
+    pub current: Option<String>,
+    /// The type names of the states on the stack, from bottom to top. See
+    /// [`StateMachine::trans_push`].
+    pub stack: Vec<String>,
+    /// Substates remembered by states with history, as the type names of the state and its
+    /// substate. See [`StateMachine::with_history`].
+    pub history: Vec<(String, String)>,
+    /// Whether the machine has entered its initial state. See [`StateMachine::set_enter_initial`].
+    pub entered: bool,
+    /// The state of the random number generator. See [`StateMachine::set_seed`].
+    pub rng: u64,
+}
+
+impl MachineSnapshot {
+    /// Hashes the snapshot with a fixed seed, so the same runtime data has the same checksum across
+    /// processes. Useful for detecting desyncs in rollback netcode.
+    pub fn checksum(&self) -> u64 {
+        FixedState.hash_one(self)
+    }
+}
+
+impl StateMachine {
+    /// Gets the state machine's runtime data, so it can be saved. See [`MachineSnapshot`].
+    pub fn snapshot(&self) -> MachineSnapshot {
+        let name = |state: &TypeId| self.states[state].name.to_owned();
+
+        MachineSnapshot {
+            current: self.current.as_ref().map(name),
+            stack: self.stack.iter().map(|(state, _)| name(state)).collect(),
+            history: self
+                .states
+                .values()
+                .filter_map(|state| {
+                    let substates = state.substates.as_ref()?;
+                    let (last, _) = substates.last.as_ref()?;
+                    Some((
+                        state.name.to_owned(),
+                        substates.machine.states[last].name.to_owned(),
+                    ))
+                })
+                .collect(),
+            entered: self.entered,
+            rng: self.rng,
+        }
+    }
+
+    /// Restores runtime data that was saved with [`StateMachine::snapshot`]. Call it on a state
+    /// machine built the same way as the saved one, before it runs. States on the stack and
+    /// remembered substates are restored with their default values, so register them with
+    /// `#[reflect(Component, Default)]`. States that can't be restored are skipped with a warning.
+    pub fn restore(&mut self, snapshot: &MachineSnapshot, registry: &TypeRegistry) {
+        let restore = |states: &HashMap<TypeId, StateMetadata>, name: &str| {
+            let Some((&state, _)) = states.iter().find(|(_, state)| state.name == name) else {
+                warn!("can't restore {name}, since it isn't one of the state machine's states");
+                return None;
+            };
+
+            let Some(default) = registry.get_type_data::<ReflectDefault>(state) else {
+                warn!("can't restore {name}, since it doesn't have `ReflectDefault` registered");
+                return None;
+            };
+
+            Some((
+                state,
+                ReflectInsert {
+                    state,
+                    value: default.default(),
+                },
+            ))
+        };
+
+        if let Some(current) = &snapshot.current {
+            match self.states.iter().find(|(_, state)| state.name == *current) {
+                Some((&state, _)) => self.current = Some(state),
+                None => warn!(
+                    "can't restore {current}, since it isn't one of the state machine's states"
+                ),
+            }
+        }
+
+        self.stack.clear();
+        for name in &snapshot.stack {
+            if let Some((state, insert)) = restore(&self.states, name) {
+                self.stack.push((state, Box::new(insert)));
+            }
+        }
+
+        for (parent, substate) in &snapshot.history {
+            let Some(substates) = self
+                .states
+                .values_mut()
+                .find(|state| state.name == *parent)
+                .and_then(|state| state.substates.as_mut())
+            else {
+                warn!("can't restore the history of {parent}, since it doesn't have substates");
+                continue;
+            };
+
+            if let Some((state, insert)) = restore(&substates.machine.states, substate) {
+                substates.last = Some((state, Box::new(insert)));
+            }
+        }
+
+        self.entered = snapshot.entered;
+        self.rng = snapshot.rng;
+        self.init_transitions = true;
+    }
+}
+
+#[cfg(feature = "snapshot")]
+pub(crate) fn snapshot_plugin(app: &mut App, schedule: BoxedScheduleLabel) {
+    app.register_type::<MachineSnapshot>()
+        .configure_sets(
+            schedule.dyn_clone(),
+            StateSet::Snapshots.after(StateSet::Transition),
+        )
+        .add_systems(
+            schedule.dyn_clone(),
+            restore_snapshots::<Or<(Added<StateMachine>, Added<MachineSnapshot>)>>
+                .after(super::instantiate_scene_machines)
+                .before(StateSet::Transition),
+        )
+        .add_systems(schedule, update_snapshots.in_set(StateSet::Snapshots));
+}
+
+/// Restores the machines that match the filter `F` from their snapshots, if they differ, such as
+/// snapshots that were loaded or rolled back
+#[cfg(feature = "snapshot")]
+pub(crate) fn restore_snapshots<F: ReadOnlyWorldQuery>(
+    mut machines: Query<(&mut StateMachine, &MachineSnapshot), F>,
+    registry: Res<AppTypeRegistry>,
+) {
+    let registry = registry.read();
+    for (mut machine, snapshot) in &mut machines {
+        // Snapshots added or updated by `update_snapshots` are already up to date
+        if machine.snapshot() != *snapshot {
+            machine.restore(snapshot, &registry);
+        }
+    }
+}
+
+#[cfg(feature = "snapshot")]
+fn update_snapshots(
+    mut commands: Commands,
+    mut machines: Query<(Entity, &StateMachine, Option<&mut MachineSnapshot>)>,
+) {
+    for (entity, machine, snapshot) in &mut machines {
+        let new = machine.snapshot();
+        match snapshot {
+            Some(mut snapshot) => {
+                if *snapshot != new {
+                    *snapshot = new;
+                }
+            }
+            None => {
+                commands.entity(entity).insert(new);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component, Default)]
+    struct StateOne;
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component, Default)]
+    struct StateTwo;
+
+    #[derive(Resource)]
+    struct SomeResource;
+
+    fn machine() -> StateMachine {
+        StateMachine::default()
+            .trans_push::<StateOne, _>(always, StateTwo)
+            .trans_pop::<StateTwo, _>(resource_exists::<SomeResource>())
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut app = App::new();
+        app.register_type::<StateOne>()
+            .add_systems(Update, transition);
+
+        let entity = app.world.spawn((machine(), StateOne)).id();
+        app.update();
+
+        let snapshot = app.world.get::<StateMachine>(entity).unwrap().snapshot();
+        assert_eq!(snapshot.stack, [std::any::type_name::<StateOne>()]);
+
+        // Load the entity's state and runtime data onto a new machine
+        let mut machine = machine();
+        machine.restore(&snapshot, &app.world.resource::<AppTypeRegistry>().read());
+        assert_eq!(machine.snapshot(), snapshot);
+        assert_eq!(machine.snapshot().checksum(), snapshot.checksum());
+        assert_ne!(MachineSnapshot::default().checksum(), snapshot.checksum());
+
+        let entity = app.world.spawn((machine, StateTwo)).id();
+        app.insert_resource(SomeResource);
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+    }
+
+    #[cfg(feature = "snapshot")]
+    #[test]
+    fn test_snapshot_plugin() {
+        let mut app = App::new();
+        app.register_type::<StateOne>().add_systems(
+            Update,
+            (
+                restore_snapshots::<Or<(Added<StateMachine>, Added<MachineSnapshot>)>>,
+                transition,
+                update_snapshots,
+            )
+                .chain(),
+        );
+
+        let entity = app.world.spawn((machine(), StateOne)).id();
+        app.update();
+        app.update();
+        let snapshot = app.world.get::<MachineSnapshot>(entity).unwrap().clone();
+        assert_eq!(snapshot.stack.len(), 1);
+
+        // Simulate loading the entity from a world snapshot, and rebuilding its machine
+        let entity = app.world.spawn((snapshot, StateTwo)).id();
+        app.world.entity_mut(entity).insert(machine());
+        app.insert_resource(SomeResource);
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+    }
+}
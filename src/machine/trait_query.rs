@@ -0,0 +1,98 @@
+//! Querying a state machine's current state as a trait object with `bevy-trait-query`. Enabled by
+//! the `trait_query` feature. See [`StateMachine::with_state_trait`].
+
+use bevy_trait_query::{RegisterExt, TraitQuery, TraitQueryMarker};
+
+use crate::prelude::*;
+
+impl StateMachine {
+    /// Registers the state `S` as an implementation of `Trait` (a `dyn` trait made queryable with
+    /// `#[bevy_trait_query::queryable]`) when [`StateMachine::register_state_traits`] is called, so
+    /// the current state, whatever it is, may be read with `Query<One<&dyn Trait>>`. Useful for
+    /// behavior that every state shares, like each state knowing its movement speed.
+    pub fn with_state_trait<Trait: ?Sized + TraitQuery, S: Clone + Component>(mut self) -> Self
+    where
+        (S,): TraitQueryMarker<Trait, Covered = S>,
+    {
+        self.metadata_mut::<S>();
+        self.state_traits.push(|world| {
+            world.register_component_as::<Trait, S>();
+        });
+        self
+    }
+
+    /// Registers the states that were given to [`StateMachine::with_state_trait`] as
+    /// implementations of their traits. `bevy-trait-query` doesn't allow registering
+    /// implementations once trait queries are in use, so call this while building the app, on a
+    /// machine built the same way as the ones that will be spawned, such as from the same
+    /// [`MachineDefinition`]. Registering a state more than once has no effect.
+    pub fn register_state_traits(&self, world: &mut World) {
+        for register in &self.state_traits {
+            register(world);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_trait_query::One;
+
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[bevy_trait_query::queryable]
+    trait Speed {
+        fn speed(&self) -> f32;
+    }
+
+    #[derive(Clone, Component)]
+    struct Walk;
+    #[derive(Clone, Component)]
+    struct Run;
+
+    impl Speed for Walk {
+        fn speed(&self) -> f32 {
+            1.
+        }
+    }
+
+    impl Speed for Run {
+        fn speed(&self) -> f32 {
+            3.
+        }
+    }
+
+    #[derive(Resource)]
+    struct SomeResource;
+
+    #[test]
+    fn test_state_traits() {
+        let machine = || {
+            StateMachine::default()
+                .trans::<Walk, _>(resource_exists::<SomeResource>(), Run)
+                .with_state_trait::<dyn Speed, Walk>()
+                .with_state_trait::<dyn Speed, Run>()
+        };
+
+        let mut app = App::new();
+        machine().register_state_traits(&mut app.world);
+        app.add_systems(Update, transition);
+
+        let entity = app.world.spawn((machine(), Walk)).id();
+        let speed = |app: &mut App| {
+            app.world
+                .query::<One<&dyn Speed>>()
+                .get(&app.world, entity)
+                .unwrap()
+                .speed()
+        };
+
+        app.update();
+        assert_eq!(speed(&mut app), 1.);
+
+        app.insert_resource(SomeResource);
+        app.update();
+        assert_eq!(speed(&mut app), 3.);
+    }
+}
@@ -0,0 +1,159 @@
+//! Labels that show entities' current states in the world, for debugging. Enabled by the
+//! `debug_overlay` feature. See [`StateLabel`].
+
+use std::{any::TypeId, time::Duration};
+
+use bevy::{ui::UiSystem, utils::get_short_name};
+
+use crate::{prelude::*, set::StateSet};
+
+/// Shows [`StateLabel`]s. Add it along with [`StateMachinePlugin`].
+#[derive(Debug, Default)]
+pub struct StateLabelPlugin;
+
+impl Plugin for StateLabelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (update_state_labels, despawn_state_labels)
+                .after(StateSet::Transition)
+                .before(UiSystem::Layout),
+        );
+    }
+}
+
+/// Add to an entity with a [`StateMachine`] to show its current state's short name above it, and
+/// optionally how long it's been in that state. The label is a UI text node, positioned each frame
+/// by projecting the entity's position through the first active camera, so it works in 2D and 3D.
+/// Remove this component, or set [`StateLabel::visible`], to hide it. Requires
+/// [`StateLabelPlugin`].
+#[derive(Component, Debug)]
+pub struct StateLabel {
+    /// Whether the label is shown. Defaults to `true`.
+    pub visible: bool,
+    /// Whether to show how long the entity has been in its current state. Defaults to `false`.
+    pub show_time: bool,
+    /// Where the label is, relative to the entity's position, such as above its head. Defaults to
+    /// zero.
+    pub offset: Vec3,
+    /// The label's text node
+    node: Option<Entity>,
+    /// The state when it was last shown
+    state: Option<TypeId>,
+    /// When the entity entered its current state, according to [`Time`]
+    since: Duration,
+}
+
+impl Default for StateLabel {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            show_time: false,
+            offset: Vec3::ZERO,
+            node: None,
+            state: None,
+            since: Duration::ZERO,
+        }
+    }
+}
+
+impl StateLabel {
+    /// Also shows how long the entity has been in its current state
+    pub fn with_time(mut self) -> Self {
+        self.show_time = true;
+        self
+    }
+
+    /// Positions the label relative to the entity's position
+    pub fn with_offset(mut self, offset: Vec3) -> Self {
+        self.offset = offset;
+        self
+    }
+}
+
+/// The text node of the [`StateLabel`] of the given entity
+#[derive(Component, Debug)]
+struct StateLabelNode(Entity);
+
+fn update_state_labels(
+    mut commands: Commands,
+    mut labels: Query<(Entity, &StateMachine, &mut StateLabel, &GlobalTransform)>,
+    mut nodes: Query<(&mut Text, &mut Style, &mut Visibility), With<StateLabelNode>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    time: Res<Time>,
+) {
+    let camera = cameras.iter().find(|(camera, _)| camera.is_active);
+
+    for (entity, machine, mut label, transform) in &mut labels {
+        let label = &mut *label;
+        let state = machine.current_state_id();
+        if label.state != state {
+            label.state = state;
+            label.since = time.elapsed();
+        }
+
+        let Some(node) = label.node else {
+            let node = commands.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                }),
+                StateLabelNode(entity),
+            ));
+            label.node = Some(node.id());
+            continue;
+        };
+
+        let Ok((mut text, mut style, mut visibility)) = nodes.get_mut(node) else {
+            continue;
+        };
+
+        let position = camera.and_then(|(camera, camera_transform)| {
+            camera.world_to_viewport(camera_transform, transform.translation() + label.offset)
+        });
+        let Some(position) = position.filter(|_| label.visible) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+
+        *visibility = Visibility::Inherited;
+        style.left = Val::Px(position.x);
+        style.top = Val::Px(position.y);
+
+        let mut value = machine
+            .current_state_name()
+            .map_or("unknown".to_string(), get_short_name);
+        if label.show_time {
+            let time_in_state = time.elapsed().saturating_sub(label.since);
+            value += &format!(" ({:.1}s)", time_in_state.as_secs_f32());
+        }
+
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+/// Despawns the text nodes of labels that were removed
+fn despawn_state_labels(
+    mut commands: Commands,
+    nodes: Query<(Entity, &StateLabelNode)>,
+    labels: Query<&StateLabel>,
+) {
+    for (node, &StateLabelNode(target)) in &nodes {
+        if !labels
+            .get(target)
+            .is_ok_and(|label| label.node == Some(node))
+        {
+            commands.entity(node).despawn();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Switches `seldom_pixel` sprites, animations, and filters when state machines transition. Enabled
+//! by the `pixel` feature. See [`StatePxAnimations`].
+
+use std::any::TypeId;
+
+use bevy::utils::HashMap;
+use seldom_pixel::prelude::{
+    PxAnimationBundle, PxAnimationDuration, PxAnimationFinishBehavior, PxFilter, PxSprite,
+};
+
+use crate::{prelude::*, set::StateSet};
+
+pub(crate) fn pixel_plugin(app: &mut App) {
+    app.configure_sets(PostUpdate, StateSet::Animation.after(StateSet::Transition))
+        .add_systems(
+            PostUpdate,
+            switch_state_px_animations.in_set(StateSet::Animation),
+        );
+}
+
+/// How a state's sprite is animated
+type PxAnimation = (PxAnimationDuration, PxAnimationFinishBehavior);
+
+/// Add to an entity with a [`StateMachine`] and a `seldom_pixel` sprite to switch its sprite,
+/// animation, and filter for each of its states. When the machine transitions to a state with a
+/// sprite, the sprite replaces the entity's, and its animation starts from the beginning. When it
+/// transitions to a state with a filter, the filter is applied, and when it transitions from a
+/// state with a filter to a state without one, the filter is removed.
+#[derive(Clone, Component, Debug, Default)]
+pub struct StatePxAnimations {
+    /// Each state's sprite, and how it's animated, if it is
+    sprites: HashMap<TypeId, (Handle<PxSprite>, Option<PxAnimation>)>,
+    /// Each state's filter
+    filters: HashMap<TypeId, Handle<PxFilter>>,
+    /// The state whose sprite and filter were last switched to
+    last: Option<TypeId>,
+}
+
+impl StatePxAnimations {
+    /// Shows the given sprite, without animating it, while the entity is in the given state
+    pub fn with_sprite<S: Clone + Component>(mut self, sprite: Handle<PxSprite>) -> Self {
+        self.sprites.insert(TypeId::of::<S>(), (sprite, None));
+        self
+    }
+
+    /// Animates the given sprite while the entity is in the given state. The animation starts
+    /// when the entity enters the state.
+    pub fn with_animation<S: Clone + Component>(
+        mut self,
+        sprite: Handle<PxSprite>,
+        duration: PxAnimationDuration,
+        on_finish: PxAnimationFinishBehavior,
+    ) -> Self {
+        self.sprites
+            .insert(TypeId::of::<S>(), (sprite, Some((duration, on_finish))));
+        self
+    }
+
+    /// Applies the given filter to the entity while it's in the given state
+    pub fn with_filter<S: Clone + Component>(mut self, filter: Handle<PxFilter>) -> Self {
+        self.filters.insert(TypeId::of::<S>(), filter);
+        self
+    }
+}
+
+fn switch_state_px_animations(
+    mut commands: Commands,
+    mut machines: Query<(Entity, &StateMachine, &mut StatePxAnimations)>,
+) {
+    for (entity, machine, mut animations) in &mut machines {
+        let Some(current) = machine.current_state_id() else {
+            continue;
+        };
+
+        if animations.last == Some(current) {
+            continue;
+        }
+
+        let last = animations.last.replace(current);
+        let mut entity = commands.entity(entity);
+
+        if let Some((sprite, animation)) = animations.sprites.get(&current) {
+            entity.insert(sprite.clone());
+            match animation {
+                Some((duration, on_finish)) => {
+                    // Inserting the bundle restarts the animation
+                    entity.insert(PxAnimationBundle {
+                        duration: *duration,
+                        on_finish: *on_finish,
+                        ..default()
+                    });
+                }
+                None => {
+                    entity.remove::<PxAnimationBundle>();
+                }
+            }
+        }
+
+        match animations.filters.get(&current) {
+            Some(filter) => {
+                entity.insert(filter.clone());
+            }
+            None => {
+                if last.is_some_and(|last| animations.filters.contains_key(&last)) {
+                    entity.remove::<Handle<PxFilter>>();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct StateOne;
+    #[derive(Clone, Component)]
+    struct StateTwo;
+
+    #[derive(Resource)]
+    struct SomeResource;
+
+    #[test]
+    fn test_state_px_animations() {
+        let mut app = App::new();
+        app.add_systems(Update, (transition, switch_state_px_animations).chain());
+
+        let idle = Handle::weak_from_u128(1);
+        let walk = Handle::weak_from_u128(2);
+        let animations = StatePxAnimations::default()
+            .with_sprite::<StateOne>(idle.clone())
+            .with_filter::<StateOne>(Handle::weak_from_u128(3))
+            .with_animation::<StateTwo>(
+                walk.clone(),
+                PxAnimationDuration::default(),
+                PxAnimationFinishBehavior::Loop,
+            );
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(resource_exists::<SomeResource>(), StateTwo);
+        let entity = app.world.spawn((machine, StateOne, animations)).id();
+
+        app.update();
+        assert_eq!(app.world.get::<Handle<PxSprite>>(entity), Some(&idle));
+        assert!(app.world.get::<Handle<PxFilter>>(entity).is_some());
+        assert!(app.world.get::<PxAnimationDuration>(entity).is_none());
+
+        app.insert_resource(SomeResource);
+        app.update();
+        assert_eq!(app.world.get::<Handle<PxSprite>>(entity), Some(&walk));
+        assert!(app.world.get::<Handle<PxFilter>>(entity).is_none());
+        assert!(app.world.get::<PxAnimationDuration>(entity).is_some());
+    }
+}
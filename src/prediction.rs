@@ -0,0 +1,205 @@
+//! Rollback for state machines on entities predicted with `lightyear`. Enabled by the `lightyear`
+//! feature. See [`PredictedMachinePlugin`].
+
+use std::collections::VecDeque;
+
+use bevy::reflect::TypeRegistry;
+use lightyear::prelude::{
+    client::{Predicted, PredictionSet, Rollback, RollbackState},
+    Tick, TickManager,
+};
+
+use crate::{prelude::*, set::StateSet};
+
+/// How many ticks of history each predicted machine keeps
+const HISTORY_LEN: usize = 128;
+
+/// Rolls back the [`StateMachine`]s of entities with `lightyear`'s `Predicted` along with their
+/// other predicted components. Predicted machines are evaluated locally in `FixedUpdate`, so build
+/// them with [`StateMachine::set_fixed_update`]. After they transition each tick, their runtime
+/// data is recorded in a [`MachineHistory`]. When `lightyear` rolls back, each machine is restored
+/// to its data at the rollback tick, and then reconciled with the entity's state components, which
+/// `lightyear` has reset to the server's correction, so its current state is whichever of its
+/// states the entity is in. The machines then transition again as the ticks are resimulated.
+///
+/// Add the states, and any components that triggers depend on, to your `lightyear` protocol, and
+/// register the states in the [`AppTypeRegistry`] with `#[reflect(Component, Default)]`. Triggers
+/// are initialized again after a rollback, so their own data, like the timers of
+/// [`StateMachine::trans_after`], restarts from the rollback tick, and pending delayed transitions
+/// (see [`StateMachine::after_delay`]) are dropped. Add it along with [`StateMachinePlugin`] and
+/// `lightyear`'s client plugins.
+#[derive(Debug, Default)]
+pub struct PredictedMachinePlugin;
+
+impl Plugin for PredictedMachinePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            rollback_machines
+                .after(PredictionSet::PrepareRollback)
+                .before(PredictionSet::Rollback),
+        )
+        .add_systems(
+            FixedUpdate,
+            record_machine_history.after(StateSet::Transition),
+        );
+    }
+}
+
+/// The runtime data of a predicted entity's [`StateMachine`] at each recent tick, for rollback.
+/// Managed by the [`PredictedMachinePlugin`], so there's no need to insert it yourself.
+#[derive(Component, Debug, Default)]
+pub struct MachineHistory {
+    snapshots: VecDeque<(Tick, MachineSnapshot)>,
+}
+
+impl MachineHistory {
+    /// Gets the machine's runtime data at the given tick, if it's still in the history
+    pub fn get(&self, tick: Tick) -> Option<&MachineSnapshot> {
+        self.snapshots
+            .iter()
+            .find(|&&(at, _)| at == tick)
+            .map(|(_, snapshot)| snapshot)
+    }
+
+    /// Records the machine's runtime data at the given tick, replacing any data recorded at or
+    /// after it, since that was from before a rollback
+    fn record(&mut self, tick: Tick, snapshot: MachineSnapshot) {
+        while self.snapshots.back().is_some_and(|&(at, _)| at >= tick) {
+            self.snapshots.pop_back();
+        }
+
+        self.snapshots.push_back((tick, snapshot));
+        if self.snapshots.len() > HISTORY_LEN {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+fn record_machine_history(
+    mut commands: Commands,
+    mut machines: Query<(Entity, &StateMachine, Option<&mut MachineHistory>), With<Predicted>>,
+    ticks: Res<TickManager>,
+) {
+    let tick = ticks.tick();
+    for (entity, machine, history) in &mut machines {
+        match history {
+            Some(mut history) => history.record(tick, machine.snapshot()),
+            None => {
+                let mut history = MachineHistory::default();
+                history.record(tick, machine.snapshot());
+                commands.entity(entity).insert(history);
+            }
+        }
+    }
+}
+
+fn rollback_machines(world: &mut World) {
+    let RollbackState::ShouldRollback { current_tick } = world.resource::<Rollback>().state else {
+        return;
+    };
+
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+
+    let entities = world
+        .query_filtered::<Entity, (With<StateMachine>, With<MachineHistory>)>()
+        .iter(world)
+        .collect::<Vec<_>>();
+    for entity in entities {
+        let snapshot = world
+            .get::<MachineHistory>(entity)
+            .unwrap()
+            .get(current_tick)
+            .cloned();
+        let snapshot = reconciled_snapshot(world, entity, snapshot, &registry);
+
+        world
+            .get_mut::<StateMachine>(entity)
+            .unwrap()
+            .restore(&snapshot, &registry);
+    }
+}
+
+/// Gets the runtime data to restore the entity's machine to, given what was recorded at the
+/// rollback tick. The current state is replaced with the state that the entity is actually in, if
+/// that's known.
+fn reconciled_snapshot(
+    world: &World,
+    entity: Entity,
+    snapshot: Option<MachineSnapshot>,
+    registry: &TypeRegistry,
+) -> MachineSnapshot {
+    let machine = world.get::<StateMachine>(entity).unwrap();
+    let mut snapshot = snapshot.unwrap_or_else(|| machine.snapshot());
+
+    let entity = world.entity(entity);
+    let actual = machine.states().find(|&(id, _)| {
+        registry
+            .get_type_data::<ReflectComponent>(id)
+            .is_some_and(|component| component.contains(entity))
+    });
+    if let Some((_, name)) = actual {
+        snapshot.current = Some(name.to_owned());
+    }
+
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component, Default, Reflect)]
+    #[reflect(Component, Default)]
+    struct StateOne;
+    #[derive(Clone, Component, Default, Reflect)]
+    #[reflect(Component, Default)]
+    struct StateTwo;
+
+    #[test]
+    fn test_machine_history() {
+        let snapshot = |rng| MachineSnapshot { rng, ..default() };
+
+        let mut history = MachineHistory::default();
+        for tick in 0..HISTORY_LEN as u16 + 2 {
+            history.record(Tick(tick), snapshot(tick as u64));
+        }
+        assert!(history.get(Tick(1)).is_none());
+        assert_eq!(history.get(Tick(2)).unwrap().rng, 2);
+
+        // Resimulating a tick replaces the history after it
+        history.record(Tick(10), snapshot(0));
+        assert_eq!(history.get(Tick(10)).unwrap().rng, 0);
+        assert!(history.get(Tick(11)).is_none());
+    }
+
+    #[test]
+    fn test_reconciled_snapshot() {
+        let mut app = App::new();
+        app.init_resource::<AppTypeRegistry>()
+            .register_type::<StateOne>()
+            .register_type::<StateTwo>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+        let entity = app.world.spawn((machine, StateOne)).id();
+        let recorded = app.world.get::<StateMachine>(entity).unwrap().snapshot();
+        app.update();
+
+        // The server says that the entity is still in `StateOne`
+        app.world
+            .entity_mut(entity)
+            .remove::<StateTwo>()
+            .insert(StateOne);
+        let registry = app.world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+        let snapshot = reconciled_snapshot(&app.world, entity, Some(recorded), &registry);
+        assert_eq!(
+            snapshot.current.as_deref(),
+            Some(std::any::type_name::<StateOne>()),
+        );
+    }
+}
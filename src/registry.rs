@@ -0,0 +1,456 @@
+//! Triggers and state builders registered by name, for data-driven machines. See
+//! [`TriggerRegistry`] and [`StateBuilderRegistry`].
+
+use std::{
+    any::{type_name, TypeId},
+    error::Error,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+use bevy::{reflect::TypePath, utils::HashMap};
+
+use crate::{prelude::*, state::Insert, trigger::TriggerOut};
+
+pub(crate) fn registry_plugin(app: &mut App) {
+    app.init_resource::<TriggerRegistry>()
+        .init_resource::<StateBuilderRegistry>();
+}
+
+type TriggerFactory = Arc<dyn Fn(&dyn Reflect) -> Option<DynTrigger> + Send + Sync>;
+
+struct RegisteredTrigger {
+    params: TypeId,
+    params_name: &'static str,
+    build: TriggerFactory,
+}
+
+/// Trigger factories registered under names, so machines that are defined as data, such as in
+/// assets or by scripts, can refer to triggers like `"within_distance"`, with parameters like
+/// `{ radius: 8.0 }`. Each factory takes a reflected parameter struct. Register them when building
+/// your app, and register their parameter types in the [`AppTypeRegistry`] too, so they can be
+/// deserialized. `StateMachineAsset`s use these triggers when their `MachineAssetRegistry` doesn't
+/// have a trigger of the same name.
+///
+/// ```ignore
+/// #[derive(Reflect)]
+/// struct WithinDistance {
+///     radius: f32,
+/// }
+///
+/// app.register_type::<WithinDistance>()
+///     .world
+///     .resource_mut::<TriggerRegistry>()
+///     .register("within_distance", |params: WithinDistance| within_distance(params.radius));
+/// ```
+#[derive(Default, Resource)]
+pub struct TriggerRegistry {
+    triggers: HashMap<String, RegisteredTrigger>,
+}
+
+impl TriggerRegistry {
+    /// Registers a trigger factory under the given name. `factory` constructs the trigger from its
+    /// parameters, which may be any type that implements [`FromReflect`], such as a struct that
+    /// derives [`Reflect`]. For triggers without parameters, take `()`. The trigger's output is
+    /// discarded. See [`TriggerRegistry::register_with_output`] to keep it.
+    pub fn register<
+        P: FromReflect + TypePath,
+        T: IntoTrigger<Marker> + 'static,
+        Marker: 'static,
+    >(
+        &mut self,
+        name: impl Into<String>,
+        factory: impl Fn(P) -> T + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.insert::<P>(
+            name.into(),
+            Arc::new(move |params: &dyn Reflect| {
+                Some(DynTrigger::new(
+                    factory(P::from_reflect(params)?).into_trigger(),
+                ))
+            }),
+        )
+    }
+
+    /// Like [`TriggerRegistry::register`], but the trigger's output is passed to state builders
+    /// registered in the [`StateBuilderRegistry`], so it must implement [`Reflect`]
+    pub fn register_with_output<
+        P: FromReflect + TypePath,
+        T: IntoTrigger<Marker> + 'static,
+        Marker: 'static,
+    >(
+        &mut self,
+        name: impl Into<String>,
+        factory: impl Fn(P) -> T + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        <<T::Trigger as Trigger>::Out as TriggerOut>::Ok: Reflect,
+    {
+        self.insert::<P>(
+            name.into(),
+            Arc::new(move |params: &dyn Reflect| {
+                Some(DynTrigger::with_output(
+                    factory(P::from_reflect(params)?).into_trigger(),
+                ))
+            }),
+        )
+    }
+
+    fn insert<P: TypePath>(&mut self, name: String, build: TriggerFactory) -> &mut Self {
+        self.triggers.insert(
+            name,
+            RegisteredTrigger {
+                params: TypeId::of::<P>(),
+                params_name: type_name::<P>(),
+                build,
+            },
+        );
+        self
+    }
+
+    /// Whether a trigger is registered under the given name
+    pub fn contains(&self, name: &str) -> bool {
+        self.triggers.contains_key(name)
+    }
+
+    /// Gets the [`TypeId`] of the parameters of the trigger registered under the given name, such
+    /// as to deserialize them with the [`AppTypeRegistry`]
+    pub fn params_type(&self, name: &str) -> Option<TypeId> {
+        Some(self.triggers.get(name)?.params)
+    }
+
+    /// Constructs the trigger registered under the given name with the given parameters, which may
+    /// be dynamic, such as a `DynamicStruct`. Add it to a machine with
+    /// [`StateMachine::trans_registered`].
+    pub fn build(&self, name: &str, params: &dyn Reflect) -> Result<DynTrigger, RegistryError> {
+        let trigger = self
+            .triggers
+            .get(name)
+            .ok_or_else(|| RegistryError::Unregistered(name.to_string()))?;
+
+        (trigger.build)(params).ok_or_else(|| RegistryError::InvalidParams {
+            name: name.to_string(),
+            expected: trigger.params_name,
+        })
+    }
+}
+
+/// An error while constructing a trigger with a [`TriggerRegistry`], or a state builder with a
+/// [`StateBuilderRegistry`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RegistryError {
+    /// Nothing is registered under the given name
+    Unregistered(String),
+    /// The parameters couldn't be converted to the registered parameter type
+    InvalidParams {
+        /// The registered name
+        name: String,
+        /// The type name of the parameters
+        expected: &'static str,
+    },
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Unregistered(name) => write!(f, "nothing named {name} is registered"),
+            Self::InvalidParams { name, expected } => {
+                write!(f, "the parameters of {name} aren't a valid {expected}")
+            }
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+trait ErasedTrigger: Send + Sync {
+    fn init(&mut self, world: &mut World);
+    fn check(&mut self, entity: Entity, world: &World) -> Option<Box<dyn Reflect>>;
+}
+
+/// Erases a trigger's type, discarding its output
+struct WithoutOutput<T>(T);
+
+impl<T: Trigger> ErasedTrigger for WithoutOutput<T> {
+    fn init(&mut self, world: &mut World) {
+        self.0.init(world);
+    }
+
+    fn check(&mut self, entity: Entity, world: &World) -> Option<Box<dyn Reflect>> {
+        self.0.check(entity, world).into_result().ok()?;
+        Some(Box::new(()))
+    }
+}
+
+/// Erases a trigger's type, reflecting its output
+struct WithOutput<T>(T);
+
+impl<T: Trigger> ErasedTrigger for WithOutput<T>
+where
+    <T::Out as TriggerOut>::Ok: Reflect,
+{
+    fn init(&mut self, world: &mut World) {
+        self.0.init(world);
+    }
+
+    fn check(&mut self, entity: Entity, world: &World) -> Option<Box<dyn Reflect>> {
+        Some(Box::new(self.0.check(entity, world).into_result().ok()?))
+    }
+}
+
+/// A trigger whose type is only known at runtime, such as one constructed by a
+/// [`TriggerRegistry`]. Its output is reflected, or `()` if it was discarded.
+pub struct DynTrigger {
+    trigger: Box<dyn ErasedTrigger>,
+    name: &'static str,
+}
+
+impl DynTrigger {
+    /// Erases the given trigger's type, discarding its output
+    pub fn new<T: Trigger>(trigger: T) -> Self {
+        Self {
+            trigger: Box::new(WithoutOutput(trigger)),
+            name: type_name::<T>(),
+        }
+    }
+
+    /// Erases the given trigger's type, keeping its output for state builders. See
+    /// [`StateBuilderRegistry`].
+    pub fn with_output<T: Trigger>(trigger: T) -> Self
+    where
+        <T::Out as TriggerOut>::Ok: Reflect,
+    {
+        Self {
+            trigger: Box::new(WithOutput(trigger)),
+            name: type_name::<T>(),
+        }
+    }
+
+    /// The type name of the trigger
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl Trigger for DynTrigger {
+    type Out = Option<Box<dyn Reflect>>;
+
+    fn init(&mut self, world: &mut World) {
+        self.trigger.init(world);
+    }
+
+    fn check(&mut self, entity: Entity, world: &World) -> Option<Box<dyn Reflect>> {
+        self.trigger.check(entity, world)
+    }
+}
+
+type StateFactory = Arc<dyn Fn(&dyn Reflect) -> Option<DynStateBuilder> + Send + Sync>;
+
+struct RegisteredStateBuilder {
+    params: TypeId,
+    params_name: &'static str,
+    build: StateFactory,
+}
+
+/// State builders registered under names, like those of [`StateMachine::trans_builder`], so
+/// machines that are defined as data can construct states with fields from their triggers'
+/// outputs. Each builder is constructed from reflected parameters, like the triggers of a
+/// [`TriggerRegistry`], and then builds the state from the output of a trigger registered with
+/// [`TriggerRegistry::register_with_output`]. Register parameter and output types in the
+/// [`AppTypeRegistry`] too, so they can be deserialized.
+///
+/// ```ignore
+/// #[derive(Reflect)]
+/// struct ChaseParams {
+///     speed: f32,
+/// }
+///
+/// // `target` is the output of a trigger, such as one that finds the nearest enemy
+/// app.world.resource_mut::<StateBuilderRegistry>().register(
+///     "chase",
+///     |params: &ChaseParams, target: Entity| Some(Chase { target, speed: params.speed }),
+/// );
+/// ```
+#[derive(Default, Resource)]
+pub struct StateBuilderRegistry {
+    builders: HashMap<String, RegisteredStateBuilder>,
+}
+
+impl StateBuilderRegistry {
+    /// Registers a state builder under the given name. `builder` takes its parameters, which may be
+    /// any type that implements [`FromReflect`], and the trigger's output, and returns the state to
+    /// transition to, or `None` to not transition. For builders without parameters, take `&()`,
+    /// and for triggers without output, take `()`.
+    pub fn register<P, O, S>(
+        &mut self,
+        name: impl Into<String>,
+        builder: impl Fn(&P, O) -> Option<S> + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        P: FromReflect + TypePath,
+        O: FromReflect,
+        S: Clone + Component,
+    {
+        let builder = Arc::new(builder);
+        self.builders.insert(
+            name.into(),
+            RegisteredStateBuilder {
+                params: TypeId::of::<P>(),
+                params_name: type_name::<P>(),
+                build: Arc::new(move |params: &dyn Reflect| {
+                    let params = P::from_reflect(params)?;
+                    let builder = builder.clone();
+                    Some(DynStateBuilder {
+                        to: TypeId::of::<S>(),
+                        register: StateMachine::with_state::<S>,
+                        build: Box::new(move |output: &dyn Reflect| {
+                            let state = builder(&params, O::from_reflect(output)?)?;
+                            Some(Box::new(state) as Box<dyn Insert>)
+                        }),
+                    })
+                }),
+            },
+        );
+        self
+    }
+
+    /// Whether a state builder is registered under the given name
+    pub fn contains(&self, name: &str) -> bool {
+        self.builders.contains_key(name)
+    }
+
+    /// Gets the [`TypeId`] of the parameters of the state builder registered under the given
+    /// name, such as to deserialize them with the [`AppTypeRegistry`]
+    pub fn params_type(&self, name: &str) -> Option<TypeId> {
+        Some(self.builders.get(name)?.params)
+    }
+
+    /// Constructs the state builder registered under the given name with the given parameters.
+    /// Add it to a machine with [`StateMachine::trans_builder_registered`].
+    pub fn build(
+        &self,
+        name: &str,
+        params: &dyn Reflect,
+    ) -> Result<DynStateBuilder, RegistryError> {
+        let builder = self
+            .builders
+            .get(name)
+            .ok_or_else(|| RegistryError::Unregistered(name.to_string()))?;
+
+        (builder.build)(params).ok_or_else(|| RegistryError::InvalidParams {
+            name: name.to_string(),
+            expected: builder.params_name,
+        })
+    }
+}
+
+/// A state builder whose types are only known at runtime, constructed by a
+/// [`StateBuilderRegistry`]
+pub struct DynStateBuilder {
+    pub(crate) to: TypeId,
+    pub(crate) register: fn(StateMachine) -> StateMachine,
+    pub(crate) build: Box<dyn Fn(&dyn Reflect) -> Option<Box<dyn Insert>> + Send + Sync>,
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::reflect::DynamicStruct;
+
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct Idle;
+    #[derive(Clone, Component)]
+    struct Chase;
+
+    #[derive(Reflect)]
+    struct WithinDistance {
+        radius: f32,
+    }
+
+    #[derive(Resource)]
+    struct Distance(f32);
+
+    #[test]
+    fn test_trigger_registry() {
+        let mut registry = TriggerRegistry::default();
+        registry.register("within_distance", |params: WithinDistance| {
+            move |distance: Res<Distance>| distance.0 <= params.radius
+        });
+
+        let mut params = DynamicStruct::default();
+        params.insert("radius", 8_f32);
+        let trigger = registry.build("within_distance", &params).unwrap();
+        assert!(trigger.name().contains("SystemTrigger"));
+
+        assert_eq!(
+            registry.build("far", &params).err(),
+            Some(RegistryError::Unregistered("far".to_string())),
+        );
+        assert!(matches!(
+            registry.build("within_distance", &()),
+            Err(RegistryError::InvalidParams { .. }),
+        ));
+
+        let mut app = App::new();
+        app.insert_resource(Distance(10.))
+            .add_systems(Update, transition);
+        let machine = StateMachine::default().trans_registered::<Idle, _>(trigger, Chase);
+        let entity = app.world.spawn((machine, Idle)).id();
+
+        app.update();
+        assert!(app.world.get::<Idle>(entity).is_some());
+
+        app.world.resource_mut::<Distance>().0 = 5.;
+        app.update();
+        assert!(app.world.get::<Chase>(entity).is_some());
+    }
+
+    #[test]
+    fn test_state_builder_registry() {
+        #[derive(Clone, Component)]
+        struct Flee {
+            distance: f32,
+            speed: f32,
+        }
+
+        #[derive(Reflect)]
+        struct FleeParams {
+            speed: f32,
+        }
+
+        let mut triggers = TriggerRegistry::default();
+        triggers.register_with_output("nearby", |(): ()| {
+            |distance: Res<Distance>| (distance.0 <= 8.).then_some(distance.0)
+        });
+        let mut builders = StateBuilderRegistry::default();
+        builders.register("flee", |params: &FleeParams, distance: f32| {
+            Some(Flee {
+                distance,
+                speed: params.speed,
+            })
+        });
+
+        let mut params = DynamicStruct::default();
+        params.insert("speed", 2_f32);
+        let machine = StateMachine::default().trans_builder_registered::<Idle>(
+            triggers.build("nearby", &()).unwrap(),
+            builders.build("flee", &params).unwrap(),
+        );
+
+        let mut app = App::new();
+        app.insert_resource(Distance(10.))
+            .add_systems(Update, transition);
+        let entity = app.world.spawn((machine, Idle)).id();
+
+        app.update();
+        assert!(app.world.get::<Idle>(entity).is_some());
+
+        app.world.resource_mut::<Distance>().0 = 5.;
+        app.update();
+        let flee = app.world.get::<Flee>(entity).unwrap();
+        assert_eq!((flee.distance, flee.speed), (5., 2.));
+    }
+}
@@ -0,0 +1,213 @@
+//! Replicates state machines' current states from the server to clients with `bevy_replicon`.
+//! Enabled by the `replicon` feature. See [`StateReplicationPlugin`].
+
+use std::any::TypeId;
+
+use bevy::reflect::{
+    serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    ReflectFromReflect, TypeRegistry,
+};
+use bevy_replicon::prelude::{has_authority, AppReplicationExt, Replication, ServerSet};
+use bincode::{DefaultOptions, Options};
+use serde::{Deserialize, Serialize};
+
+use crate::{prelude::*, set::StateSet};
+
+/// Replicates the current states of [`StateMachine`]s on entities with `Replication` from the
+/// server to clients. On the server, each such entity gets a [`ReplicatedState`], which is kept up
+/// to date after the machines transition. On clients, changes to [`ReplicatedState`] are forced on
+/// the entity's machine (see [`StateMachine::force_state_reflect`]) before the machines
+/// transition. Client-side machines should usually be follow-only (see
+/// [`StateMachine::set_follow_only`]), so they don't transition on their own. Add it along with
+/// [`StateMachinePlugin`] and `bevy_replicon`'s plugins.
+///
+/// Replicated states must be registered in the [`AppTypeRegistry`] with `#[reflect(Component)]`,
+/// and their reflected data must be serializable. States are identified by their type names, so
+/// the server and clients must be built from the same code. Substates and regions aren't
+/// replicated.
+#[derive(Debug, Default)]
+pub struct StateReplicationPlugin;
+
+impl Plugin for StateReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.replicate::<ReplicatedState>().add_systems(
+            PostUpdate,
+            (
+                follow_replicated_states
+                    .before(StateSet::Transition)
+                    .run_if(not(has_authority())),
+                replicate_states
+                    .after(StateSet::Transition)
+                    .before(ServerSet::Send)
+                    .run_if(has_authority()),
+            ),
+        );
+    }
+}
+
+/// The authoritative current state of an entity's [`StateMachine`], as replicated by the
+/// [`StateReplicationPlugin`]. Managed by the plugin, so there's no need to insert it yourself.
+#[derive(Clone, Component, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ReplicatedState {
+    /// Compact identifier of the state's type. See [`replicated_state_id`].
+    pub state: u64,
+    /// The state's reflected data, serialized with `bincode`. Empty if the state couldn't be
+    /// serialized.
+    pub data: Vec<u8>,
+}
+
+/// Gets the compact identifier that the [`StateReplicationPlugin`] uses for the state with the
+/// given type name, as in [`StateMachine::states`]. This is a 64-bit FNV-1a hash of the name, so
+/// it's the same across processes.
+pub fn replicated_state_id(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn serialize_state(
+    world: &World,
+    entity: Entity,
+    state: TypeId,
+    registry: &TypeRegistry,
+) -> Option<Vec<u8>> {
+    let component = registry
+        .get_type_data::<ReflectComponent>(state)?
+        .reflect(world.get_entity(entity)?)?;
+    DefaultOptions::new()
+        .serialize(&TypedReflectSerializer::new(component, registry))
+        .ok()
+}
+
+fn replicate_states(world: &mut World) {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+
+    let updates = world
+        .query_filtered::<(Entity, &StateMachine, Option<&ReplicatedState>), With<Replication>>()
+        .iter(world)
+        .filter_map(|(entity, machine, replicated)| {
+            let current = machine.current_state_id()?;
+            let name = machine.current_state_name()?;
+            let data = serialize_state(world, entity, current, &registry).unwrap_or_else(|| {
+                warn!(
+                    "can't replicate {}'s state, since it isn't registered with \
+                    `#[reflect(Component)]`, or can't be serialized",
+                    machine.display(entity),
+                );
+                Vec::new()
+            });
+
+            let state = ReplicatedState {
+                state: replicated_state_id(name),
+                data,
+            };
+            (replicated != Some(&state)).then_some((entity, state))
+        })
+        .collect::<Vec<_>>();
+
+    for (entity, state) in updates {
+        world.entity_mut(entity).insert(state);
+    }
+}
+
+fn follow_replicated_states(
+    mut machines: Query<(Entity, &ReplicatedState, &mut StateMachine), Changed<ReplicatedState>>,
+    registry: Res<AppTypeRegistry>,
+) {
+    let registry = registry.read();
+
+    for (entity, replicated, mut machine) in &mut machines {
+        let Some(id) = machine
+            .states()
+            .find(|&(_, name)| replicated_state_id(name) == replicated.state)
+            .map(|(id, _)| id)
+        else {
+            warn!(
+                "can't follow {}'s replicated state, since it isn't one of its states",
+                machine.display(entity),
+            );
+            continue;
+        };
+
+        let state = registry.get(id).and_then(|registration| {
+            let value = DefaultOptions::new()
+                .deserialize_seed(
+                    TypedReflectDeserializer::new(registration, &registry),
+                    &replicated.data,
+                )
+                .ok()?;
+            registry
+                .get_type_data::<ReflectFromReflect>(id)?
+                .from_reflect(&*value)
+        });
+        let Some(state) = state else {
+            warn!(
+                "can't follow {}'s replicated state, since it isn't registered with \
+                `#[reflect(Component)]`, or can't be deserialized",
+                machine.display(entity),
+            );
+            continue;
+        };
+
+        machine.force_state_reflect(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component, Reflect)]
+    #[reflect(Component)]
+    struct StateOne;
+    #[derive(Clone, Component, Reflect)]
+    #[reflect(Component)]
+    struct StateTwo(u32);
+
+    #[test]
+    fn test_replicate_states() {
+        let mut app = App::new();
+        app.init_resource::<AppTypeRegistry>()
+            .register_type::<StateOne>()
+            .register_type::<StateTwo>()
+            .add_systems(
+                Update,
+                (
+                    follow_replicated_states.before(transition),
+                    transition,
+                    replicate_states.after(transition),
+                ),
+            );
+
+        let machine = || {
+            StateMachine::default()
+                .trans::<StateOne, _>(done(None), StateTwo(7))
+                .trans::<StateTwo, _>(done(None), StateOne)
+        };
+        let server = app.world.spawn((machine(), StateOne, Replication)).id();
+        let client = app
+            .world
+            .spawn((machine().set_follow_only(true), StateOne))
+            .id();
+
+        app.update();
+        let replicated = app.world.get::<ReplicatedState>(server).unwrap().clone();
+        assert_eq!(
+            replicated.state,
+            replicated_state_id(std::any::type_name::<StateOne>())
+        );
+
+        app.world.entity_mut(server).insert(Done::Success);
+        app.world.entity_mut(client).insert(Done::Success);
+        app.update();
+        assert!(app.world.get::<StateOne>(client).is_some());
+
+        let replicated = app.world.get::<ReplicatedState>(server).unwrap().clone();
+        app.world.entity_mut(client).insert(replicated);
+        app.update();
+        assert_eq!(app.world.get::<StateTwo>(client).unwrap().0, 7);
+    }
+}
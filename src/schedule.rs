@@ -0,0 +1,183 @@
+//! Schedules that run when entities enter or exit states of a given type. See
+//! [`state_schedules_plugin`].
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+};
+
+use bevy::ecs::{
+    schedule::{BoxedScheduleLabel, ScheduleLabel},
+    system::SystemState,
+};
+
+use crate::{machine::MachineSchedule, prelude::*, set::StateSet};
+
+/// Schedule that runs after state machines transition, if any entities entered a state of type `S`
+/// that frame. The entities are in the [`EnteredEntities<S>`] resource while it runs. Unlike
+/// [`StateMachine::on_enter`], systems in this schedule run once for all of the entities. Requires
+/// [`state_schedules_plugin`].
+#[derive(ScheduleLabel)]
+pub struct OnEnterState<S: Clone + Component>(PhantomData<S>);
+
+/// Schedule that runs after state machines transition, if any entities exited a state of type `S`
+/// that frame. The entities are in the [`ExitedEntities<S>`] resource while it runs. Runs before
+/// [`OnEnterState<S>`]. Requires [`state_schedules_plugin`].
+#[derive(ScheduleLabel)]
+pub struct OnExitState<S: Clone + Component>(PhantomData<S>);
+
+// These are implemented manually, so `S` doesn't need to implement them
+macro_rules! impl_label_traits {
+    ($label:ident) => {
+        impl<S: Clone + Component> Default for $label<S> {
+            fn default() -> Self {
+                Self(PhantomData)
+            }
+        }
+
+        impl<S: Clone + Component> Clone for $label<S> {
+            fn clone(&self) -> Self {
+                default()
+            }
+        }
+
+        impl<S: Clone + Component> Debug for $label<S> {
+            fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+                write!(f, "{}<{}>", stringify!($label), std::any::type_name::<S>())
+            }
+        }
+
+        impl<S: Clone + Component> PartialEq for $label<S> {
+            fn eq(&self, _: &Self) -> bool {
+                true
+            }
+        }
+
+        impl<S: Clone + Component> Eq for $label<S> {}
+
+        impl<S: Clone + Component> Hash for $label<S> {
+            fn hash<H: Hasher>(&self, _: &mut H) {}
+        }
+    };
+}
+
+impl_label_traits!(OnEnterState);
+impl_label_traits!(OnExitState);
+
+/// The entities that entered a state of type `S` this frame. Only present while
+/// [`OnEnterState<S>`] runs.
+#[derive(Debug, Deref, Resource)]
+pub struct EnteredEntities<S: Clone + Component> {
+    #[deref]
+    entities: Vec<Entity>,
+    state: PhantomData<S>,
+}
+
+/// The entities that exited a state of type `S` this frame. Only present while
+/// [`OnExitState<S>`] runs.
+#[derive(Debug, Deref, Resource)]
+pub struct ExitedEntities<S: Clone + Component> {
+    #[deref]
+    entities: Vec<Entity>,
+    state: PhantomData<S>,
+}
+
+/// Runs [`OnEnterState<S>`] and [`OnExitState<S>`] when entities enter and exit states of type `S`.
+/// Add it after [`StateMachinePlugin`], once for each state type. It runs in the same schedule as
+/// [`StateMachinePlugin`]. Entering and exiting are detected by the state's component being added
+/// and removed, so this includes reentering the same state, and despawning entities exits their
+/// states.
+pub fn state_schedules_plugin<S: Clone + Component>(app: &mut App) {
+    let schedule = app.world.get_resource::<MachineSchedule>().map_or_else(
+        || Box::new(PostUpdate) as BoxedScheduleLabel,
+        |schedule| schedule.0.dyn_clone(),
+    );
+    app.configure_sets(
+        schedule.dyn_clone(),
+        StateSet::Schedules.after(StateSet::Transition),
+    )
+    .add_systems(
+        schedule,
+        run_state_schedules::<S>.in_set(StateSet::Schedules),
+    );
+}
+
+fn run_state_schedules<S: Clone + Component>(
+    world: &mut World,
+    entered: &mut QueryState<Entity, Added<S>>,
+    exited: &mut SystemState<RemovedComponents<S>>,
+) {
+    let exited = exited.get_mut(world).read().collect::<Vec<_>>();
+    if !exited.is_empty() {
+        world.insert_resource(ExitedEntities::<S> {
+            entities: exited,
+            state: PhantomData,
+        });
+        world.try_run_schedule(OnExitState::<S>::default()).ok();
+        world.remove_resource::<ExitedEntities<S>>();
+    }
+
+    let entered = entered.iter(world).collect::<Vec<_>>();
+    if !entered.is_empty() {
+        world.insert_resource(EnteredEntities::<S> {
+            entities: entered,
+            state: PhantomData,
+        });
+        world.try_run_schedule(OnEnterState::<S>::default()).ok();
+        world.remove_resource::<EnteredEntities<S>>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component)]
+    struct StateOne;
+    #[derive(Component, Clone, Default, Reflect)]
+    #[reflect(Component)]
+    struct StateTwo;
+
+    #[derive(Default, Resource)]
+    struct Log(Vec<(&'static str, Vec<Entity>)>);
+
+    #[test]
+    fn test_state_schedules() {
+        let mut app = App::new();
+        app.init_resource::<Log>()
+            .add_systems(
+                Update,
+                (
+                    transition,
+                    run_state_schedules::<StateOne>,
+                    run_state_schedules::<StateTwo>,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                OnExitState::<StateOne>::default(),
+                |exited: Res<ExitedEntities<StateOne>>, mut log: ResMut<Log>| {
+                    log.0.push(("exit", exited.to_vec()));
+                },
+            )
+            .add_systems(
+                OnEnterState::<StateTwo>::default(),
+                |entered: Res<EnteredEntities<StateTwo>>, mut log: ResMut<Log>| {
+                    log.0.push(("enter", entered.to_vec()));
+                },
+            );
+
+        let machine = StateMachine::default().trans::<StateOne, _>(always, StateTwo);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert_eq!(
+            app.world.resource::<Log>().0,
+            [("exit", vec![entity]), ("enter", vec![entity])],
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! `bevy_mod_scripting` bindings, so Lua and Rhai scripts can interact with state machines. Enabled
+//! by the `scripting_lua` and `scripting_rhai` features. See [`StateMachineLuaApi`] and
+//! [`StateMachineRhaiApi`]. The bindings are built on [`script_current_state`],
+//! [`script_request_transition`], and [`script_set_done`], which may be used to bind other
+//! scripting languages.
+
+#[cfg(feature = "scripting_lua")]
+use std::sync::Mutex;
+
+use bevy::{reflect::std_traits::ReflectDefault, utils::get_short_name};
+use bevy_mod_scripting::prelude::{APIProvider, ScriptError};
+#[cfg(feature = "scripting_rhai")]
+use bevy_mod_scripting::prelude::{Engine, RhaiContext, RhaiDocFragment, ScriptData, WorldPointer};
+#[cfg(feature = "scripting_lua")]
+use bevy_mod_scripting::prelude::{GetWorld, Lua, LuaDocFragment};
+
+use crate::prelude::*;
+
+/// Gets the short type name of the entity's current state, like `"Idle"`, or `None` if the entity
+/// doesn't have a [`StateMachine`] or its state isn't known yet. Substates and regions aren't
+/// included.
+pub fn script_current_state(world: &World, entity: Entity) -> Option<String> {
+    world
+        .get::<StateMachine>(entity)?
+        .current_state_name()
+        .map(get_short_name)
+}
+
+/// Requests that the entity's [`StateMachine`] transition to the state with the given type name,
+/// either short, like `"Idle"`, or full, like `"my_game::ai::Idle"`, on its next run (see
+/// [`StateMachine::force_state_reflect`]). The state is constructed with its `Default`
+/// implementation, so it must be one of the machine's states, and be registered in the
+/// [`AppTypeRegistry`] with `#[reflect(Component, Default)]`. Returns whether the transition was
+/// requested, and logs a warning if it wasn't.
+pub fn script_request_transition(world: &mut World, entity: Entity, state: &str) -> bool {
+    let Some(machine) = world.get::<StateMachine>(entity) else {
+        warn!("can't request a transition for {entity:?}, since it doesn't have a state machine");
+        return false;
+    };
+
+    let Some(id) = machine
+        .states()
+        .find(|&(_, name)| name == state || get_short_name(name) == state)
+        .map(|(id, _)| id)
+    else {
+        warn!("can't request a transition to {state}, since it isn't one of {entity:?}'s states");
+        return false;
+    };
+
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    let default = registry
+        .get_type_data::<ReflectComponent>(id)
+        .and(registry.get_type_data::<ReflectDefault>(id));
+    let Some(default) = default else {
+        warn!(
+            "can't request a transition to {state}, since it isn't registered with \
+            `#[reflect(Component, Default)]`"
+        );
+        return false;
+    };
+
+    world
+        .get_mut::<StateMachine>(entity)
+        .unwrap()
+        .force_state_reflect(default.default());
+    true
+}
+
+/// Marks the entity's current state as completed by inserting [`Done::Success`] or
+/// [`Done::Failure`], for [`done`] triggers. Returns whether the entity exists.
+pub fn script_set_done(world: &mut World, entity: Entity, success: bool) -> bool {
+    let Some(mut entity) = world.get_entity_mut(entity) else {
+        return false;
+    };
+
+    entity.insert(if success {
+        Done::Success
+    } else {
+        Done::Failure
+    });
+    true
+}
+
+/// A `bevy_mod_scripting` API provider for Lua. Add it with
+/// `app.add_api_provider::<LuaScriptHost<Args>>(Box::new(StateMachineLuaApi))`. Scripts identify
+/// entities by their bits (see [`Entity::to_bits`]). Adds these global functions:
+///
+/// - `current_state(entity)` returns the short name of the entity's current state, or `nil` (see
+/// [`script_current_state`])
+/// - `request_transition(entity, state)` requests a transition to the state with the given name,
+/// and returns whether it was requested (see [`script_request_transition`])
+/// - `set_done(entity, success)` marks the current state as completed (see [`script_set_done`])
+#[cfg(feature = "scripting_lua")]
+#[derive(Debug, Default)]
+pub struct StateMachineLuaApi;
+
+#[cfg(feature = "scripting_lua")]
+impl APIProvider for StateMachineLuaApi {
+    type APITarget = Mutex<Lua>;
+    type DocTarget = LuaDocFragment;
+    type ScriptContext = Mutex<Lua>;
+
+    fn attach_api(&mut self, lua: &mut Self::APITarget) -> Result<(), ScriptError> {
+        let lua = lua.get_mut().unwrap();
+        let globals = lua.globals();
+
+        let current_state = lua
+            .create_function(|lua, entity: u64| {
+                let world = lua.get_world()?;
+                let world = world.read();
+                Ok(script_current_state(&world, Entity::from_bits(entity)))
+            })
+            .map_err(ScriptError::new_other)?;
+        globals
+            .set("current_state", current_state)
+            .map_err(ScriptError::new_other)?;
+
+        let request_transition = lua
+            .create_function(|lua, (entity, state): (u64, String)| {
+                let world = lua.get_world()?;
+                let mut world = world.write();
+                Ok(script_request_transition(
+                    &mut world,
+                    Entity::from_bits(entity),
+                    &state,
+                ))
+            })
+            .map_err(ScriptError::new_other)?;
+        globals
+            .set("request_transition", request_transition)
+            .map_err(ScriptError::new_other)?;
+
+        let set_done = lua
+            .create_function(|lua, (entity, success): (u64, bool)| {
+                let world = lua.get_world()?;
+                let mut world = world.write();
+                Ok(script_set_done(
+                    &mut world,
+                    Entity::from_bits(entity),
+                    success,
+                ))
+            })
+            .map_err(ScriptError::new_other)?;
+        globals
+            .set("set_done", set_done)
+            .map_err(ScriptError::new_other)?;
+
+        Ok(())
+    }
+}
+
+/// A `bevy_mod_scripting` API provider for Rhai. Add it with
+/// `app.add_api_provider::<RhaiScriptHost<Args>>(Box::new(StateMachineRhaiApi))`. Scripts identify
+/// entities by their bits (see [`Entity::to_bits`]). Adds a `world` variable to scripts' scope,
+/// with these methods:
+///
+/// - `world.current_state(entity)` returns the short name of the entity's current state, or an
+/// empty string (see [`script_current_state`])
+/// - `world.request_transition(entity, state)` requests a transition to the state with the given
+/// name, and returns whether it was requested (see [`script_request_transition`])
+/// - `world.set_done(entity, success)` marks the current state as completed (see
+/// [`script_set_done`])
+#[cfg(feature = "scripting_rhai")]
+#[derive(Debug, Default)]
+pub struct StateMachineRhaiApi;
+
+#[cfg(feature = "scripting_rhai")]
+impl APIProvider for StateMachineRhaiApi {
+    type APITarget = Engine;
+    type DocTarget = RhaiDocFragment;
+    type ScriptContext = RhaiContext;
+
+    fn attach_api(&mut self, engine: &mut Self::APITarget) -> Result<(), ScriptError> {
+        engine
+            .register_fn("current_state", |world: &mut WorldPointer, entity: i64| {
+                script_current_state(&world.read(), Entity::from_bits(entity as u64))
+                    .unwrap_or_default()
+            })
+            .register_fn(
+                "request_transition",
+                |world: &mut WorldPointer, entity: i64, state: &str| {
+                    script_request_transition(
+                        &mut world.write(),
+                        Entity::from_bits(entity as u64),
+                        state,
+                    )
+                },
+            )
+            .register_fn(
+                "set_done",
+                |world: &mut WorldPointer, entity: i64, success: bool| {
+                    script_set_done(
+                        &mut world.write(),
+                        Entity::from_bits(entity as u64),
+                        success,
+                    )
+                },
+            );
+
+        Ok(())
+    }
+
+    fn setup_script_runtime(
+        &mut self,
+        world: WorldPointer,
+        _: &ScriptData,
+        context: &mut Self::ScriptContext,
+    ) -> Result<(), ScriptError> {
+        context.scope.set_value("world", world);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component, Default, Reflect)]
+    #[reflect(Component, Default)]
+    struct StateOne;
+    #[derive(Clone, Component, Default, Reflect)]
+    #[reflect(Component, Default)]
+    struct StateTwo;
+    #[derive(Clone, Component)]
+    struct StateThree;
+
+    #[test]
+    fn test_script_api() {
+        let mut app = App::new();
+        app.init_resource::<AppTypeRegistry>()
+            .register_type::<StateOne>()
+            .register_type::<StateTwo>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(done(None), StateTwo)
+            .trans::<StateTwo, _>(|| false, StateThree);
+        let entity = app.world.spawn((machine, StateOne)).id();
+
+        app.update();
+        assert_eq!(
+            script_current_state(&app.world, entity).as_deref(),
+            Some("StateOne"),
+        );
+
+        assert!(script_set_done(&mut app.world, entity, true));
+        app.update();
+        assert_eq!(
+            script_current_state(&app.world, entity).as_deref(),
+            Some("StateTwo"),
+        );
+
+        assert!(!script_request_transition(
+            &mut app.world,
+            entity,
+            "StateThree"
+        ));
+        assert!(!script_request_transition(&mut app.world, entity, "Other"));
+        assert!(script_request_transition(
+            &mut app.world,
+            entity,
+            "StateOne"
+        ));
+        app.update();
+        assert!(app.world.get::<StateOne>(entity).is_some());
+
+        app.world.despawn(entity);
+        assert!(!script_set_done(&mut app.world, entity, false));
+        assert_eq!(script_current_state(&app.world, entity), None);
+    }
+}
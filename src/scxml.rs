@@ -0,0 +1,346 @@
+//! Importing and exporting state machines as SCXML, the W3C statechart format, for interop with
+//! statechart editors. Enabled by the `scxml` feature. See [`StateMachineAsset::from_scxml`] and
+//! [`StateMachine::to_scxml`].
+
+use std::{
+    any::TypeId,
+    error::Error,
+    fmt::{self, Display, Formatter, Write},
+};
+
+use bevy::utils::get_short_name;
+
+use crate::prelude::*;
+
+/// The id of the compound state that contains every other state, for [`AnyState`] transitions
+const ANY_STATE: &str = "AnyState";
+
+/// An error while importing SCXML. See [`StateMachineAsset::from_scxml`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ScxmlError {
+    /// A tag, comment, or attribute isn't closed or is otherwise malformed
+    Malformed,
+    /// An element is missing an attribute that's required to import it
+    MissingAttribute {
+        /// The element's name
+        element: String,
+        /// The attribute's name
+        attribute: &'static str,
+    },
+    /// A `<transition>` isn't inside a `<state>`
+    TransitionOutsideState,
+}
+
+impl Display for ScxmlError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "malformed SCXML"),
+            Self::MissingAttribute { element, attribute } => {
+                write!(f, "`<{element}>` is missing the `{attribute}` attribute")
+            }
+            Self::TransitionOutsideState => write!(f, "`<transition>` outside of a state"),
+        }
+    }
+}
+
+impl Error for ScxmlError {}
+
+impl StateMachineAsset {
+    /// Imports a state machine from SCXML. Each `<state>`, `<final>`, and `<parallel>` element
+    /// becomes a state named by its `id`, and each `<transition>` becomes a transition from the
+    /// state that contains it to its `target`, for each of the names in its `event`. Events are
+    /// looked up as triggers in the [`MachineAssetRegistry`], and states as states, when the asset
+    /// is built. Transitions in a state with the id `AnyState` are from [`AnyState`]. Everything
+    /// else, such as the `initial` attribute and executable content, is ignored, since the entity's
+    /// initial state is given when it's spawned.
+    pub fn from_scxml(scxml: &str) -> Result<Self, ScxmlError> {
+        let mut asset = Self::default();
+        // The ids of the open elements, if they're states
+        let mut parents = Vec::<Option<String>>::new();
+        let mut rest = scxml;
+
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start + 1..];
+
+            if let Some(comment) = rest.strip_prefix("!--") {
+                let end = comment.find("-->").ok_or(ScxmlError::Malformed)?;
+                rest = &comment[end + 3..];
+                continue;
+            }
+
+            let end = rest.find('>').ok_or(ScxmlError::Malformed)?;
+            let tag = &rest[..end];
+            rest = &rest[end + 1..];
+
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+
+            if tag.starts_with('/') {
+                parents.pop().ok_or(ScxmlError::Malformed)?;
+                continue;
+            }
+
+            let (tag, closed) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let (element, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+            let attributes = parse_attributes(attributes)?;
+            let attribute = |attribute: &'static str| {
+                attributes
+                    .iter()
+                    .find(|&&(name, _)| name == attribute)
+                    .map(|(_, value)| value.clone())
+                    .ok_or_else(|| ScxmlError::MissingAttribute {
+                        element: element.to_string(),
+                        attribute,
+                    })
+            };
+
+            let state = match element {
+                "state" | "final" | "parallel" => {
+                    let id = attribute("id")?;
+                    if id != ANY_STATE {
+                        asset.states.push(id.clone());
+                    }
+                    Some(id)
+                }
+                "transition" => {
+                    let from = parents
+                        .iter()
+                        .rev()
+                        .find_map(Clone::clone)
+                        .ok_or(ScxmlError::TransitionOutsideState)?;
+                    let target = attribute("target")?;
+
+                    for event in attribute("event")?.split_whitespace() {
+                        asset.transitions.push(TransitionAsset {
+                            from: from.clone(),
+                            trigger: event.to_string(),
+                            params: None,
+                            to: target.clone(),
+                            state: None,
+                            builder: None,
+                            builder_params: None,
+                            priority: 0,
+                        });
+                    }
+
+                    None
+                }
+                _ => None,
+            };
+
+            if !closed {
+                parents.push(state);
+            }
+        }
+
+        Ok(asset)
+    }
+}
+
+impl StateMachine {
+    /// Exports the state machine's states and transitions as SCXML. States are named by their type
+    /// names, without module paths, and transitions' events are their triggers' type names, which
+    /// you may rename to match your [`MachineAssetRegistry`] before importing them with
+    /// [`StateMachineAsset::from_scxml`]. [`AnyState`] transitions are on a compound state that
+    /// contains every other state. Transitions that don't go to a known state, like those of
+    /// [`StateMachine::trans_pop`], aren't exported, and neither are substates and regions.
+    pub fn to_scxml(&self) -> String {
+        let any = TypeId::of::<AnyState>();
+        let transitions = self
+            .transitions()
+            .filter(|transition| transition.to.is_some())
+            .collect::<Vec<_>>();
+        let mut states = self.states().collect::<Vec<_>>();
+        states.sort_by_key(|&(_, name)| name);
+
+        let mut scxml = String::new();
+        writeln!(scxml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(
+            scxml,
+            r#"<scxml xmlns="http://www.w3.org/2005/07/scxml" version="1.0">"#
+        )
+        .unwrap();
+
+        let any_transitions = transitions
+            .iter()
+            .filter(|transition| transition.from == any);
+        let indent = if any_transitions.clone().next().is_some() {
+            writeln!(scxml, r#"  <state id="{ANY_STATE}">"#).unwrap();
+            for transition in any_transitions {
+                write_transition(&mut scxml, "    ", transition);
+            }
+            "    "
+        } else {
+            "  "
+        };
+
+        for (id, name) in states {
+            let name = escape(&get_short_name(name));
+            let mut state_transitions = transitions
+                .iter()
+                .filter(|transition| transition.from == id)
+                .peekable();
+
+            if state_transitions.peek().is_none() {
+                writeln!(scxml, r#"{indent}<state id="{name}"/>"#).unwrap();
+                continue;
+            }
+
+            writeln!(scxml, r#"{indent}<state id="{name}">"#).unwrap();
+            for transition in state_transitions {
+                write_transition(&mut scxml, &format!("{indent}  "), transition);
+            }
+            writeln!(scxml, "{indent}</state>").unwrap();
+        }
+
+        if indent.len() > 2 {
+            writeln!(scxml, "  </state>").unwrap();
+        }
+
+        writeln!(scxml, "</scxml>").unwrap();
+        scxml
+    }
+}
+
+fn write_transition(scxml: &mut String, indent: &str, transition: &TransitionInfo) {
+    let Some(to_name) = transition.to_name else {
+        return;
+    };
+
+    writeln!(
+        scxml,
+        r#"{indent}<transition event="{}" target="{}"/>"#,
+        escape(&get_short_name(transition.trigger_name)),
+        escape(&get_short_name(to_name)),
+    )
+    .unwrap();
+}
+
+/// Parses the attributes of an element into their names and unescaped values
+fn parse_attributes(mut attributes: &str) -> Result<Vec<(&str, String)>, ScxmlError> {
+    let mut parsed = Vec::new();
+
+    loop {
+        attributes = attributes.trim_start();
+        if attributes.is_empty() {
+            return Ok(parsed);
+        }
+
+        let (name, rest) = attributes.split_once('=').ok_or(ScxmlError::Malformed)?;
+        let rest = rest.trim_start();
+        let quote = rest
+            .chars()
+            .next()
+            .filter(|&quote| quote == '"' || quote == '\'')
+            .ok_or(ScxmlError::Malformed)?;
+        let (value, rest) = rest[1..].split_once(quote).ok_or(ScxmlError::Malformed)?;
+
+        parsed.push((name.trim(), unescape(value)));
+        attributes = rest;
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct StateOne;
+    #[derive(Clone, Component)]
+    struct StateTwo;
+
+    fn names(asset: &StateMachineAsset) -> Vec<(&str, &str, &str)> {
+        asset
+            .transitions
+            .iter()
+            .map(|transition| (&*transition.from, &*transition.trigger, &*transition.to))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_scxml() {
+        let asset = StateMachineAsset::from_scxml(
+            r#"<?xml version="1.0"?>
+            <scxml xmlns="http://www.w3.org/2005/07/scxml" version="1.0" initial="Idle">
+                <!-- Comments <state id="Ignored"/> are skipped -->
+                <state id="AnyState">
+                    <transition event="dead" target="Dead"/>
+                    <state id="Idle">
+                        <transition event="near seen" target='Chase'/>
+                    </state>
+                    <state id="Chase">
+                        <onentry><log expr="'chasing'"/></onentry>
+                        <transition event="far" target="Idle"></transition>
+                    </state>
+                </state>
+                <final id="Dead"/>
+            </scxml>"#,
+        )
+        .unwrap();
+
+        assert_eq!(asset.states, ["Idle", "Chase", "Dead"]);
+        assert_eq!(
+            names(&asset),
+            [
+                ("AnyState", "dead", "Dead"),
+                ("Idle", "near", "Chase"),
+                ("Idle", "seen", "Chase"),
+                ("Chase", "far", "Idle"),
+            ],
+        );
+
+        assert_eq!(
+            StateMachineAsset::from_scxml(r#"<scxml><transition event="e" target="T"/></scxml>"#)
+                .unwrap_err(),
+            ScxmlError::TransitionOutsideState,
+        );
+        assert_eq!(
+            StateMachineAsset::from_scxml(r#"<scxml><state/></scxml>"#).unwrap_err(),
+            ScxmlError::MissingAttribute {
+                element: "state".to_string(),
+                attribute: "id",
+            },
+        );
+    }
+
+    #[test]
+    fn test_to_scxml() {
+        let machine = StateMachine::default()
+            .trans::<StateOne, _>(always, StateTwo)
+            .trans::<AnyState, _>(always, StateOne);
+
+        let asset = StateMachineAsset::from_scxml(&machine.to_scxml()).unwrap();
+        assert_eq!(asset.states, ["StateOne", "StateTwo"]);
+
+        let transitions = names(&asset);
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(
+            (transitions[0].0, transitions[0].2),
+            ("AnyState", "StateOne")
+        );
+        assert_eq!(
+            (transitions[1].0, transitions[1].2),
+            ("StateOne", "StateTwo")
+        );
+    }
+}
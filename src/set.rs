@@ -5,8 +5,18 @@ use crate::prelude::*;
 /// System sets used by this crate
 #[derive(Clone, Debug, Eq, Hash, PartialEq, SystemSet)]
 pub enum StateSet {
-    /// Do state transitions
+    /// Do state transitions. These systems need exclusive access to the `World`, since triggers may
+    /// be any systems, so they can't run in parallel with other systems. Triggers are still checked
+    /// in parallel across entities.
     Transition,
     /// Remove `Done` markers
     RemoveDoneMarkers,
+    /// Update `StateWeights`
+    Blend,
+    /// Run `OnEnterState` and `OnExitState` schedules
+    Schedules,
+    /// Play `StateAnimations`
+    Animation,
+    /// Update `MachineSnapshot`s
+    Snapshots,
 }
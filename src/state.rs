@@ -1,22 +1,35 @@
 use std::{
     any::TypeId,
     fmt::{self, Debug, Formatter},
+    sync::Arc,
 };
 
-use bevy::ecs::system::{Command, EntityCommands};
+use bevy::{
+    ecs::system::{Command, EntityCommands},
+    reflect::TypeRegistry,
+};
 
 use crate::prelude::*;
 
 use self::sealed::EntityStateSealed;
+pub(crate) use self::sealed::Insert;
 
 mod sealed {
-    use bevy::ecs::system::EntityCommands;
+    use std::any::TypeId;
+
+    use bevy::{
+        ecs::system::EntityCommands,
+        ptr::Ptr,
+        reflect::{ReflectFromPtr, TypeRegistry},
+    };
 
     use crate::prelude::*;
 
     pub trait EntityStateSealed {
         fn from_entity(entity: Entity, world: &World) -> &Self;
         fn remove(entity: &mut EntityCommands);
+        /// Clones the entity's state of this type, so it may be inserted later
+        fn clone_from_entity(entity: Entity, world: &World) -> Box<dyn Insert>;
     }
 
     impl<T: Clone + Component> EntityStateSealed for T {
@@ -27,6 +40,10 @@ mod sealed {
         fn remove(entity: &mut EntityCommands) {
             entity.remove::<Self>();
         }
+
+        fn clone_from_entity(entity: Entity, world: &World) -> Box<dyn Insert> {
+            Box::new(Self::from_entity(entity, world).clone())
+        }
     }
 
     impl EntityStateSealed for AnyState {
@@ -35,6 +52,29 @@ mod sealed {
         }
 
         fn remove(_: &mut EntityCommands) {}
+
+        fn clone_from_entity(entity: Entity, _: &World) -> Box<dyn Insert> {
+            panic!("tried to clone `AnyState` from entity {entity:?}, which is never a real state")
+        }
+    }
+
+    pub trait Insert: Send + Sync {
+        fn insert(self: Box<Self>, entity: &mut EntityCommands) -> TypeId;
+        /// Gets this state as a `Reflect`, if its type is registered
+        fn reflect<'a>(&'a self, registry: &TypeRegistry) -> Option<&'a dyn Reflect>;
+    }
+
+    impl<S: Component> Insert for S {
+        fn insert(self: Box<Self>, entity: &mut EntityCommands) -> TypeId {
+            entity.insert(*self);
+            TypeId::of::<AnyState>()
+        }
+
+        fn reflect<'a>(&'a self, registry: &TypeRegistry) -> Option<&'a dyn Reflect> {
+            let from_ptr = registry.get_type_data::<ReflectFromPtr>(TypeId::of::<S>())?;
+            // SAFETY: `from_ptr` was registered for `S`, and the pointer points to an `S`
+            Some(unsafe { from_ptr.as_reflect(Ptr::from(self)) })
+        }
     }
 }
 
@@ -53,15 +93,110 @@ pub struct AnyState(pub(crate) ());
 
 impl EntityState for AnyState {}
 
-pub(crate) trait Insert: Send {
-    fn insert(self: Box<Self>, entity: &mut EntityCommands) -> TypeId;
+/// A set of states, given as a tuple, such as `(Dead, Cutscene)`. A set of one state is written
+/// `(Dead,)`. See [`StateMachine::trans_from_any_except`].
+pub trait EntityStates: 'static + Send + Sync {
+    /// Gets the [`TypeId`] of each state in the set
+    fn type_ids() -> Vec<TypeId>;
+}
+
+macro_rules! impl_entity_states {
+    ($($state:ident),*) => {
+        impl<$($state: Clone + Component),*> EntityStates for ($($state,)*) {
+            fn type_ids() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$state>()),*]
+            }
+        }
+    };
+}
+
+impl_entity_states!(S1);
+impl_entity_states!(S1, S2);
+impl_entity_states!(S1, S2, S3);
+impl_entity_states!(S1, S2, S3, S4);
+impl_entity_states!(S1, S2, S3, S4, S5);
+impl_entity_states!(S1, S2, S3, S4, S5, S6);
+impl_entity_states!(S1, S2, S3, S4, S5, S6, S7);
+impl_entity_states!(S1, S2, S3, S4, S5, S6, S7, S8);
+
+/// An enum whose variants are states. Derive it with `#[derive(MachineStates)]`, which generates a
+/// module, named after the enum in snake case, with a component for each variant. For example, the
+/// `Enemy::Idle` variant's state is the `enemy::Idle` component, which is what you spawn the entity
+/// with, and use in queries. Use with [`StateMachine::trans_variant`].
+pub trait MachineStates: 'static + Clone + Send + Sync {
+    /// Gets the [`TypeId`] of this variant's component
+    fn state_id(&self) -> TypeId;
+    /// Registers each variant's component as a state of the state machine
+    fn register(machine: StateMachine) -> StateMachine;
+    /// Inserts this variant's component into the entity
+    fn insert(&self, entity: &mut EntityCommands);
 }
 
-impl<S: Component> Insert for S {
+/// Inserts a state, and then runs commands on the entity. See
+/// [`StateMachine::trans_builder_with_commands`].
+pub(crate) struct WithCommands<S: Component, F: FnOnce(&mut EntityCommands) + Send + Sync>(
+    pub S,
+    pub F,
+);
+
+impl<S: Component, F: FnOnce(&mut EntityCommands) + Send + Sync> Insert for WithCommands<S, F> {
     fn insert(self: Box<Self>, entity: &mut EntityCommands) -> TypeId {
-        entity.insert(*self);
+        let Self(state, commands) = *self;
+        entity.insert(state);
+        commands(entity);
         TypeId::of::<AnyState>()
     }
+
+    fn reflect<'a>(&'a self, registry: &TypeRegistry) -> Option<&'a dyn Reflect> {
+        Insert::reflect(&self.0, registry)
+    }
+}
+
+/// Inserts a state from its reflected value. See [`StateMachine::restore`].
+pub(crate) struct ReflectInsert {
+    pub state: TypeId,
+    pub value: Box<dyn Reflect>,
+}
+
+impl Insert for ReflectInsert {
+    fn insert(self: Box<Self>, entity: &mut EntityCommands) -> TypeId {
+        entity.add(move |entity: Entity, world: &mut World| {
+            let registry = world.resource::<AppTypeRegistry>().clone();
+            let registry = registry.read();
+            if let Some(component) = registry.get_type_data::<ReflectComponent>(self.state) {
+                component.insert(&mut world.entity_mut(entity), &*self.value);
+            }
+        });
+        TypeId::of::<AnyState>()
+    }
+
+    fn reflect<'a>(&'a self, _: &TypeRegistry) -> Option<&'a dyn Reflect> {
+        Some(&*self.value)
+    }
+}
+
+/// Event sent when an entity enters a state of type `S`. Only sent for states that were passed to
+/// [`StateMachine::send_events`]. Register it with `App::add_event`.
+#[derive(Clone, Debug, Event)]
+pub struct StateEntered<S: Clone + Component> {
+    /// The entity that entered the state
+    pub entity: Entity,
+    /// The state that was entered
+    pub state: S,
+    /// The name of the entity's [`StateMachine`], if it's named. See [`StateMachine::named`].
+    pub machine: Option<Arc<str>>,
+}
+
+/// Event sent when an entity exits a state of type `S`. Only sent for states that were passed to
+/// [`StateMachine::send_events`]. Register it with `App::add_event`.
+#[derive(Clone, Debug, Event)]
+pub struct StateExited<S: Clone + Component> {
+    /// The entity that exited the state
+    pub entity: Entity,
+    /// The state that was exited
+    pub state: S,
+    /// The name of the entity's [`StateMachine`], if it's named. See [`StateMachine::named`].
+    pub machine: Option<Arc<str>>,
 }
 
 #[derive(Debug)]
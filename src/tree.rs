@@ -0,0 +1,237 @@
+//! Small behavior trees that are compiled down to a state machine's states and transitions. Enabled
+//! by the `behavior_tree` feature. See [`BehaviorNode`].
+
+use std::{any::TypeId, collections::HashSet, sync::Arc};
+
+use crate::{prelude::*, state::Insert};
+
+/// A state that's a leaf of a behavior tree, or one of its outcomes
+#[derive(Clone)]
+struct Leaf {
+    id: TypeId,
+    name: &'static str,
+    state: Arc<dyn Fn() -> Box<dyn Insert> + Send + Sync>,
+    register: fn(StateMachine) -> StateMachine,
+}
+
+impl Leaf {
+    fn new<S: Clone + Component>(state: S, register: fn(StateMachine) -> StateMachine) -> Self {
+        Self {
+            id: TypeId::of::<S>(),
+            name: std::any::type_name::<S>(),
+            state: Arc::new(move || Box::new(state.clone())),
+            register,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    Leaf(Leaf),
+    Sequence(Vec<BehaviorNode>),
+    Selector(Vec<BehaviorNode>),
+    Invert(Box<BehaviorNode>),
+    Succeed(Box<BehaviorNode>),
+    RepeatUntilFailure(Box<BehaviorNode>),
+}
+
+/// A node of a behavior tree, which is added to a state machine with
+/// [`StateMachine::behavior_tree`]. Each leaf is a state, which runs until the entity gets
+/// [`Done::Success`] or [`Done::Failure`], and the tree's composite nodes decide which leaf runs
+/// next. The tree is compiled down to transitions between its leaves, triggered by [`done`], so it
+/// runs like any other part of the machine. Each state type may only be one leaf of a tree.
+#[derive(Clone)]
+pub struct BehaviorNode(Node);
+
+impl BehaviorNode {
+    /// A leaf that enters the given state, and succeeds or fails when the entity gets [`Done`]
+    pub fn leaf<S: Clone + Component>(state: S) -> Self {
+        Self(Node::Leaf(Leaf::new(state, |machine| {
+            machine.on_enter::<S>(|entity| {
+                // Don't let the previous leaf's `Done` finish this leaf too
+                entity.remove::<Done>();
+            })
+        })))
+    }
+
+    /// Runs the children in order until one fails. Succeeds if they all succeed.
+    pub fn sequence(children: impl IntoIterator<Item = BehaviorNode>) -> Self {
+        Self(Node::Sequence(children.into_iter().collect()))
+    }
+
+    /// Runs the children in order until one succeeds. Fails if they all fail.
+    pub fn selector(children: impl IntoIterator<Item = BehaviorNode>) -> Self {
+        Self(Node::Selector(children.into_iter().collect()))
+    }
+
+    /// Runs the child, and fails if it succeeds, and succeeds if it fails
+    pub fn invert(child: BehaviorNode) -> Self {
+        Self(Node::Invert(Box::new(child)))
+    }
+
+    /// Runs the child, and succeeds whether it succeeds or fails
+    pub fn succeed(child: BehaviorNode) -> Self {
+        Self(Node::Succeed(Box::new(child)))
+    }
+
+    /// Runs the child again each time it succeeds, and succeeds once it fails
+    pub fn repeat_until_failure(child: BehaviorNode) -> Self {
+        Self(Node::RepeatUntilFailure(Box::new(child)))
+    }
+
+    /// The leaf that runs first when this node runs, if it has any leaves
+    fn entry(&self) -> Option<&Leaf> {
+        match &self.0 {
+            Node::Leaf(leaf) => Some(leaf),
+            Node::Sequence(children) | Node::Selector(children) => {
+                children.iter().find_map(BehaviorNode::entry)
+            }
+            Node::Invert(child) | Node::Succeed(child) | Node::RepeatUntilFailure(child) => {
+                child.entry()
+            }
+        }
+    }
+
+    /// Adds the leaves of this node to `edges`, along with where each leaf goes when it succeeds
+    /// and when it fails, given where this node goes. Returns the leaf that runs first when this
+    /// node runs.
+    fn compile<'a>(
+        &'a self,
+        success: &'a Leaf,
+        failure: &'a Leaf,
+        edges: &mut Vec<(&'a Leaf, &'a Leaf, &'a Leaf)>,
+    ) -> &'a Leaf {
+        match &self.0 {
+            Node::Leaf(leaf) => {
+                edges.push((leaf, success, failure));
+                leaf
+            }
+            Node::Sequence(children) => children
+                .iter()
+                .rev()
+                .fold(success, |next, child| child.compile(next, failure, edges)),
+            Node::Selector(children) => children
+                .iter()
+                .rev()
+                .fold(failure, |next, child| child.compile(success, next, edges)),
+            Node::Invert(child) => child.compile(failure, success, edges),
+            Node::Succeed(child) => child.compile(success, success, edges),
+            Node::RepeatUntilFailure(child) => match child.entry() {
+                Some(entry) => child.compile(entry, success, edges),
+                None => success,
+            },
+        }
+    }
+}
+
+impl StateMachine {
+    /// Adds a behavior tree to the state machine. Spawn the entity in the tree's first leaf, or
+    /// transition to it. When the tree succeeds, the machine transitions to `success`, and when it
+    /// fails, to `failure`. These may have their own transitions, such as back to the first leaf to
+    /// run the tree again. See [`BehaviorNode`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if a state type is more than one leaf of the tree, or a leaf has the type of
+    /// `success` or `failure`.
+    pub fn behavior_tree(
+        mut self,
+        root: BehaviorNode,
+        success: impl Clone + Component,
+        failure: impl Clone + Component,
+    ) -> Self {
+        fn outcome<S: Clone + Component>(state: S) -> Leaf {
+            Leaf::new(state, StateMachine::with_state::<S>)
+        }
+
+        let (success, failure) = (outcome(success), outcome(failure));
+        let mut edges = Vec::new();
+        root.compile(&success, &failure, &mut edges);
+
+        let mut leaves = HashSet::from([success.id, failure.id]);
+        for &(leaf, _, _) in &edges {
+            assert!(
+                leaves.insert(leaf.id),
+                "{} is more than one state of the behavior tree",
+                leaf.name,
+            );
+        }
+
+        self = (success.register)(self);
+        self = (failure.register)(self);
+        for &(leaf, _, _) in &edges {
+            self = (leaf.register)(self);
+        }
+
+        for (leaf, on_success, on_failure) in edges {
+            for (result, next) in [(Done::Success, on_success), (Done::Failure, on_failure)] {
+                let state = next.state.clone();
+                self = self.trans_dyn(leaf.id, done(Some(result)), "done", next.id, move || {
+                    state()
+                });
+            }
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct Patrol;
+    #[derive(Clone, Component)]
+    struct Chase;
+    #[derive(Clone, Component)]
+    struct Attack;
+    #[derive(Clone, Component)]
+    struct Flee;
+    #[derive(Clone, Component)]
+    struct Succeeded;
+    #[derive(Clone, Component)]
+    struct Failed;
+
+    #[test]
+    fn test_behavior_tree() {
+        let mut app = App::new();
+        app.add_systems(Update, transition);
+
+        let tree = BehaviorNode::selector([
+            BehaviorNode::sequence([
+                BehaviorNode::leaf(Patrol),
+                BehaviorNode::leaf(Chase),
+                BehaviorNode::leaf(Attack),
+            ]),
+            BehaviorNode::invert(BehaviorNode::leaf(Flee)),
+        ]);
+        let machine = StateMachine::default().behavior_tree(tree, Succeeded, Failed);
+        let entity = app.world.spawn((machine, Patrol)).id();
+        app.update();
+
+        let finish = |app: &mut App, done: Done| {
+            app.world.entity_mut(entity).insert(done);
+            app.update();
+        };
+
+        finish(&mut app, Done::Success);
+        assert!(app.world.get::<Chase>(entity).is_some());
+        finish(&mut app, Done::Failure);
+        assert!(app.world.get::<Flee>(entity).is_some());
+        finish(&mut app, Done::Failure);
+        assert!(app.world.get::<Succeeded>(entity).is_some());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_duplicate_leaves() {
+        StateMachine::default().behavior_tree(
+            BehaviorNode::sequence([BehaviorNode::leaf(Patrol), BehaviorNode::leaf(Patrol)]),
+            Succeeded,
+            Failed,
+        );
+    }
+}
@@ -10,24 +10,47 @@ pub use input::{
     action_data, axis_pair, axis_pair_length_bounds, axis_pair_max_length, axis_pair_min_length,
     axis_pair_rotation_bounds, axis_pair_unbounded, clamped_axis_pair,
     clamped_axis_pair_length_bounds, clamped_axis_pair_max_length, clamped_axis_pair_min_length,
-    clamped_axis_pair_rotation_bounds, clamped_axis_pair_unbounded, clamped_value,
-    clamped_value_max, clamped_value_min, clamped_value_unbounded, just_pressed, just_released,
-    pressed, value, value_max, value_min, value_unbounded,
+    clamped_axis_pair_rotation_bounds, clamped_axis_pair_snapped, clamped_axis_pair_unbounded,
+    clamped_value, clamped_value_max, clamped_value_min, clamped_value_unbounded, just_pressed,
+    just_released, pressed, released_for, value, value_delta, value_max, value_min,
+    value_unbounded, ActionStateSource,
 };
 
 use std::{convert::Infallible, fmt::Debug};
 
-use crate::{prelude::*, set::StateSet};
-
-pub(crate) fn trigger_plugin(app: &mut App) {
-    app.configure_sets(
-        PostUpdate,
-        StateSet::RemoveDoneMarkers.after(StateSet::Transition),
-    )
-    .add_systems(
-        PostUpdate,
-        remove_done_markers.in_set(StateSet::RemoveDoneMarkers),
-    );
+use bevy::{ecs::schedule::BoxedScheduleLabel, utils::HashMap};
+
+use crate::{machine::MachineComponent, prelude::*, set::StateSet};
+
+pub(crate) fn trigger_plugin(app: &mut App, schedule: BoxedScheduleLabel) {
+    app.init_resource::<DoneMachines<false>>()
+        .init_resource::<DoneMachines<true>>()
+        .configure_sets(
+            schedule.dyn_clone(),
+            StateSet::RemoveDoneMarkers.after(StateSet::Transition),
+        )
+        .add_systems(
+            schedule,
+            (
+                collect_done_machines::<StateMachine, false>,
+                remove_done_markers::<false>,
+            )
+                .chain()
+                .in_set(StateSet::RemoveDoneMarkers),
+        )
+        .configure_sets(
+            FixedUpdate,
+            StateSet::RemoveDoneMarkers.after(StateSet::Transition),
+        )
+        .add_systems(
+            FixedUpdate,
+            (
+                collect_done_machines::<StateMachine, true>,
+                remove_done_markers::<true>,
+            )
+                .chain()
+                .in_set(StateSet::RemoveDoneMarkers),
+        );
 }
 
 /// Wrapper for [`core::convert::Infallible`]. Use for [`Trigger::Err`] if the trigger is
@@ -63,6 +86,15 @@ pub trait TriggerOut {
 
     /// Convert `Self` to a `Result`
     fn into_result(self) -> Result<Self::Ok, Self::Err>;
+
+    /// Whether the trigger occurred, for transitions and guards that don't use its data, such as
+    /// those added with `StateMachine::trans`. `bool` and `Option` skip converting to a `Result`.
+    fn occurred(self) -> bool
+    where
+        Self: Sized,
+    {
+        self.into_result().is_ok()
+    }
 }
 
 impl TriggerOut for bool {
@@ -76,6 +108,10 @@ impl TriggerOut for bool {
             Err(())
         }
     }
+
+    fn occurred(self) -> bool {
+        self
+    }
 }
 
 impl<T> TriggerOut for Option<T> {
@@ -85,6 +121,10 @@ impl<T> TriggerOut for Option<T> {
     fn into_result(self) -> Result<T, ()> {
         self.ok_or(())
     }
+
+    fn occurred(self) -> bool {
+        self.is_some()
+    }
 }
 
 impl<Ok, Err> TriggerOut for Result<Ok, Err> {
@@ -147,7 +187,9 @@ pub trait Trigger: 'static + Send + Sized + Sync {
     /// The trigger's output. See [`TriggerOut`].
     type Out: TriggerOut;
 
-    /// Initializes/resets this trigger. Runs every time the state machine transitions.
+    /// Initializes/resets this trigger. Runs before the trigger is first checked after the state
+    /// machine transitions, so triggers of states that the entity isn't in aren't reset until it
+    /// enters them.
     fn init(&mut self, world: &mut World);
     /// Checks whether the state machine should transition
     fn check(&mut self, entity: Entity, world: &World) -> Self::Out;
@@ -268,7 +310,7 @@ impl<T: Trigger, U: Trigger> Trigger for OrTrigger<T, U> {
 }
 
 /// Marker component that represents that the current state has completed. Removed from every entity
-/// each frame after checking triggers. To be used with [`done`].
+/// each frame after checking triggers, unless its machine is [`Paused`]. To be used with [`done`].
 #[derive(Component, Debug, Eq, PartialEq, Clone, Copy)]
 #[component(storage = "SparseSet")]
 pub enum Done {
@@ -295,8 +337,77 @@ pub fn on_event<T: Clone + Event>(mut reader: EventReader<T>) -> Option<T> {
     reader.read().last().cloned()
 }
 
-pub(crate) fn remove_done_markers(mut commands: Commands, dones: Query<Entity, With<Done>>) {
+/// Event that requests that an entity's state machine transition to the given state. It's only
+/// taken if the state machine has a transition with the [`requested`] trigger for `S`, such as one
+/// added with [`StateMachine::trans_on_request`], so guards and priorities still apply. Useful for
+/// scripted sequences and network commands. Register it with `App::add_event`.
+#[derive(Clone, Debug, Event)]
+pub struct TransitionRequest<S: Clone + Component> {
+    /// The entity whose state machine should transition
+    pub entity: Entity,
+    /// The requested state
+    pub state: S,
+}
+
+/// Trigger that transitions when a [`TransitionRequest`] for the entity is received, and gives the
+/// requested state
+pub fn requested<S: Clone + Component>() -> impl Trigger<Out = Option<S>> {
+    (|In(entity): In<Entity>, mut requests: EventReader<TransitionRequest<S>>| {
+        requests
+            .read()
+            .filter(|request| request.entity == entity)
+            .last()
+            .map(|request| request.state.clone())
+    })
+    .into_trigger()
+}
+
+/// Trigger that transitions when Bevy's global [`States`] is set to the given value through
+/// [`NextState`], before it's applied. Useful for entity state machines that follow the app's
+/// state. To set the global state from a state machine instead, see
+/// [`StateMachine::drive_global_state`].
+pub fn next_global_state<T: States>(value: T) -> impl Trigger<Out = bool> {
+    (move |next: Res<NextState<T>>| next.0.as_ref() == Some(&value)).into_trigger()
+}
+
+/// Entities with `Done` markers and state machines of any kind, by whether their markers may be
+/// removed. `None` means that none of the entity's machines run in this schedule. `FIXED` is
+/// whether this is for `FixedUpdate` or the rest.
+#[derive(Debug, Default, Resource)]
+pub(crate) struct DoneMachines<const FIXED: bool>(HashMap<Entity, Option<bool>>);
+
+/// Records which entities with `Done` markers have state machines of type `M` that ran this frame,
+/// for [`remove_done_markers`]. `FIXED` is whether this is for `FixedUpdate` or the rest.
+pub(crate) fn collect_done_machines<M: MachineComponent, const FIXED: bool>(
+    machines: Query<(Entity, &M), (With<Done>, Without<Paused>)>,
+    mut dones: ResMut<DoneMachines<FIXED>>,
+) {
+    for (entity, machine) in &machines {
+        let machine = machine.machine();
+        let removable = dones.0.entry(entity).or_insert(None);
+        if machine.is_fixed_update() == FIXED {
+            *removable = Some(removable.unwrap_or(true) && !machine.is_skipped());
+        }
+    }
+}
+
+/// Removes `Done` markers. `FIXED` is whether to remove them from entities whose state machines run
+/// in `FixedUpdate` or from the rest. Markers are kept while any of the entity's machines that run
+/// in this schedule, including [`LabeledMachine`]s, were skipped.
+pub(crate) fn remove_done_markers<const FIXED: bool>(
+    mut commands: Commands,
+    dones: Query<Entity, (With<Done>, Without<Paused>)>,
+    mut machines: ResMut<DoneMachines<FIXED>>,
+) {
     for done in &dones {
-        commands.entity(done).remove::<Done>();
+        if machines
+            .0
+            .get(&done)
+            .map_or(!FIXED, |&removable| removable == Some(true))
+        {
+            commands.entity(done).remove::<Done>();
+        }
     }
+
+    machines.0.clear();
 }
@@ -1,24 +1,49 @@
-use std::{any::type_name, ops::Range};
+use std::{any::type_name, f32::consts::TAU, ops::Range, time::Duration};
 
+use bevy::ecs::system::SystemParam;
 use leafwing_input_manager::{
     action_state::ActionData, axislike::DualAxisData, orientation::Rotation,
 };
 
 use crate::prelude::*;
 
+/// Add to an entity with a [`StateMachine`] to have its input triggers read the [`ActionState`]s of
+/// the given entity instead of its own. Useful when the input lives on a separate controller
+/// entity, such as for vehicles or possessed characters.
+#[derive(Clone, Component, Copy, Debug, Deref, DerefMut, Eq, PartialEq)]
+pub struct ActionStateSource(pub Entity);
+
+/// Finds the [`ActionState`] that an entity's input triggers should read
+#[derive(SystemParam)]
+struct Actors<'w, 's, A: Actionlike> {
+    actors: Query<'w, 's, &'static ActionState<A>>,
+    sources: Query<'w, 's, &'static ActionStateSource>,
+}
+
+impl<A: Actionlike> Actors<'_, '_, A> {
+    /// Gets the [`ActionState`] for the given entity's input triggers. `trigger` is for debug
+    /// information.
+    fn get(&self, entity: Entity, trigger: &str) -> &ActionState<A> {
+        let source = self
+            .sources
+            .get(entity)
+            .map_or(entity, |&ActionStateSource(source)| source);
+
+        self.actors.get(source).unwrap_or_else(|_| {
+            panic!(
+                "entity {source:?}, read by `{trigger}<{0}>` on entity {entity:?}, is missing \
+                `ActionState<{0}>`",
+                type_name::<A>()
+            )
+        })
+    }
+}
+
 /// Trigger that transitions if the given [`Actionlike`]'s value is within the given bounds.
 /// Consider using `f32::NEG_INFINITY`/`f32::INFINITY` in the bounds.
 pub fn value<A: Actionlike>(action: A, bounds: Range<f32>) -> impl Trigger<Out = Result<f32, f32>> {
-    (move |In(entity): In<Entity>, actors: Query<&ActionState<A>>| {
-        let value = actors
-            .get(entity)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "entity {entity:?} with `ValueTrigger<{0}>` is missing `ActionState<{0}>`",
-                    type_name::<A>()
-                )
-            })
-            .value(action.clone());
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
+        let value = actors.get(entity, "ValueTrigger").value(action.clone());
 
         if bounds.contains(&value) {
             Ok(value)
@@ -44,20 +69,40 @@ pub fn value_max(action: impl Actionlike, max: f32) -> impl Trigger<Out = Result
     value(action, f32::NEG_INFINITY..max)
 }
 
+/// Trigger that transitions if the given [`Actionlike`]'s value changed by more than the given
+/// threshold since the previous check, providing the change. Useful for detecting flicks. The
+/// previous value is forgotten whenever the machine transitions, so this won't trigger on the first
+/// check after entering a state.
+pub fn value_delta<A: Actionlike>(
+    action: A,
+    threshold: f32,
+) -> impl Trigger<Out = Result<f32, f32>> {
+    (move |In(entity): In<Entity>, actors: Actors<A>, mut previous: Local<Option<f32>>| {
+        let value = actors
+            .get(entity, "ValueDeltaTrigger")
+            .value(action.clone());
+
+        let delta = previous
+            .replace(value)
+            .map_or(0., |previous| value - previous);
+
+        if delta.abs() > threshold {
+            Ok(delta)
+        } else {
+            Err(delta)
+        }
+    })
+    .into_trigger()
+}
+
 /// [`value`] clamped to [-1, 1]
 pub fn clamped_value<A: Actionlike>(
     action: A,
     bounds: Range<f32>,
 ) -> impl Trigger<Out = Result<f32, f32>> {
-    (move |In(entity): In<Entity>, actors: Query<&ActionState<A>>| {
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
         let value = actors
-            .get(entity)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "entity {entity:?} with `ClampedValueTrigger<{0}>` is missing `ActionState<{0}>`",
-                    type_name::<A>()
-                )
-            })
+            .get(entity, "ClampedValueTrigger")
             .clamped_value(action.clone());
 
         if bounds.contains(&value) {
@@ -100,15 +145,9 @@ pub fn axis_pair<A: Actionlike>(
     length_bounds: Range<f32>,
     rotation_bounds: Range<Rotation>,
 ) -> impl Trigger<Out = Result<DualAxisData, Option<DualAxisData>>> {
-    (move |In(entity): In<Entity>, actors: Query<&ActionState<A>>| {
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
         let axis_pair = actors
-            .get(entity)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "entity {entity:?} with `AxisPairTrigger<{0}>` is missing `ActionState<{0}>`",
-                    type_name::<A>()
-                )
-            })
+            .get(entity, "AxisPairTrigger")
             .axis_pair(action.clone());
 
         axis_pair
@@ -182,15 +221,9 @@ pub fn clamped_axis_pair<A: Actionlike>(
     length_bounds: Range<f32>,
     rotation_bounds: Range<Rotation>,
 ) -> impl Trigger<Out = Result<DualAxisData, Option<DualAxisData>>> {
-    (move |In(entity): In<Entity>, actors: Query<&ActionState<A>>| {
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
         let axis_pair = actors
-            .get(entity)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "entity {entity:?} with `AxisPairTrigger<{0}>` is missing `ActionState<{0}>`",
-                    type_name::<A>()
-                )
-            })
+            .get(entity, "AxisPairTrigger")
             .clamped_axis_pair(action.clone());
 
         axis_pair
@@ -258,17 +291,38 @@ pub fn clamped_axis_pair_rotation_bounds(
     clamped_axis_pair(action, 0.0..f32::INFINITY, rotation_bounds)
 }
 
+/// Trigger that transitions if the given [`Actionlike`]'s clamped [`DualAxisData`] is not neutral,
+/// providing its direction snapped to the nearest of `n_directions` evenly spaced unit vectors. The
+/// first direction points east, and the rest go counterclockwise, so `4` gives the cardinal
+/// directions and `8` adds the diagonals.
+pub fn clamped_axis_pair_snapped<A: Actionlike>(
+    action: A,
+    n_directions: u32,
+) -> impl Trigger<Out = Result<Vec2, Option<DualAxisData>>> {
+    assert!(n_directions > 0, "cannot snap to zero directions");
+    let step = TAU / n_directions as f32;
+
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
+        let axis_pair = actors
+            .get(entity, "ClampedAxisPairSnappedTrigger")
+            .clamped_axis_pair(action.clone());
+
+        axis_pair
+            .and_then(|axis_pair| {
+                let xy = axis_pair.xy();
+                (xy != Vec2::ZERO)
+                    .then(|| Vec2::from_angle((xy.y.atan2(xy.x) / step).round() * step))
+            })
+            .ok_or(axis_pair)
+    })
+    .into_trigger()
+}
+
 /// Trigger that transitions upon pressing the given [`Actionlike`]
 pub fn just_pressed<A: Actionlike>(action: A) -> impl Trigger<Out = bool> {
-    (move |In(entity): In<Entity>, actors: Query<&ActionState<A>>| {
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
         actors
-            .get(entity)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "entity {entity:?} with `JustPressedTrigger<{0}>` is missing `ActionState<{0}>`",
-                    type_name::<A>()
-                )
-            })
+            .get(entity, "JustPressedTrigger")
             .just_pressed(action.clone())
     })
     .into_trigger()
@@ -276,49 +330,136 @@ pub fn just_pressed<A: Actionlike>(action: A) -> impl Trigger<Out = bool> {
 
 /// Trigger that transitions while pressing the given [`Actionlike`]
 pub fn pressed<A: Actionlike>(action: A) -> impl Trigger<Out = bool> {
-    (move |In(entity): In<Entity>, actors: Query<&ActionState<A>>| {
-        actors
-            .get(entity)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "entity {entity:?} with `JustPressedTrigger<{0}>` is missing `ActionState<{0}>`",
-                    type_name::<A>()
-                )
-            })
-            .pressed(action.clone())
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
+        actors.get(entity, "PressedTrigger").pressed(action.clone())
     })
     .into_trigger()
 }
 
 /// Trigger that transitions upon releasing the given [`Actionlike`]
 pub fn just_released<A: Actionlike>(action: A) -> impl Trigger<Out = bool> {
-    (move |In(entity): In<Entity>, actors: Query<&ActionState<A>>| {
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
         actors
-            .get(entity)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "entity {entity:?} with `JustPressedTrigger<{0}>` is missing `ActionState<{0}>`",
-                    type_name::<A>()
-                )
-            })
+            .get(entity, "JustReleasedTrigger")
             .just_released(action.clone())
     })
     .into_trigger()
 }
 
+/// Trigger that transitions once the given [`Actionlike`] has been released for at least the given
+/// duration
+pub fn released_for<A: Actionlike>(action: A, duration: Duration) -> impl Trigger<Out = bool> {
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
+        let actor = actors.get(entity, "ReleasedForTrigger");
+
+        actor.released(action.clone()) && actor.current_duration(action.clone()) >= duration
+    })
+    .into_trigger()
+}
+
 /// Trigger that always transitions, providing the given [`Actionlike`]'s [`ActionData`]
 pub fn action_data<A: Actionlike>(action: A) -> impl Trigger<Out = Result<ActionData, Never>> {
-    (move |In(entity): In<Entity>, actors: Query<&ActionState<A>>| {
+    (move |In(entity): In<Entity>, actors: Actors<A>| {
         Ok(actors
-            .get(entity)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "entity {entity:?} with `ActionDataTrigger<{0}>` is missing `ActionState<{0}>`",
-                    type_name::<A>()
-                )
-            })
+            .get(entity, "ActionDataTrigger")
             .action_data(action.clone())
             .clone())
     })
     .into_trigger()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Actionlike, Clone, Copy, Debug, Eq, Hash, PartialEq, Reflect)]
+    enum Action {
+        Jump,
+        Throttle,
+        Move,
+    }
+
+    /// Spawns an entity with an [`ActionState`] and initializes the trigger
+    fn setup(trigger: &mut impl Trigger) -> (World, Entity) {
+        let mut world = World::new();
+        let entity = world.spawn(ActionState::<Action>::default()).id();
+        trigger.init(&mut world);
+        (world, entity)
+    }
+
+    fn action_data(world: &mut World, entity: Entity, action: Action) -> Mut<ActionData> {
+        world
+            .get_mut::<ActionState<Action>>(entity)
+            .unwrap()
+            .map_unchanged(|actor| actor.action_data_mut(action))
+    }
+
+    #[test]
+    fn test_released_for() {
+        let mut trigger = released_for(Action::Jump, Duration::from_secs(1));
+        let (mut world, entity) = setup(&mut trigger);
+
+        assert!(!trigger.check(entity, &world));
+
+        action_data(&mut world, entity, Action::Jump)
+            .timing
+            .current_duration = Duration::from_secs(2);
+        assert!(trigger.check(entity, &world));
+
+        world
+            .get_mut::<ActionState<Action>>(entity)
+            .unwrap()
+            .press(Action::Jump);
+        assert!(
+            !trigger.check(entity, &world),
+            "pressed actions shouldn't count as released"
+        );
+    }
+
+    #[test]
+    fn test_value_delta() {
+        let mut trigger = value_delta(Action::Throttle, 0.5);
+        let (mut world, entity) = setup(&mut trigger);
+
+        assert_eq!(trigger.check(entity, &world), Err(0.));
+
+        action_data(&mut world, entity, Action::Throttle).value = 0.25;
+        assert_eq!(trigger.check(entity, &world), Err(0.25));
+
+        action_data(&mut world, entity, Action::Throttle).value = 1.;
+        assert_eq!(trigger.check(entity, &world), Ok(0.75));
+
+        action_data(&mut world, entity, Action::Throttle).value = 0.;
+        assert_eq!(trigger.check(entity, &world), Ok(-1.));
+
+        assert_eq!(
+            trigger.check(entity, &world),
+            Err(0.),
+            "an unchanged value shouldn't trigger"
+        );
+    }
+
+    #[test]
+    fn test_clamped_axis_pair_snapped() {
+        let mut trigger = clamped_axis_pair_snapped(Action::Move, 4);
+        let (mut world, entity) = setup(&mut trigger);
+
+        assert!(trigger.check(entity, &world).is_err());
+
+        action_data(&mut world, entity, Action::Move).axis_pair = Some(DualAxisData::new(0.9, 0.2));
+        assert_eq!(trigger.check(entity, &world), Ok(Vec2::X));
+
+        action_data(&mut world, entity, Action::Move).axis_pair =
+            Some(DualAxisData::new(-0.1, 0.8));
+        assert!(trigger
+            .check(entity, &world)
+            .unwrap()
+            .abs_diff_eq(Vec2::Y, 1e-6));
+
+        action_data(&mut world, entity, Action::Move).axis_pair = Some(DualAxisData::new(0., 0.));
+        assert!(
+            trigger.check(entity, &world).is_err(),
+            "neutral input shouldn't trigger"
+        );
+    }
+}
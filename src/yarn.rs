@@ -0,0 +1,108 @@
+//! Interoperability with `bevy_yarnspinner`, so dialogue and state machines stay in step. Enabled
+//! by the `yarnspinner` feature. See [`yarn_node_started`], [`yarn_node_completed`], and
+//! [`StateMachine::start_yarn_node`].
+
+use bevy_yarnspinner::{
+    events::{NodeCompleteEvent, NodeStartEvent},
+    prelude::DialogueRunner,
+};
+
+use crate::prelude::*;
+
+/// Trigger that transitions when a `bevy_yarnspinner` dialogue runner starts the Yarn node with
+/// the given name. Dialogue events don't say which runner sent them, so this occurs for any
+/// runner's node with that name.
+pub fn yarn_node_started(node: impl Into<String>) -> impl Trigger<Out = bool> {
+    let node = node.into();
+    (move |mut events: EventReader<NodeStartEvent>| {
+        events.read().any(|event| event.node_name == node)
+    })
+    .into_trigger()
+}
+
+/// Trigger that transitions when a `bevy_yarnspinner` dialogue runner completes the Yarn node with
+/// the given name. Dialogue events don't say which runner sent them, so this occurs for any
+/// runner's node with that name.
+pub fn yarn_node_completed(node: impl Into<String>) -> impl Trigger<Out = bool> {
+    let node = node.into();
+    (move |mut events: EventReader<NodeCompleteEvent>| {
+        events.read().any(|event| event.node_name == node)
+    })
+    .into_trigger()
+}
+
+impl StateMachine {
+    /// Starts the Yarn node with the given name on the entity's `DialogueRunner` whenever the
+    /// entity enters the state given as a type parameter, and stops the dialogue if it's still
+    /// running when the entity exits the state. Combine it with [`yarn_node_completed`] to leave
+    /// the state when the conversation ends.
+    pub fn start_yarn_node<S: EntityState>(self, node: impl Into<String>) -> Self {
+        let node = node.into();
+        self.on_enter::<S>(move |entity| {
+            let node = node.clone();
+            entity.add(move |entity: Entity, world: &mut World| {
+                let Some(mut runner) = world.get_mut::<DialogueRunner>(entity) else {
+                    warn!(
+                        "can't start the Yarn node {node}, since {entity:?} doesn't have a \
+                        `DialogueRunner`"
+                    );
+                    return;
+                };
+
+                runner.start_node(&node);
+            });
+        })
+        .on_exit::<S>(|entity| {
+            entity.add(|entity: Entity, world: &mut World| {
+                if let Some(mut runner) = world.get_mut::<DialogueRunner>(entity) {
+                    if runner.is_running() {
+                        runner.stop();
+                    }
+                }
+            });
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::transition;
+
+    use super::*;
+
+    #[derive(Clone, Component)]
+    struct Idle;
+    #[derive(Clone, Component)]
+    struct Talking;
+
+    #[test]
+    fn test_yarn_triggers() {
+        let mut app = App::new();
+        app.add_event::<NodeStartEvent>()
+            .add_event::<NodeCompleteEvent>()
+            .add_systems(Update, transition);
+
+        let machine = StateMachine::default()
+            .trans::<Idle, _>(yarn_node_started("Greeting"), Talking)
+            .trans::<Talking, _>(yarn_node_completed("Greeting"), Idle);
+        let entity = app.world.spawn((machine, Idle)).id();
+        app.update();
+
+        let start = |node: &str| NodeStartEvent {
+            node_name: node.to_string(),
+        };
+        app.world.send_event(start("Farewell"));
+        app.update();
+        assert!(app.world.get::<Idle>(entity).is_some());
+
+        app.world.send_event(start("Greeting"));
+        app.update();
+        assert!(app.world.get::<Talking>(entity).is_some());
+
+        app.world.send_event(NodeCompleteEvent {
+            node_name: "Greeting".to_string(),
+        });
+        app.update();
+        assert!(app.world.get::<Idle>(entity).is_some());
+    }
+}